@@ -1,8 +1,19 @@
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
 use serde_derive::{Serialize, Deserialize};
 use reqwest::blocking::Client;
 use std::io::Read;
-use serde_json;
+use crate::config::Config;
+use crate::events::{self, Event};
+use crate::scoring;
+use crate::storage;
+
+/// Set while a background pass runs.
+static PASS_RUNNING: AtomicBool = AtomicBool::new(false);
+/// Set by requests for a pass; the running pass starts over once it finishes.
+static PASS_REQUESTED: AtomicBool = AtomicBool::new(false);
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AIAnalysisResult {
@@ -24,7 +35,7 @@ struct OllamaResponse {
     response: String,
 }
 
-pub fn analyze_file_with_ai(path: &Path, config: &crate::config::Config) -> Option<AIAnalysisResult> {
+pub fn analyze_file_with_ai(path: &Path, config: &Config) -> Option<AIAnalysisResult> {
     // Only analyze if AI analysis is enabled
     if !config.use_ai_analysis {
         return None;
@@ -69,6 +80,10 @@ pub fn analyze_file_with_ai(path: &Path, config: &crate::config::Config) -> Opti
                 if let Ok(ollama_response) = response.json::<OllamaResponse>() {
                     // Parse JSON response
                     if let Ok(analysis) = serde_json::from_str::<AIAnalysisResult>(&ollama_response.response) {
+                        events::publish(Event::AiAnalysisCompleted {
+                            path: path.to_path_buf(),
+                            analysis: analysis.clone(),
+                        });
                         return Some(analysis);
                     }
                 }
@@ -113,3 +128,71 @@ fn read_file_sample(path: &Path, max_bytes: usize) -> Option<String> {
     // Try to convert to UTF-8 string
     String::from_utf8(buffer).ok()
 }
+
+/// Reacts to a configuration update. When AI analysis has just been switched
+/// on (or pointed at a different model), files already in the index are
+/// analyzed in the background instead of waiting for the next scan.
+pub fn apply_config_change(old: &Config, new: &Config, config: Arc<Mutex<Config>>) {
+    let enabled = !old.use_ai_analysis && new.use_ai_analysis;
+    let model_changed = new.use_ai_analysis && (old.ollama_model != new.ollama_model || old.ollama_url != new.ollama_url);
+    if enabled || model_changed {
+        analyze_in_background(config);
+    }
+}
+
+/// Analyzes indexed files that have no AI result yet on a background thread.
+/// Scans call this when they finish rather than querying the model from
+/// their workers. Requests made while a pass runs are folded into one more pass.
+pub fn analyze_in_background(config: Arc<Mutex<Config>>) {
+    PASS_REQUESTED.store(true, Ordering::SeqCst);
+    if PASS_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(move || loop {
+        while PASS_REQUESTED.swap(false, Ordering::SeqCst) {
+            analyze_indexed_files(&config);
+        }
+        PASS_RUNNING.store(false, Ordering::SeqCst);
+        // A request that came in after the last check but before the flag was
+        // cleared found the pass still running
+        if !PASS_REQUESTED.load(Ordering::SeqCst) || PASS_RUNNING.swap(true, Ordering::SeqCst) {
+            break;
+        }
+    });
+}
+
+fn analyze_indexed_files(config: &Mutex<Config>) {
+    let config_dir = crate::get_config_dir();
+    let metadata = match storage::load_file_metadata(&config_dir) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!("AI analysis: failed to load file metadata: {}", e);
+            return;
+        }
+    };
+
+    let mut analyzed = Vec::new();
+    for (path, meta) in metadata {
+        // Re-read the settings for every file so that switching AI off stops this pass
+        let snapshot = config.lock().unwrap().clone();
+        if !snapshot.use_ai_analysis {
+            break;
+        }
+        if meta.is_deleted || meta.ai_analysis.is_some() || !is_analyzable(&path) {
+            continue;
+        }
+        if let Some(analysis) = analyze_file_with_ai(&path, &snapshot) {
+            let mut meta = meta;
+            meta.ai_analysis = Some(analysis.clone());
+            let score = scoring::importance(&meta, &snapshot);
+            analyzed.push((path, analysis, score));
+        }
+    }
+
+    if !analyzed.is_empty() {
+        println!("AI analysis completed for {} indexed files", analyzed.len());
+        if let Err(e) = storage::set_ai_analysis(&config_dir, &analyzed) {
+            eprintln!("AI analysis: failed to save file metadata: {}", e);
+        }
+    }
+}
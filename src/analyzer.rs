@@ -1,20 +1,36 @@
 use std::path::Path;
 use std::fs::Metadata;
-use crate::storage::FileMetadata;
+use crate::config::Config;
+use crate::extract;
+use crate::scoring;
+use crate::sniff::{self, Sniffed};
+use crate::storage::{self, FileMetadata};
+use crate::taxonomy::{FileFacts, Taxonomy};
 use std::time::SystemTime;
 use chrono::{DateTime, Utc};
 
-pub fn analyze_file(path: &Path, system_metadata: &Metadata) -> FileMetadata {
+/// Version of what [`analyze_file`] records. Entries from an older version
+/// are analyzed again on the next scan even if the file did not change.
+pub const ANALYSIS_VERSION: u32 = 5;
+
+pub fn analyze_file(path: &Path, system_metadata: &Metadata, config: &Config) -> FileMetadata {
     let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
     let extension = path.extension().map(|ext| ext.to_string_lossy().to_lowercase());
     let file_size = system_metadata.len();
+    let (device, inode, link_count) = storage::file_identity(system_metadata);
     
     // Get creation and modification times
     let created = system_time_to_date_time(system_metadata.created().unwrap_or(SystemTime::now()));
     let modified = system_time_to_date_time(system_metadata.modified().unwrap_or(SystemTime::now()));
+    // Taken before the content is read below, which may update it
+    let accessed = system_metadata.accessed().map_or(modified, system_time_to_date_time);
+    
+    // Content beats extension for the type, but the extension says what the file claims to be
+    let sniff_content = config.analyze_content && file_size > 0;
+    let (mime_type, extension_mismatch) = detect_mime_type(path, extension.as_deref(), sniff_content);
+    let extended = if sniff_content { extract::extract(path, &mime_type) } else { None };
     
-    // Determine file category
-    let category = determine_file_category(path, &extension);
+    let category = categorize(config, path, extension.as_deref().unwrap_or_default(), &mime_type, system_metadata);
     
     // Create basic metadata
     let mut metadata = FileMetadata {
@@ -22,110 +38,91 @@ pub fn analyze_file(path: &Path, system_metadata: &Metadata) -> FileMetadata {
         file_name,
         extension: extension.unwrap_or_default(),
         size: file_size,
+        allocated_size: storage::allocated_size_of(system_metadata),
         created,
         modified,
         category,
-        mime_type: get_mime_type(path),
+        mime_type,
+        extension_mismatch,
         importance_score: 0,
-        last_accessed: modified,
+        last_accessed: accessed,
         is_duplicate: false,
         duplicate_of: None,
+        content_hash: None,
         ai_analysis: None,
+        extended,
+        device,
+        inode,
+        link_count,
+        hardlink_of: None,
+        is_deleted: false,
+        analysis_version: ANALYSIS_VERSION,
     };
     
-    // Set importance score based on initial analysis
-    metadata.importance_score = calculate_importance_score(&metadata);
+    // Duplicates are only known after the scan, which scores the index again
+    metadata.importance_score = scoring::importance(&metadata, config);
     
     metadata
 }
 
-fn determine_file_category(path: &Path, extension: &Option<String>) -> String {
-    if let Some(ext) = extension {
-        match ext.as_str() {
-            "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tiff" | "webp" | "heic" => "image".to_string(),
-            "mp4" | "avi" | "mov" | "wmv" | "flv" | "mkv" | "webm" => "video".to_string(),
-            "mp3" | "wav" | "ogg" | "flac" | "aac" | "m4a" => "audio".to_string(),
-            "doc" | "docx" | "pdf" | "txt" | "rtf" | "odt" => "document".to_string(),
-            "xls" | "xlsx" | "csv" | "ods" => "spreadsheet".to_string(),
-            "ppt" | "pptx" | "odp" => "presentation".to_string(),
-            "exe" | "app" | "dmg" | "deb" | "rpm" => "application".to_string(),
-            "zip" | "rar" | "7z" | "tar" | "gz" => "archive".to_string(),
-            _ => "other".to_string()
-        }
-    } else {
-        // Files without extensions
-        if is_executable(path) {
-            "application".to_string()
-        } else {
-            "other".to_string()
-        }
+/// Re-files an unchanged entry under the current category rules, so that a
+/// rule change takes effect without analyzing the file again. `None` when
+/// its category still holds.
+pub fn recategorize(entry: &FileMetadata, system_metadata: &Metadata, config: &Config) -> Option<FileMetadata> {
+    let category = categorize(config, &entry.path, &entry.extension, &entry.mime_type, system_metadata);
+    if category == entry.category {
+        return None;
     }
+    let mut entry = entry.clone();
+    entry.category = category;
+    entry.importance_score = scoring::importance(&entry, config);
+    Some(entry)
 }
 
-fn calculate_importance_score(metadata: &FileMetadata) -> u8 {
-    let mut score = 0;
-    
-    // Recently modified files are more important
-    let now = Utc::now();
-    let days_since_modification = (now - metadata.modified).num_days();
-    
-    if days_since_modification < 7 {
-        score += 30;
-    } else if days_since_modification < 30 {
-        score += 20;
-    } else if days_since_modification < 90 {
-        score += 10;
-    }
-    
-    // File category importance
-    match metadata.category.as_str() {
-        "document" => score += 25,
-        "spreadsheet" | "presentation" => score += 20,
-        "image" | "video" => score += 15,
-        "audio" => score += 10,
-        "application" => score += 5,
-        _ => score += 0,
-    }
-    
-    // Clamp to 0-100
-    score.min(100) as u8
+fn categorize(config: &Config, path: &Path, extension: &str, mime_type: &str, system_metadata: &Metadata) -> String {
+    Taxonomy::for_config(config).categorize(&FileFacts {
+        path,
+        extension,
+        mime_type,
+        size: system_metadata.len(),
+        executable: is_executable(system_metadata),
+    })
 }
 
-fn get_mime_type(path: &Path) -> String {
-    // Simple MIME type detection based on extension
-    if let Some(ext) = path.extension() {
-        match ext.to_string_lossy().to_lowercase().as_str() {
-            "jpg" | "jpeg" => "image/jpeg",
-            "png" => "image/png",
-            "pdf" => "application/pdf",
-            "txt" => "text/plain",
-            // Add more mappings as needed
-            _ => "application/octet-stream",
-        }
-        .to_string()
-    } else {
-        "application/octet-stream".to_string()
-    }
+/// MIME type from the file's leading bytes, falling back to the extension,
+/// and whether the two disagree. Without `sniff_content` only the extension
+/// is used and nothing is flagged.
+fn detect_mime_type(path: &Path, extension: Option<&str>, sniff_content: bool) -> (String, bool) {
+    let expected = extension.and_then(sniff::mime_for_extension);
+    let sniffed = match sniff_content {
+        true => sniff::sniff(path).unwrap_or(Sniffed::Unknown),
+        false => Sniffed::Unknown,
+    };
+    let (mime_type, mismatch) = match (sniffed, expected) {
+        // The extension is kept when it names a more specific format, like a JAR for a ZIP
+        (Sniffed::Format(sniffed), Some(expected)) if sniff::is_compatible(expected, sniffed) => (expected, false),
+        (Sniffed::Format(sniffed), expected) => (sniffed, expected.is_some()),
+        (Sniffed::Text, Some(expected)) => (expected, !sniff::is_text(expected)),
+        (Sniffed::Text, None) => ("text/plain", false),
+        (Sniffed::Unknown, expected) => (expected.unwrap_or(sniff::OCTET_STREAM), false),
+    };
+    (mime_type.to_string(), mismatch)
 }
 
-fn is_executable(path: &Path) -> bool {
+/// Whether an execute permission bit is set. Windows has none; its programs
+/// are recognised by extension instead.
+fn is_executable(system_metadata: &Metadata) -> bool {
     #[cfg(unix)]
     {
         use std::os::unix::fs::PermissionsExt;
-        if let Ok(metadata) = std::fs::metadata(path) {
-            return metadata.permissions().mode() & 0o111 != 0;
-        }
+        system_metadata.permissions().mode() & 0o111 != 0
     }
     
-    #[cfg(windows)]
+    #[cfg(not(unix))]
     {
-        if let Some(ext) = path.extension() {
-            let ext = ext.to_string_lossy().to_lowercase();
-            return ext == "exe" || ext == "bat" || ext == "cmd";
-        }
+        let _ = system_metadata;
+        false
     }
-    
-    false
 }
 
 fn system_time_to_date_time(time: SystemTime) -> DateTime<Utc> {
@@ -133,6 +130,6 @@ fn system_time_to_date_time(time: SystemTime) -> DateTime<Utc> {
         .duration_since(SystemTime::UNIX_EPOCH)
         .unwrap_or_else(|_| std::time::Duration::from_secs(0));
     let secs = duration.as_secs() as i64;
-    let nsecs = duration.subsec_nanos() as u32;
+    let nsecs = duration.subsec_nanos();
     DateTime::from_timestamp(secs, nsecs).unwrap_or(Utc::now())
 }
@@ -1,13 +1,27 @@
 use std::sync::{Arc, Mutex};
-use actix_web::{web, App, HttpResponse, HttpServer, Responder, middleware::Logger};
+use std::time::Duration;
+use actix_web::{web, App, HttpResponse, HttpServer, Responder, http::header, middleware::Logger};
 use actix_cors::Cors;
 use serde_derive::{Serialize, Deserialize};
-use crate::config::Config;
+use tokio::sync::broadcast::error::RecvError;
+use crate::config::{Config, ConfigError, CONFIG_FILE_NAME};
+use crate::duplicates::{self, DuplicateGroup, HardlinkGroup};
+use crate::events::{self, Event};
+use crate::history::{self, DiffError, DiffQuery, Direction};
+use crate::jobs::{JobAction, JobError, JobManager};
+use crate::mounts;
 use crate::scanner;
-use crate::storage;
-use std::thread;
+use crate::scoring;
+use crate::ai_integration;
+use crate::storage::{self, FileQuery, FileSort, SizeMode};
+use crate::tree;
+use crate::treemap;
+use crate::watcher;
 use std::path::PathBuf;
-use tokio;
+use chrono::{NaiveDate, NaiveDateTime};
+
+/// Interval of the comment lines that keep idle `/events` streams open.
+const EVENT_KEEPALIVE: Duration = Duration::from_secs(15);
 
 // API response types
 #[derive(Serialize)]
@@ -16,37 +30,15 @@ struct StatusResponse {
     version: String,
 }
 
-#[derive(Serialize)]
-struct DriveInfoResponse {
-    drives: Vec<String>,
-}
-
-#[derive(Serialize)]
-struct ScanResultResponse {
-    total_files: usize,
-    total_size: u64,
-    file_types: Vec<FileTypeInfo>,
-}
-
-#[derive(Serialize)]
-struct FileTypeInfo {
-    extension: String,
-    count: usize,
-}
-
 #[derive(Deserialize)]
 struct ScanDriveRequest {
     path: String,
 }
 
-#[derive(Serialize)]
-struct DriveDetail {
-    mount_point: String,
-    fs_type: String,
-    total_space: u64,
-    available_space: u64,
-    used_space: u64,
-    is_removable: bool,
+#[derive(Deserialize)]
+struct StartScanRequest {
+    /// Roots to scan; all drives when omitted.
+    paths: Option<Vec<String>>,
 }
 
 #[derive(Deserialize)]
@@ -64,40 +56,15 @@ async fn health_check() -> impl Responder {
     HttpResponse::Ok().json(response)
 }
 
-async fn get_drives() -> impl Responder {
-    let drives = scanner::get_all_drives();
-    let mut details = Vec::new();
-    for drive in drives {
-        let stat = nix::sys::statvfs::statvfs(&drive).ok();
-        let (total, avail, used) = if let Some(stat) = stat {
-            let total = stat.blocks() * stat.block_size();
-            let avail = stat.blocks_available() * stat.block_size();
-            let used = total - avail;
-            (total, avail, used)
-        } else { (0, 0, 0) };
-        let fs_type = "unknown".to_string(); // Optionally detect fs type
-        let is_removable = drive.to_string_lossy().starts_with("/media/") || drive.to_string_lossy().starts_with("/mnt/");
-        details.push(DriveDetail {
-            mount_point: drive.to_string_lossy().to_string(),
-            fs_type,
-            total_space: total,
-            available_space: avail,
-            used_space: used,
-            is_removable,
-        });
-    }
-    HttpResponse::Ok().json(serde_json::json!({ "drives": details }))
+async fn get_drives(config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
+    let drives = scanner::get_all_drives(&config.lock().unwrap());
+    HttpResponse::Ok().json(serde_json::json!({ "drives": mounts::drive_details(&drives) }))
 }
 
 async fn get_scan_stats() -> impl Responder {
     // Get the stats from the latest scan
-    let config_dir = crate::get_config_dir();
-    let stats_path = config_dir.join("data").join("latest_stats.json");
-    
-    if let Ok(content) = std::fs::read_to_string(stats_path) {
-        if let Ok(stats) = serde_json::from_str::<serde_json::Value>(&content) {
-            return HttpResponse::Ok().json(stats);
-        }
+    if let Some(stats) = storage::load_scan_stats(&crate::get_config_dir()) {
+        return HttpResponse::Ok().json(stats);
     }
     
     // Return empty stats if no data available
@@ -105,50 +72,317 @@ async fn get_scan_stats() -> impl Responder {
         "timestamp": 0,
         "total_files": 0,
         "total_size": 0,
+        "total_allocated_size": 0,
         "file_types": {}
     }))
 }
 
-async fn initiate_scan(data: web::Json<ScanDriveRequest>, config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
-    let path = PathBuf::from(&data.path);
-    
-    // Start a scan in a background thread
-    let config_clone = config.get_ref().clone();
-    thread::spawn(move || {
-        println!("API triggered scan of drive: {:?}", path);
-        let _ = scanner::scan_drive(&path, config_clone);
-    });
+async fn initiate_scan(
+    data: web::Json<ScanDriveRequest>,
+    config: web::Data<Arc<Mutex<Config>>>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
+    println!("API triggered scan of drive: {}", data.path);
+    let job = jobs.start(vec![PathBuf::from(&data.path)], config.get_ref().clone());
     
     HttpResponse::Ok().json(serde_json::json!({
         "status": "started",
-        "path": data.path
+        "path": data.path,
+        "scan_id": job.id
     }))
 }
 
+async fn list_scans(jobs: web::Data<Arc<JobManager>>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({ "scans": jobs.list() }))
+}
+
+async fn start_scan(
+    data: Option<web::Json<StartScanRequest>>,
+    config: web::Data<Arc<Mutex<Config>>>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
+    let roots = match data.and_then(|data| data.into_inner().paths) {
+        Some(paths) if !paths.is_empty() => paths.into_iter().map(PathBuf::from).collect(),
+        _ => scanner::get_all_drives(&config.lock().unwrap()),
+    };
+    if let Some(missing) = roots.iter().find(|root| !root.is_dir()) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("{} is not a directory", missing.display())
+        }));
+    }
+    HttpResponse::Accepted().json(jobs.start(roots, config.get_ref().clone()))
+}
+
+async fn get_scan(path: web::Path<u64>, jobs: web::Data<Arc<JobManager>>) -> impl Responder {
+    let id = path.into_inner();
+    match jobs.get(id) {
+        Some(job) => HttpResponse::Ok().json(job),
+        None => job_error_response(JobError::NotFound(id)),
+    }
+}
+
+async fn control_scan(path: web::Path<(u64, String)>, jobs: web::Data<Arc<JobManager>>) -> impl Responder {
+    let (id, action) = path.into_inner();
+    let action = match action.as_str() {
+        "pause" => JobAction::Pause,
+        "resume" => JobAction::Resume,
+        "cancel" => JobAction::Cancel,
+        _ => return HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("unknown scan action '{}'", action)
+        })),
+    };
+    match jobs.control(id, action) {
+        Ok(job) => HttpResponse::Ok().json(job),
+        Err(e) => job_error_response(e),
+    }
+}
+
+async fn cancel_scan(path: web::Path<u64>, jobs: web::Data<Arc<JobManager>>) -> impl Responder {
+    match jobs.control(path.into_inner(), JobAction::Cancel) {
+        Ok(job) => HttpResponse::Ok().json(job),
+        Err(e) => job_error_response(e),
+    }
+}
+
+/// Server-Sent Events stream of scan progress, file changes, AI results and
+/// configuration updates. Each message's `event:` name matches the `type`
+/// field of its JSON payload.
+async fn stream_events() -> impl Responder {
+    let receiver = events::subscribe();
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        let frame = match tokio::time::timeout(EVENT_KEEPALIVE, receiver.recv()).await {
+            Ok(Ok(event)) => format!(
+                "event: {}\ndata: {}\n\n",
+                event.name(),
+                serde_json::to_string(&event).unwrap_or_default()
+            ),
+            // Slow clients skip ahead and are told how much they missed
+            Ok(Err(RecvError::Lagged(skipped))) => format!(
+                "event: lagged\ndata: {{\"type\":\"lagged\",\"skipped\":{}}}\n\n",
+                skipped
+            ),
+            Ok(Err(RecvError::Closed)) => return None,
+            Err(_) => ": keep-alive\n\n".to_string(),
+        };
+        Some((Ok::<_, actix_web::Error>(web::Bytes::from(frame)), receiver))
+    });
+    
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(stream)
+}
+
+fn job_error_response(error: JobError) -> HttpResponse {
+    let body = serde_json::json!({ "error": error.to_string() });
+    match error {
+        JobError::NotFound(_) => HttpResponse::NotFound().json(body),
+        JobError::AlreadyFinished(_) => HttpResponse::Conflict().json(body),
+    }
+}
+
 async fn get_metadata(_config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
-    // Load file metadata
-    let config_dir = crate::get_config_dir();
-    match storage::load_file_metadata(&config_dir) {
-        Ok(metadata) => {
-            // Convert metadata to a list format
-            let simplified: Vec<serde_json::Value> = metadata.values()
-                .map(|meta| {
-                    serde_json::json!({
-                        "path": meta.path.to_string_lossy(),
-                        "name": meta.file_name,
-                        "size": meta.size,
-                        "category": meta.category,
-                        "importance": meta.importance_score,
-                    })
-                })
-                .collect();
-            
-            HttpResponse::Ok().json(simplified) // Return as a list
+    match web::block(|| storage::list_file_summaries(&crate::get_config_dir())).await {
+        Ok(Ok(files)) => HttpResponse::Ok().json(files),
+        // Return an empty list instead of a map to match frontend expectations
+        _ => HttpResponse::Ok().json(Vec::<serde_json::Value>::new()),
+    }
+}
+
+#[derive(Deserialize)]
+struct DuplicatesQuery {
+    min_size: Option<u64>,
+    limit: Option<usize>,
+}
+
+async fn get_duplicates(query: web::Query<DuplicatesQuery>) -> impl Responder {
+    let min_size = query.min_size.unwrap_or(0);
+    let loaded = web::block(move || duplicates::duplicate_groups(&crate::get_config_dir(), min_size)).await;
+    let groups = match loaded.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok(groups) => groups,
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load duplicates: {}", e)
+        })),
+    };
+    let total_groups = groups.len();
+    let wasted_bytes: u64 = groups.iter().map(|group| group.wasted_bytes).sum();
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "total_groups": total_groups,
+        "wasted_bytes": wasted_bytes,
+        "wasted_size_formatted": format_file_size(wasted_bytes),
+        "groups": groups.into_iter().take(query.limit.unwrap_or(100)).collect::<Vec<DuplicateGroup>>(),
+    }))
+}
+
+async fn get_hardlinks(query: web::Query<DuplicatesQuery>) -> impl Responder {
+    let min_size = query.min_size.unwrap_or(0);
+    let loaded = web::block(move || duplicates::hardlink_groups(&crate::get_config_dir(), min_size)).await;
+    let groups = match loaded.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok(groups) => groups,
+        Err(e) => return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load hardlinks: {}", e)
+        })),
+    };
+    let total_groups = groups.len();
+    let shared_bytes: u64 = groups.iter().map(|group| group.shared_bytes).sum();
+    
+    HttpResponse::Ok().json(serde_json::json!({
+        "total_groups": total_groups,
+        "shared_bytes": shared_bytes,
+        "shared_size_formatted": format_file_size(shared_bytes),
+        "groups": groups.into_iter().take(query.limit.unwrap_or(100)).collect::<Vec<HardlinkGroup>>(),
+    }))
+}
+
+#[derive(Deserialize)]
+struct SnapshotListQuery {
+    root: Option<String>,
+    limit: Option<usize>,
+}
+
+async fn list_snapshots(query: web::Query<SnapshotListQuery>) -> impl Responder {
+    let root = query.root.as_ref().map(PathBuf::from);
+    let loaded = web::block(move || storage::list_snapshots(&crate::get_config_dir(), root.as_deref())).await;
+    match loaded.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok(mut snapshots) => {
+            snapshots.truncate(query.limit.unwrap_or(100));
+            HttpResponse::Ok().json(serde_json::json!({ "snapshots": snapshots }))
         },
-        Err(_) => {
-            // Return an empty list instead of a map to match frontend expectations
-            HttpResponse::Ok().json(Vec::<serde_json::Value>::new())
-        }
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load snapshots: {}", e)
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+struct SnapshotDiffQuery {
+    root: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    /// `7d`, `12h` or an RFC 3339 timestamp.
+    since: Option<String>,
+    min_change: Option<u64>,
+    /// `grew`, `shrank` or `any`.
+    direction: Option<String>,
+    depth: Option<usize>,
+    limit: Option<usize>,
+}
+
+async fn diff_snapshots(query: web::Query<SnapshotDiffQuery>) -> impl Responder {
+    let since = match query.since.as_deref().map(|since| history::parse_since(since, chrono::Utc::now())) {
+        Some(None) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "since must look like 7d, 12h, 30m or an RFC 3339 timestamp"
+        })),
+        Some(since) => since,
+        None => None,
+    };
+    let direction = match query.direction.as_deref().unwrap_or("any") {
+        "any" => Direction::Any,
+        "grew" => Direction::Grew,
+        "shrank" => Direction::Shrank,
+        other => return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("unknown direction '{}', expected grew, shrank or any", other)
+        })),
+    };
+    let diff_query = DiffQuery {
+        root: query.root.as_ref().map(PathBuf::from),
+        from: query.from,
+        to: query.to,
+        since,
+        min_change: query.min_change.unwrap_or(0),
+        direction,
+        depth: query.depth.unwrap_or(2),
+        limit: query.limit.unwrap_or(50),
+    };
+    
+    let diff = web::block(move || history::diff(&crate::get_config_dir(), &diff_query)).await;
+    match diff.unwrap_or_else(|e| Err(DiffError::Io(std::io::Error::other(e)))) {
+        Ok(diff) => HttpResponse::Ok().json(diff),
+        Err(e @ (DiffError::NoSnapshots | DiffError::NotFound(_))) => {
+            HttpResponse::NotFound().json(serde_json::json!({ "error": e.to_string() }))
+        },
+        Err(e @ DiffError::RootMismatch(..)) => {
+            HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }))
+        },
+        Err(e @ DiffError::Io(_)) => {
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": e.to_string() }))
+        },
+    }
+}
+
+#[derive(Deserialize)]
+struct TreeQuery {
+    path: Option<String>,
+    depth: Option<usize>,
+    limit: Option<usize>,
+    size_mode: Option<SizeMode>,
+}
+
+async fn get_tree(query: web::Query<TreeQuery>, config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
+    let path = match &query.path {
+        Some(path) => PathBuf::from(path),
+        None => scanner::get_all_drives(&config.lock().unwrap()).into_iter().next().unwrap_or_else(|| PathBuf::from("/")),
+    };
+    let depth = query.depth.unwrap_or(1).min(10);
+    let limit = query.limit.unwrap_or(100).max(1);
+    let size_mode = query.size_mode.unwrap_or_default();
+    
+    let root = path.clone();
+    let built = web::block(move || tree::build_tree(&crate::get_config_dir(), &root, depth, limit, size_mode)).await;
+    match built.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok(Some(tree)) => HttpResponse::Ok().json(tree),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("{} is not an indexed directory", path.display())
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load directory tree: {}", e)
+        })),
+    }
+}
+
+#[derive(Deserialize)]
+struct TreemapQuery {
+    path: Option<String>,
+    width: Option<f64>,
+    height: Option<f64>,
+    depth: Option<usize>,
+    limit: Option<usize>,
+    size_mode: Option<SizeMode>,
+}
+
+async fn get_treemap(query: web::Query<TreemapQuery>, config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
+    let path = match &query.path {
+        Some(path) => PathBuf::from(path),
+        None => scanner::get_all_drives(&config.lock().unwrap()).into_iter().next().unwrap_or_else(|| PathBuf::from("/")),
+    };
+    let width = query.width.unwrap_or(1000.0);
+    let height = query.height.unwrap_or(1000.0);
+    if !(width.is_finite() && height.is_finite() && width > 0.0 && height > 0.0) {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "width and height must be positive numbers"
+        }));
+    }
+    let depth = query.depth.unwrap_or(2).min(6);
+    let limit = query.limit.unwrap_or(50).max(1);
+    let size_mode = query.size_mode.unwrap_or_default();
+    
+    let root = path.clone();
+    let built = web::block(move || tree::build_tree(&crate::get_config_dir(), &root, depth, limit, size_mode)).await;
+    match built.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok(Some(tree)) => HttpResponse::Ok().json(serde_json::json!({
+            "path": path,
+            "width": width,
+            "height": height,
+            "size_mode": size_mode,
+            "rects": treemap::layout(&tree, width, height, size_mode),
+        })),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("{} is not an indexed directory", path.display())
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load directory tree: {}", e)
+        })),
     }
 }
 
@@ -180,7 +414,7 @@ async fn get_system_status() -> impl Responder {
 }
 
 // Add a new endpoint for live system/file status
-async fn get_live_status() -> impl Responder {
+async fn get_live_status(config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
     let uptime = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap_or_default()
@@ -188,27 +422,8 @@ async fn get_live_status() -> impl Responder {
     let memory_usage = get_memory_usage();
     let cpu_usage = get_cpu_usage();
     let scan_stats = get_scan_statistics();
-    let drives = scanner::get_all_drives();
-    let mut drive_details = Vec::new();
-    for drive in drives {
-        let stat = nix::sys::statvfs::statvfs(&drive).ok();
-        let (total, avail, used) = if let Some(stat) = stat {
-            let total = stat.blocks() * stat.block_size();
-            let avail = stat.blocks_available() * stat.block_size();
-            let used = total - avail;
-            (total, avail, used)
-        } else { (0, 0, 0) };
-        let fs_type = "unknown".to_string();
-        let is_removable = drive.to_string_lossy().starts_with("/media/") || drive.to_string_lossy().starts_with("/mnt/");
-        drive_details.push(serde_json::json!({
-            "mount_point": drive.to_string_lossy(),
-            "fs_type": fs_type,
-            "total_space": total,
-            "available_space": avail,
-            "used_space": used,
-            "is_removable": is_removable
-        }));
-    }
+    let drives = scanner::get_all_drives(&config.lock().unwrap());
+    let drive_details = mounts::drive_details(&drives);
     let response = serde_json::json!({
         "status": "running",
         "version": env!("CARGO_PKG_VERSION"),
@@ -224,93 +439,73 @@ async fn get_live_status() -> impl Responder {
 }
 
 // Add config management endpoints
-async fn get_config() -> impl Responder {
-    let config_dir = crate::get_config_dir();
-    let config_path = config_dir.join("config.json");
-    
-    if let Ok(content) = std::fs::read_to_string(config_path) {
-        if let Ok(config) = serde_json::from_str::<serde_json::Value>(&content) {
-            return HttpResponse::Ok().json(config);
-        }
-    }
-    
-    // Return default config if file doesn't exist
-    HttpResponse::Ok().json(get_default_config())
-}
-
-async fn update_config(data: web::Json<serde_json::Value>) -> impl Responder {
-    let config_dir = crate::get_config_dir();
-    let config_path = config_dir.join("config.json");
-    
-    // Validate config before saving
-    if !validate_config(&data) {
+async fn get_config(config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
+    let config = config.lock().unwrap().clone();
+    HttpResponse::Ok().json(config)
+}
+
+async fn update_config(
+    data: web::Json<serde_json::Value>,
+    config: web::Data<Arc<Mutex<Config>>>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
+    let config_path = crate::get_config_dir().join(CONFIG_FILE_NAME);
+    let serde_json::Value::Object(changes) = data.into_inner() else {
         return HttpResponse::BadRequest().json(serde_json::json!({
-            "error": "Invalid configuration"
+            "error": "Invalid configuration: expected a JSON object"
         }));
-    }
+    };
     
-    // Save config to file
-    match std::fs::write(&config_path, serde_json::to_string_pretty(&data).unwrap()) {
-        Ok(_) => {
-            // Notify config change to running processes
-            notify_config_change();
-            HttpResponse::Ok().json(serde_json::json!({
-                "status": "success",
-                "message": "Configuration updated successfully"
-            }))
+    // Hold the lock for the whole update so concurrent requests cannot interleave
+    let mut live = config.lock().unwrap();
+    
+    // Settings omitted from the request keep their current value
+    let mut merged = match serde_json::to_value(&*live) {
+        Ok(serde_json::Value::Object(current)) => current,
+        _ => serde_json::Map::new(),
+    };
+    merged.extend(changes);
+    
+    let updated = match Config::from_value(serde_json::Value::Object(merged)) {
+        Ok(updated) => updated,
+        Err(ConfigError::Invalid(fields)) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": "Invalid configuration",
+                "fields": fields
+            }));
         },
         Err(e) => {
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": format!("Failed to save configuration: {}", e)
-            }))
-        }
-    }
-}
-
-// Helper function to validate configuration
-fn validate_config(config: &serde_json::Value) -> bool {
-    // Perform validation checks here
-    if let Some(obj) = config.as_object() {
-        // Check required fields
-        if !obj.contains_key("scan_mode") || !obj.contains_key("excluded_paths") {
-            return false;
+            return HttpResponse::BadRequest().json(serde_json::json!({
+                "error": format!("Invalid configuration: {}", e)
+            }));
         }
-        
-        // Validate scan_mode
-        if let Some(scan_mode) = obj.get("scan_mode").and_then(|m| m.as_str()) {
-            if scan_mode != "sequential" && scan_mode != "concurrent" {
-                return false;
-            }
-        } else {
-            return false;
-        }
-        
-        // Add more validation as needed
-        return true;
+    };
+    
+    if let Err(e) = updated.save(&config_path) {
+        return HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to save configuration: {}", e)
+        }));
     }
-    false
-}
-
-// Get default configuration
-fn get_default_config() -> serde_json::Value {
-    serde_json::json!({
-        "scan_mode": "sequential",
-        "excluded_paths": [],
-        "max_concurrent_scans": 4,
-        "analyze_content": true,
-        "use_ai_analysis": false,
-        "scan_interval_hours": 24,
-        "notification_enabled": true,
-        "ui_theme": "system",
-        "file_preview_enabled": true
-    })
+    
+    let previous = std::mem::replace(&mut *live, updated.clone());
+    drop(live);
+    
+    notify_config_change(&previous, &updated, config.get_ref().clone(), jobs.get_ref());
+    HttpResponse::Ok().json(serde_json::json!({
+        "status": "success",
+        "message": "Configuration updated successfully",
+        "config": updated
+    }))
 }
 
-// Notify system of config change
-fn notify_config_change() {
-    // Implementation would depend on how you want to handle live config changes
+// Push a config change to the subsystems that cache or act on settings
+fn notify_config_change(old: &Config, new: &Config, config: Arc<Mutex<Config>>, jobs: &Arc<JobManager>) {
     println!("Configuration changed, notifying system...");
-    // You could use a channel, a shared state, or other mechanisms here
+    scanner::apply_config_change(old, new);
+    ai_integration::apply_config_change(old, new, config.clone());
+    scoring::apply_config_change(old, new, jobs, config);
+    watcher::apply_config_change(old, new);
+    events::publish(Event::ConfigChanged { config: new.clone() });
 }
 
 // Helper functions for system monitoring
@@ -341,13 +536,8 @@ fn get_cpu_usage() -> f32 {
 
 // Helper function to get scan statistics
 fn get_scan_statistics() -> serde_json::Value {
-    let config_dir = crate::get_config_dir();
-    let stats_path = config_dir.join("data").join("latest_stats.json");
-    
-    if let Ok(content) = std::fs::read_to_string(stats_path) {
-        if let Ok(stats) = serde_json::from_str::<serde_json::Value>(&content) {
-            return stats;
-        }
+    if let Some(stats) = storage::load_scan_stats(&crate::get_config_dir()) {
+        return stats;
     }
     
     // Return empty stats if no data available
@@ -355,6 +545,7 @@ fn get_scan_statistics() -> serde_json::Value {
         "timestamp": 0,
         "total_files": 0,
         "total_size": 0,
+        "total_allocated_size": 0,
         "file_types": {}
     })
 }
@@ -366,91 +557,93 @@ struct FileListRequest {
     page_size: Option<usize>,
     sort_by: Option<String>,
     sort_order: Option<String>,
+    /// A category, including its subcategories.
     filter_category: Option<String>,
     filter_size_min: Option<u64>,
     filter_size_max: Option<u64>,
     search_term: Option<String>,
+    /// Whether the size filters and sort use apparent or allocated size.
+    size_mode: Option<SizeMode>,
+    /// Only files whose content does not match their extension.
+    filter_extension_mismatch: Option<bool>,
+    /// Capture time range as `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM:SS`; the end is exclusive.
+    filter_captured_after: Option<String>,
+    filter_captured_before: Option<String>,
+    filter_width_min: Option<u32>,
+    filter_width_max: Option<u32>,
+    filter_height_min: Option<u32>,
+    filter_height_max: Option<u32>,
+    /// Recording length range in seconds.
+    filter_duration_min: Option<f64>,
+    filter_duration_max: Option<f64>,
+    /// Exact artist or album tag, ignoring ASCII case.
+    filter_artist: Option<String>,
+    filter_album: Option<String>,
+}
+
+/// Parses a date, or a date and time without timezone as capture times are
+/// stored. The error is the rejected value.
+fn parse_capture_time(value: Option<&str>) -> Result<Option<NaiveDateTime>, String> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S").ok()
+        .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+        .map(Some)
+        .ok_or_else(|| value.to_string())
 }
 
 async fn get_file_list(query: web::Query<FileListRequest>) -> impl Responder {
     let config_dir = crate::get_config_dir();
     
-    match storage::load_file_metadata(&config_dir) {
-        Ok(metadata) => {
-            // Convert to a vector for easier filtering and sorting
-            let mut files: Vec<&storage::FileMetadata> = metadata.values().collect();
-            
-            // Apply filters
-            if let Some(category) = &query.filter_category {
-                files.retain(|meta| meta.category.to_lowercase() == category.to_lowercase());
-            }
-            
-            if let Some(min_size) = query.filter_size_min {
-                files.retain(|meta| meta.size >= min_size);
-            }
-            
-            if let Some(max_size) = query.filter_size_max {
-                files.retain(|meta| meta.size <= max_size);
-            }
-            
-            if let Some(term) = &query.search_term {
-                let term_lower = term.to_lowercase();
-                files.retain(|meta| {
-                    meta.file_name.to_lowercase().contains(&term_lower) || 
-                    meta.path.to_string_lossy().to_lowercase().contains(&term_lower)
-                });
-            }
-            
-            // Sort files
-            let sort_by = query.sort_by.as_deref().unwrap_or("name");
-            let ascending = query.sort_order.as_deref().unwrap_or("asc") == "asc";
-            
-            match sort_by {
-                "name" => {
-                    if ascending {
-                        files.sort_by(|a, b| a.file_name.cmp(&b.file_name));
-                    } else {
-                        files.sort_by(|a, b| b.file_name.cmp(&a.file_name));
-                    }
-                },
-                "size" => {
-                    if ascending {
-                        files.sort_by(|a, b| a.size.cmp(&b.size));
-                    } else {
-                        files.sort_by(|a, b| b.size.cmp(&a.size));
-                    }
-                },
-                "date" => {
-                    if ascending {
-                        files.sort_by(|a, b| a.modified.cmp(&b.modified));
-                    } else {
-                        files.sort_by(|a, b| b.modified.cmp(&a.modified));
-                    }
-                },
-                "importance" => {
-                    if ascending {
-                        files.sort_by(|a, b| a.importance_score.cmp(&b.importance_score));
-                    } else {
-                        files.sort_by(|a, b| b.importance_score.cmp(&a.importance_score));
-                    }
-                },
-                _ => {}
-            }
-            
-            // Apply pagination
-            let page = query.page.unwrap_or(1).max(1);
-            let page_size = query.page_size.unwrap_or(50).min(1000);
-            let total_files = files.len();
-            let total_pages = (total_files + page_size - 1) / page_size;
-            let start_index = (page - 1) * page_size;
-            let end_index = (start_index + page_size).min(total_files);
-            
-            let page_files = if start_index < total_files {
-                &files[start_index..end_index]
-            } else {
-                &[]
-            };
-            
+    let sort = match query.sort_by.as_deref().unwrap_or("name") {
+        "name" => Some(FileSort::Name),
+        "size" => Some(FileSort::Size),
+        "date" => Some(FileSort::Modified),
+        "importance" => Some(FileSort::Importance),
+        "captured" => Some(FileSort::Captured),
+        "resolution" => Some(FileSort::Pixels),
+        "duration" => Some(FileSort::Duration),
+        _ => None,
+    };
+    let captured = (
+        parse_capture_time(query.filter_captured_after.as_deref()),
+        parse_capture_time(query.filter_captured_before.as_deref()),
+    );
+    let (captured_after, captured_before) = match captured {
+        (Ok(after), Ok(before)) => (after, before),
+        (Err(value), _) | (_, Err(value)) => return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!("Invalid capture time '{}': expected YYYY-MM-DD or YYYY-MM-DDTHH:MM:SS", value)
+        })),
+    };
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, 1000);
+    let file_query = FileQuery {
+        category: query.filter_category.clone(),
+        size_min: query.filter_size_min,
+        size_max: query.filter_size_max,
+        search: query.search_term.clone(),
+        size_mode: query.size_mode.unwrap_or_default(),
+        mismatched_only: query.filter_extension_mismatch.unwrap_or(false),
+        captured_after,
+        captured_before,
+        width_min: query.filter_width_min,
+        width_max: query.filter_width_max,
+        height_min: query.filter_height_min,
+        height_max: query.filter_height_max,
+        duration_min: query.filter_duration_min,
+        duration_max: query.filter_duration_max,
+        artist: query.filter_artist.clone(),
+        album: query.filter_album.clone(),
+        sort,
+        descending: query.sort_order.as_deref().unwrap_or("asc") != "asc",
+        offset: (page - 1) * page_size,
+        limit: page_size,
+    };
+    
+    let queried = web::block(move || storage::query_files(&config_dir, &file_query)).await;
+    match queried.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+        Ok((page_files, total_files)) => {
             // Convert to a simplified format for the frontend
             let file_list: Vec<serde_json::Value> = page_files.iter()
                 .map(|meta| {
@@ -461,10 +654,14 @@ async fn get_file_list(query: web::Query<FileListRequest>) -> impl Responder {
                         "extension": meta.extension,
                         "size": meta.size,
                         "size_formatted": format_file_size(meta.size),
+                        "allocated_size": meta.allocated_size,
+                        "allocated_size_formatted": format_file_size(meta.allocated_size),
                         "created": meta.created.timestamp(),
                         "modified": meta.modified.timestamp(),
                         "category": meta.category,
                         "mime_type": meta.mime_type,
+                        "extension_mismatch": meta.extension_mismatch,
+                        "extended": meta.extended,
                         "importance": meta.importance_score,
                         "is_duplicate": meta.is_duplicate,
                     })
@@ -475,7 +672,7 @@ async fn get_file_list(query: web::Query<FileListRequest>) -> impl Responder {
                 "total": total_files,
                 "page": page,
                 "page_size": page_size,
-                "total_pages": total_pages,
+                "total_pages": total_files.div_ceil(page_size),
                 "files": file_list
             }))
         },
@@ -509,29 +706,32 @@ async fn get_file_details(path: web::Path<String>) -> impl Responder {
     let file_path = std::path::Path::new(&*path);
     let config_dir = crate::get_config_dir();
     
-    match storage::load_file_metadata(&config_dir) {
-        Ok(metadata) => {
-            // Find the file in the metadata
-            for (_, meta) in metadata.iter() {
-                if meta.path == file_path {
-                    return HttpResponse::Ok().json(serde_json::json!({
-                        "path": meta.path.to_string_lossy().to_string(),
-                        "name": meta.file_name,
-                        "extension": meta.extension,
-                        "size": meta.size,
-                        "size_formatted": format_file_size(meta.size),
-                        "created": meta.created.timestamp(),
-                        "modified": meta.modified.timestamp(),
-                        "category": meta.category,
-                        "mime_type": meta.mime_type,
-                        "importance": meta.importance_score,
-                        "is_duplicate": meta.is_duplicate,
-                        "duplicate_of": meta.duplicate_of.as_ref().map(|p| p.to_string_lossy().to_string()),
-                        "ai_analysis": meta.ai_analysis,
-                    }));
-                }
-            }
-            
+    match storage::get_file_metadata(&config_dir, file_path) {
+        Ok(Some(meta)) => {
+            HttpResponse::Ok().json(serde_json::json!({
+                "path": meta.path.to_string_lossy().to_string(),
+                "name": meta.file_name,
+                "extension": meta.extension,
+                "size": meta.size,
+                "size_formatted": format_file_size(meta.size),
+                "allocated_size": meta.allocated_size,
+                "allocated_size_formatted": format_file_size(meta.allocated_size),
+                "created": meta.created.timestamp(),
+                "modified": meta.modified.timestamp(),
+                "category": meta.category,
+                "mime_type": meta.mime_type,
+                "extension_mismatch": meta.extension_mismatch,
+                "extended": meta.extended,
+                "importance": meta.importance_score,
+                "is_duplicate": meta.is_duplicate,
+                "duplicate_of": meta.duplicate_of.as_ref().map(|p| p.to_string_lossy().to_string()),
+                "link_count": meta.link_count,
+                "hardlink_of": meta.hardlink_of.as_ref().map(|p| p.to_string_lossy().to_string()),
+                "is_deleted": meta.is_deleted,
+                "ai_analysis": meta.ai_analysis,
+            }))
+        },
+        Ok(None) => {
             HttpResponse::NotFound().json(serde_json::json!({
                 "error": "File not found in metadata"
             }))
@@ -544,6 +744,84 @@ async fn get_file_details(path: web::Path<String>) -> impl Responder {
     }
 }
 
+#[derive(Deserialize)]
+struct PathQuery {
+    path: String,
+}
+
+// How each factor contributed to a file's importance
+async fn explain_importance(query: web::Query<PathQuery>, config: web::Data<Arc<Mutex<Config>>>) -> impl Responder {
+    match storage::get_file_metadata(&crate::get_config_dir(), std::path::Path::new(&query.path)) {
+        Ok(Some(meta)) if !meta.is_deleted => {
+            let config = config.lock().unwrap().clone();
+            let explanation = scoring::explain(&meta, &config);
+            HttpResponse::Ok().json(serde_json::json!({
+                "stored_score": meta.importance_score,
+                "explanation": explanation,
+            }))
+        },
+        Ok(_) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "File not found in metadata"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load metadata: {}", e)
+        })),
+    }
+}
+
+async fn list_pins() -> impl Responder {
+    match storage::load_pins(&crate::get_config_dir()) {
+        Ok(pins) => HttpResponse::Ok().json(pins),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to load pins: {}", e)
+        })),
+    }
+}
+
+// Pinning a directory pins everything below it
+async fn pin_path(
+    data: web::Json<PathQuery>,
+    config: web::Data<Arc<Mutex<Config>>>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
+    let path = PathBuf::from(&data.path);
+    if !path.is_absolute() {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "Path must be absolute"
+        }));
+    }
+    match storage::add_pin(&crate::get_config_dir(), &path) {
+        Ok(added) => {
+            if added {
+                jobs.rescore(config.get_ref().clone());
+            }
+            HttpResponse::Ok().json(serde_json::json!({"status": "pinned", "path": path}))
+        },
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to save pin: {}", e)
+        })),
+    }
+}
+
+async fn unpin_path(
+    query: web::Query<PathQuery>,
+    config: web::Data<Arc<Mutex<Config>>>,
+    jobs: web::Data<Arc<JobManager>>,
+) -> impl Responder {
+    match storage::remove_pin(&crate::get_config_dir(), std::path::Path::new(&query.path)) {
+        Ok(true) => {
+            jobs.rescore(config.get_ref().clone());
+            HttpResponse::Ok().json(serde_json::json!({"status": "unpinned", "path": query.path}))
+        },
+        Ok(false) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "Path is not pinned"
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(serde_json::json!({
+            "error": format!("Failed to remove pin: {}", e)
+        })),
+    }
+}
+
 async fn create_file(data: web::Json<FileOpRequest>) -> impl Responder {
     let path = std::path::Path::new(&data.path);
     if let Some(content) = &data.content {
@@ -552,7 +830,7 @@ async fn create_file(data: web::Json<FileOpRequest>) -> impl Responder {
             let _ = file.write_all(content.as_bytes());
             return HttpResponse::Ok().json(serde_json::json!({"status": "created"}));
         }
-    } else if let Ok(_) = std::fs::File::create(path) {
+    } else if std::fs::File::create(path).is_ok() {
         return HttpResponse::Ok().json(serde_json::json!({"status": "created"}));
     }
     HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to create file"}))
@@ -593,27 +871,22 @@ async fn move_file(data: web::Json<FileOpRequest>) -> impl Responder {
     HttpResponse::InternalServerError().json(serde_json::json!({"error": "Failed to move file"}))
 }
 
-pub fn start_server(config: Arc<Mutex<Config>>, port: u16, verbose: bool) {
+pub fn start_server(config: Arc<Mutex<Config>>, jobs: Arc<JobManager>, port: u16, verbose: bool) {
     if verbose {
         println!("Starting API server on http://0.0.0.0:{}", port);
     }
     
     // Use actix_web to run the server
     let config_data = web::Data::new(config);
+    let jobs_data = web::Data::new(jobs);
     
     // Create an actix system
     let system = actix_web::rt::System::new();
     
     // Run the server in the system
     system.block_on(async move {
-        // Configure CORS to allow access from Flutter app
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header()
-            .max_age(3600);
-        
         let app = HttpServer::new(move || {
+            // Configure CORS to allow access from Flutter app
             let cors = Cors::default()
                 .allow_any_origin()
                 .allow_any_method()
@@ -624,15 +897,32 @@ pub fn start_server(config: Arc<Mutex<Config>>, port: u16, verbose: bool) {
                 .wrap(Logger::default())
                 .wrap(cors)
                 .app_data(config_data.clone())
+                .app_data(jobs_data.clone())
                 .route("/health", web::get().to(health_check))
                 .route("/status", web::get().to(get_system_status))
                 .route("/status/live", web::get().to(get_live_status))
+                .route("/events", web::get().to(stream_events))
                 .route("/drives", web::get().to(get_drives))
                 .route("/stats", web::get().to(get_scan_stats))
+                .route("/snapshots", web::get().to(list_snapshots))
+                .route("/snapshots/diff", web::get().to(diff_snapshots))
                 .route("/scan", web::post().to(initiate_scan))
+                .route("/scans", web::get().to(list_scans))
+                .route("/scans", web::post().to(start_scan))
+                .route("/scans/{id}", web::get().to(get_scan))
+                .route("/scans/{id}", web::delete().to(cancel_scan))
+                .route("/scans/{id}/{action}", web::post().to(control_scan))
                 .route("/metadata", web::get().to(get_metadata))
+                .route("/duplicates", web::get().to(get_duplicates))
+                .route("/hardlinks", web::get().to(get_hardlinks))
+                .route("/tree", web::get().to(get_tree))
+                .route("/treemap", web::get().to(get_treemap))
                 .route("/files", web::get().to(get_file_list))
                 .route("/files/{path:.*}", web::get().to(get_file_details))
+                .route("/importance", web::get().to(explain_importance))
+                .route("/pins", web::get().to(list_pins))
+                .route("/pins", web::post().to(pin_path))
+                .route("/pins", web::delete().to(unpin_path))
                 .route("/config", web::get().to(get_config))
                 .route("/config", web::post().to(update_config))
                 .route("/file/create", web::post().to(create_file))
@@ -642,7 +932,7 @@ pub fn start_server(config: Arc<Mutex<Config>>, port: u16, verbose: bool) {
                 .route("/file/move", web::post().to(move_file))
         })
         .bind(format!("0.0.0.0:{}", port))
-        .expect(&format!("Failed to bind to port {}", port));
+        .unwrap_or_else(|_| panic!("Failed to bind to port {}", port));
         
         if verbose {
             println!("API server started successfully on port {}", port);
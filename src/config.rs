@@ -1,36 +1,289 @@
-use std::collections::HashSet;
-use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde_derive::{Serialize, Deserialize};
+use crate::exclusion::ExclusionRules;
+use crate::persist;
+use crate::scoring::ScoringConfig;
+use crate::taxonomy::{CategoryRule, Taxonomy};
 
-#[derive(Debug)]
+/// Settings file in the config directory.
+pub const CONFIG_FILE_NAME: &str = "config.json";
+
+/// Version of the on-disk configuration format written by this build.
+/// Files without a `version` key predate versioning and are upgraded on load.
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanMode {
+    Sequential,
+    Concurrent,
+}
+
+/// Settings of one scan root, keyed by its path in [`Config::root_options`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RootOptions {
+    /// Stop at mount points below the root, like `du -x`. When off, mounts
+    /// below are walked as part of the root instead of as roots of their own.
+    pub same_file_system: bool,
+}
+
+impl Default for RootOptions {
+    fn default() -> Self {
+        RootOptions { same_file_system: true }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub version: u32,
+    pub scan_mode: ScanMode,
+    /// Gitignore-style patterns, applied in order; see [`ExclusionRules`].
+    pub excluded_paths: Vec<String>,
+    /// Also honour `.gitignore` and `.ddignore` files found while scanning.
+    pub respect_ignore_files: bool,
+    /// Per-root settings; a path not listed takes those of the closest
+    /// listed ancestor, or the defaults.
+    pub root_options: BTreeMap<String, RootOptions>,
+    /// Filesystem types never scanned, such as network and in-memory ones.
+    pub skip_fs_types: Vec<String>,
+    /// Category rules tried before the built-in ones; see [`CategoryRule`].
+    pub category_rules: Vec<CategoryRule>,
+    /// Weights of the importance score; see [`ScoringConfig`].
+    pub scoring: ScoringConfig,
+    pub max_concurrent_scans: usize,
+    pub analyze_content: bool,
     pub use_ai_analysis: bool,
     pub ollama_model: String,
     pub ollama_url: String,
-    pub excluded_paths: HashSet<PathBuf>,
+    pub scan_interval_hours: u64,
+    pub notification_enabled: bool,
+    pub ui_theme: String,
+    pub file_preview_enabled: bool,
 }
 
-impl Config {
-    pub fn load_or_create(config_path: &Path) -> Self {
-        if !config_path.exists() {
-            let default = Config {
-                use_ai_analysis: false,
-                ollama_model: "default-model".to_string(),
-                ollama_url: "http://localhost:11434".to_string(),
-                excluded_paths: HashSet::new(),
-            };
-            // Optionally save default configuration
-            return default;
-        }
-        // Load configuration logic (placeholder)
+impl Default for Config {
+    fn default() -> Self {
         Config {
-            use_ai_analysis: true,
+            version: CONFIG_VERSION,
+            scan_mode: ScanMode::Sequential,
+            excluded_paths: Vec::new(),
+            respect_ignore_files: false,
+            root_options: BTreeMap::new(),
+            skip_fs_types: ["nfs", "nfs4", "cifs", "smb3", "fuse.sshfs", "overlay", "tmpfs", "squashfs"]
+                .iter()
+                .map(|fs_type| fs_type.to_string())
+                .collect(),
+            category_rules: Vec::new(),
+            scoring: ScoringConfig::default(),
+            max_concurrent_scans: 4,
+            analyze_content: true,
+            use_ai_analysis: false,
             ollama_model: "default-model".to_string(),
             ollama_url: "http://localhost:11434".to_string(),
-            excluded_paths: HashSet::new(),
+            scan_interval_hours: 24,
+            notification_enabled: true,
+            ui_theme: "system".to_string(),
+            file_preview_enabled: true,
+        }
+    }
+}
+
+/// A problem with a single configuration setting.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Syntax(serde_json::Error),
+    UnsupportedVersion(u32),
+    Invalid(Vec<FieldError>),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "could not access configuration file: {}", e),
+            ConfigError::Syntax(e) => write!(f, "configuration is not valid JSON: {}", e),
+            ConfigError::UnsupportedVersion(v) => write!(
+                f,
+                "configuration version {} is newer than the supported version {}",
+                v, CONFIG_VERSION
+            ),
+            ConfigError::Invalid(errors) => {
+                write!(f, "invalid configuration:")?;
+                for error in errors {
+                    write!(f, "\n  - {}: {}", error.field, error.message)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl Config {
+    /// Loads the configuration at `config_path`, writing the defaults there
+    /// first if the file does not exist yet.
+    pub fn load_or_create(config_path: &Path) -> Result<Self, ConfigError> {
+        if !config_path.exists() {
+            let default = Config::default();
+            default.save(config_path)?;
+            return Ok(default);
+        }
+
+        let content = fs::read_to_string(config_path)?;
+        let value: serde_json::Value = serde_json::from_str(&content).map_err(ConfigError::Syntax)?;
+        let upgrade = value.get("version").is_none();
+        let config = Config::from_value(value)?;
+
+        // Stamp legacy files with the current version so the upgrade only happens once
+        if upgrade {
+            config.save(config_path)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Builds a configuration from a JSON object, collecting an error for
+    /// every setting that has the wrong type or an out-of-range value.
+    /// Settings missing from `value` take their default.
+    pub fn from_value(value: serde_json::Value) -> Result<Self, ConfigError> {
+        let serde_json::Value::Object(settings) = value else {
+            return Err(ConfigError::Invalid(vec![FieldError {
+                field: "(root)".to_string(),
+                message: "expected a JSON object".to_string(),
+            }]));
+        };
+
+        let version = match settings.get("version") {
+            None => 0,
+            Some(v) => match v.as_u64() {
+                Some(v) => v as u32,
+                None => {
+                    return Err(ConfigError::Invalid(vec![FieldError {
+                        field: "version".to_string(),
+                        message: "expected an unsigned integer".to_string(),
+                    }]))
+                }
+            },
+        };
+        if version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+
+        let serde_json::Value::Object(defaults) = serde_json::to_value(Config::default()).map_err(ConfigError::Syntax)? else {
+            unreachable!("Config always serializes to an object");
+        };
+
+        // Check each setting on its own against the defaults so that every bad
+        // field is reported, not just the first one serde trips over.
+        let mut errors = Vec::new();
+        let mut merged = defaults.clone();
+        for (key, setting) in settings {
+            if key == "version" {
+                continue;
+            }
+            if !defaults.contains_key(&key) {
+                eprintln!("Ignoring unknown configuration setting '{}'", key);
+                continue;
+            }
+            let mut candidate = defaults.clone();
+            candidate.insert(key.clone(), setting.clone());
+            match serde_json::from_value::<Config>(serde_json::Value::Object(candidate)) {
+                Ok(_) => {
+                    merged.insert(key, setting);
+                }
+                Err(e) => errors.push(FieldError { field: key, message: e.to_string() }),
+            }
+        }
+
+        let mut config: Config = serde_json::from_value(serde_json::Value::Object(merged)).map_err(ConfigError::Syntax)?;
+        config.version = CONFIG_VERSION;
+
+        errors.extend(config.validate());
+        if !errors.is_empty() {
+            return Err(ConfigError::Invalid(errors));
+        }
+
+        Ok(config)
+    }
+
+    /// Checks value ranges that the type system cannot express.
+    pub fn validate(&self) -> Vec<FieldError> {
+        let mut errors = Vec::new();
+        let mut invalid = |field: &str, message: &str| {
+            errors.push(FieldError { field: field.to_string(), message: message.to_string() });
+        };
+
+        for (index, message) in ExclusionRules::pattern_errors(&self.excluded_paths) {
+            invalid(&format!("excluded_paths[{}]", index), &message);
+        }
+        for root in self.root_options.keys() {
+            if !Path::new(root).is_absolute() {
+                invalid(&format!("root_options.{}", root), "must be an absolute path");
+            }
+        }
+        for (index, fs_type) in self.skip_fs_types.iter().enumerate() {
+            if fs_type.trim().is_empty() || fs_type.contains(char::is_whitespace) {
+                invalid(&format!("skip_fs_types[{}]", index), "must be a filesystem type name");
+            }
+        }
+        for (index, message) in Taxonomy::rule_errors(&self.category_rules) {
+            invalid(&format!("category_rules[{}]", index), &message);
+        }
+        for (field, message) in self.scoring.errors() {
+            invalid(&format!("scoring.{}", field), &message);
+        }
+        if self.max_concurrent_scans == 0 || self.max_concurrent_scans > 256 {
+            invalid("max_concurrent_scans", "must be between 1 and 256");
+        }
+        if self.scan_interval_hours == 0 {
+            invalid("scan_interval_hours", "must be at least 1");
         }
+        if self.ollama_model.trim().is_empty() {
+            invalid("ollama_model", "must not be empty");
+        }
+        if !self.ollama_url.starts_with("http://") && !self.ollama_url.starts_with("https://") {
+            invalid("ollama_url", "must start with http:// or https://");
+        }
+        if !matches!(self.ui_theme.as_str(), "system" | "light" | "dark") {
+            invalid("ui_theme", "must be one of system, light, dark");
+        }
+
+        errors
+    }
+
+    /// Options of the root at or closest above `path`.
+    pub fn root_options_for(&self, path: &Path) -> RootOptions {
+        self.root_options.iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .max_by_key(|(root, _)| Path::new(root).components().count())
+            .map(|(_, options)| options.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn same_file_system(&self, path: &Path) -> bool {
+        self.root_options_for(path).same_file_system
     }
 
-    pub fn is_path_excluded(&self, path: &Path) -> bool {
-        self.excluded_paths.contains(path)
+    pub fn save(&self, config_path: &Path) -> io::Result<()> {
+        persist::write_atomic(config_path, serde_json::to_string_pretty(self)?.as_bytes())
     }
 }
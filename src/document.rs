@@ -0,0 +1,134 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use serde_derive::{Serialize, Deserialize};
+use zip::ZipArchive;
+
+/// PDFs larger than this are not read, as the reader loads the whole file.
+const MAX_PDF_BYTES: u64 = 256 * 1024 * 1024;
+/// Largest XML part read from an office document.
+const MAX_PART_BYTES: u64 = 4 * 1024 * 1024;
+const OLE_SIGNATURE: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+
+/// What the properties of a PDF or office document tell about it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DocumentMetadata {
+    /// Pages; slides of presentations and sheets of spreadsheets.
+    pub page_count: Option<u32>,
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// Application that created the document.
+    pub creator_tool: Option<String>,
+    /// Whether the content is password protected. The other fields are
+    /// usually unknown then.
+    pub encrypted: bool,
+}
+
+/// Reads the properties of PDF, Office Open XML and OpenDocument files.
+/// `None` for other formats and unreadable files.
+pub fn read(path: &Path, mime_type: &str) -> Option<DocumentMetadata> {
+    match mime_type {
+        "application/pdf" => pdf(path),
+        _ if mime_type.starts_with("application/vnd.openxmlformats-officedocument.") => office_open_xml(path),
+        _ if mime_type.starts_with("application/vnd.oasis.opendocument.") => open_document(path),
+        _ => None,
+    }
+}
+
+fn pdf(path: &Path) -> Option<DocumentMetadata> {
+    if fs::metadata(path).ok()?.len() > MAX_PDF_BYTES {
+        return None;
+    }
+    let pdf = lopdf::Document::load_metadata(path).ok()?;
+    let text = |value: Option<String>| value.as_deref().and_then(clean);
+    Some(DocumentMetadata {
+        page_count: Some(pdf.page_count).filter(|count| *count > 0),
+        title: text(pdf.title),
+        author: text(pdf.author),
+        // The authoring application, or the one that converted it to PDF
+        creator_tool: text(pdf.creator).or_else(|| text(pdf.producer)),
+        encrypted: pdf.encrypted,
+    })
+}
+
+fn office_open_xml(path: &Path) -> Option<DocumentMetadata> {
+    let mut file = File::open(path).ok()?;
+    // Office encrypts these documents by wrapping them in an OLE container
+    let mut signature = [0; 8];
+    if file.read_exact(&mut signature).is_ok() && signature == OLE_SIGNATURE {
+        return Some(DocumentMetadata { encrypted: true, ..Default::default() });
+    }
+    let mut archive = ZipArchive::new(file).ok()?;
+    let mut metadata = DocumentMetadata::default();
+    if let Some(core) = zip_part(&mut archive, "docProps/core.xml") {
+        if let Ok(core) = roxmltree::Document::parse(&core) {
+            metadata.title = element_text(&core, "title");
+            metadata.author = element_text(&core, "creator");
+        }
+    }
+    if let Some(app) = zip_part(&mut archive, "docProps/app.xml") {
+        if let Ok(app) = roxmltree::Document::parse(&app) {
+            metadata.creator_tool = element_text(&app, "Application");
+            metadata.page_count = ["Pages", "Slides"].iter()
+                .find_map(|name| element_text(&app, name)?.parse().ok());
+        }
+    }
+    // Workbooks list their sheets rather than count pages
+    if metadata.page_count.is_none() {
+        if let Some(workbook) = zip_part(&mut archive, "xl/workbook.xml") {
+            if let Ok(workbook) = roxmltree::Document::parse(&workbook) {
+                let sheets = workbook.descendants().filter(|node| node.tag_name().name() == "sheet").count();
+                metadata.page_count = u32::try_from(sheets).ok().filter(|sheets| *sheets > 0);
+            }
+        }
+    }
+    Some(metadata)
+}
+
+fn open_document(path: &Path) -> Option<DocumentMetadata> {
+    let mut archive = ZipArchive::new(File::open(path).ok()?).ok()?;
+    let mut metadata = DocumentMetadata {
+        // The manifest describes how each encrypted part was encrypted;
+        // meta.xml itself stays readable
+        encrypted: zip_part(&mut archive, "META-INF/manifest.xml")
+            .is_some_and(|manifest| manifest.contains("encryption-data")),
+        ..Default::default()
+    };
+    if let Some(meta) = zip_part(&mut archive, "meta.xml") {
+        if let Ok(meta) = roxmltree::Document::parse(&meta) {
+            metadata.title = element_text(&meta, "title");
+            // dc:creator is whoever saved it last
+            metadata.author = element_text(&meta, "initial-creator").or_else(|| element_text(&meta, "creator"));
+            metadata.creator_tool = element_text(&meta, "generator");
+            metadata.page_count = meta.descendants()
+                .find(|node| node.tag_name().name() == "document-statistic")
+                .and_then(|statistics| {
+                    statistics.attributes()
+                        .find(|attribute| matches!(attribute.name(), "page-count" | "table-count"))?
+                        .value()
+                        .parse()
+                        .ok()
+                });
+        }
+    }
+    Some(metadata)
+}
+
+fn zip_part(archive: &mut ZipArchive<File>, name: &str) -> Option<String> {
+    let part = archive.by_name(name).ok()?;
+    let mut text = String::new();
+    part.take(MAX_PART_BYTES).read_to_string(&mut text).ok()?;
+    Some(text)
+}
+
+/// Text of the first element with this local name, whatever its namespace.
+fn element_text(document: &roxmltree::Document, name: &str) -> Option<String> {
+    document.descendants()
+        .find(|node| node.is_element() && node.tag_name().name() == name)
+        .and_then(|node| clean(node.text()?))
+}
+
+fn clean(text: &str) -> Option<String> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
@@ -0,0 +1,216 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use rayon::prelude::*;
+use serde_derive::Serialize;
+use crate::storage::{self, FileMetadata, SharedFile};
+
+/// Bytes hashed from each end of a file before committing to a full read.
+const PARTIAL_HASH_BYTES: u64 = 16 * 1024;
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files with identical content, as served by `/duplicates`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub size: u64,
+    /// The copy the others are marked as duplicates of comes first.
+    pub files: Vec<PathBuf>,
+    /// Space that removing every copy but the first would free.
+    pub wasted_bytes: u64,
+}
+
+/// Paths that are hardlinks to one inode, as served by `/hardlinks`. They
+/// share their storage, so unlike duplicates none of them is a cleanup candidate.
+#[derive(Debug, Clone, Serialize)]
+pub struct HardlinkGroup {
+    pub device: u64,
+    pub inode: u64,
+    pub size: u64,
+    /// The link that carries the size in the totals comes first.
+    pub files: Vec<PathBuf>,
+    /// Space the links would take up if they were separate copies.
+    pub shared_bytes: u64,
+}
+
+/// Re-evaluates duplicates across the whole stored index and saves the hash
+/// and duplicate flags that changed. Returns the number of duplicates.
+pub fn update_index(config_dir: &Path) -> io::Result<usize> {
+    let mut index = storage::load_file_metadata(config_dir)?;
+    let changed = mark_duplicates(&mut index);
+    if !changed.is_empty() {
+        storage::set_duplicate_state(config_dir, &changed)?;
+    }
+    Ok(index.values().filter(|meta| meta.is_duplicate).count())
+}
+
+/// Finds files with identical content in three stages, each only looking at
+/// the survivors of the previous one: equal size, equal hash of the first and
+/// last few kilobytes, equal hash of the whole file. Full hashes are kept in
+/// `content_hash` and reused while the file is unchanged, without reading
+/// the file again for a partial hash.
+///
+/// Within a group the oldest file is treated as the original. Hardlinks are
+/// the same file rather than copies, so each inode takes part only once,
+/// through the link that carries its size. Returns the entries that were
+/// modified.
+fn mark_duplicates(index: &mut HashMap<PathBuf, FileMetadata>) -> HashMap<PathBuf, FileMetadata> {
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    let mut inodes = HashSet::new();
+    for meta in index.values().filter(|meta| !meta.is_deleted && meta.size > 0 && meta.hardlink_of.is_none()) {
+        if meta.link_count > 1 && !inodes.insert((meta.device, meta.inode)) {
+            continue;
+        }
+        by_size.entry(meta.size).or_default().push(meta.path.clone());
+    }
+    // Files hashed before are grouped by that hash directly. The rest only
+    // need a full read when they share a partial hash with another one, or
+    // a size with a hashed file they could be a copy of.
+    let mut full_hashes: Vec<(PathBuf, String)> = Vec::new();
+    let mut unhashed: Vec<(u64, PathBuf)> = Vec::new();
+    let mut hashed_sizes = HashSet::new();
+    for (size, paths) in by_size.into_iter().filter(|(_, paths)| paths.len() > 1) {
+        for path in paths {
+            match index[&path].content_hash.clone() {
+                Some(hash) => {
+                    hashed_sizes.insert(size);
+                    full_hashes.push((path, hash));
+                },
+                None => unhashed.push((size, path)),
+            }
+        }
+    }
+
+    let mut by_partial: HashMap<(u64, blake3::Hash), Vec<PathBuf>> = HashMap::new();
+    let partial_hashes: Vec<_> = unhashed
+        .into_par_iter()
+        .filter_map(|(size, path)| partial_hash(&path, size).ok().map(|hash| ((size, hash), path)))
+        .collect();
+    for (key, path) in partial_hashes {
+        by_partial.entry(key).or_default().push(path);
+    }
+    let candidates: Vec<PathBuf> = by_partial
+        .into_iter()
+        .filter(|((size, _), paths)| paths.len() > 1 || hashed_sizes.contains(size))
+        .flat_map(|(_, paths)| paths)
+        .collect();
+
+    full_hashes.par_extend(candidates.into_par_iter().filter_map(|path| {
+        let hash = full_hash(&path).ok()?;
+        Some((path, hash.to_hex().to_string()))
+    }));
+
+    let mut groups: HashMap<(u64, String), Vec<PathBuf>> = HashMap::new();
+    let mut hashes: HashMap<PathBuf, String> = HashMap::new();
+    for (path, hash) in full_hashes {
+        let size = index[&path].size;
+        groups.entry((size, hash.clone())).or_default().push(path.clone());
+        hashes.insert(path, hash);
+    }
+
+    let mut originals: HashMap<PathBuf, PathBuf> = HashMap::new();
+    for mut paths in groups.into_values().filter(|paths| paths.len() > 1) {
+        paths.sort_by(|a, b| index[a].modified.cmp(&index[b].modified).then_with(|| a.cmp(b)));
+        let (original, copies) = paths.split_first().unwrap();
+        for copy in copies {
+            originals.insert(copy.clone(), original.clone());
+        }
+    }
+
+    let mut changed = HashMap::new();
+    for (path, meta) in index.iter_mut().filter(|(_, meta)| !meta.is_deleted) {
+        let duplicate_of = originals.remove(path);
+        let content_hash = hashes.remove(path).or_else(|| meta.content_hash.clone());
+        if meta.duplicate_of == duplicate_of && meta.is_duplicate == duplicate_of.is_some() && meta.content_hash == content_hash {
+            continue;
+        }
+        meta.is_duplicate = duplicate_of.is_some();
+        meta.duplicate_of = duplicate_of;
+        meta.content_hash = content_hash;
+        changed.insert(path.clone(), meta.clone());
+    }
+    changed
+}
+
+/// Sets of files with identical content of at least `min_size` bytes,
+/// largest waste first.
+pub fn duplicate_groups(config_dir: &Path, min_size: u64) -> io::Result<Vec<DuplicateGroup>> {
+    let mut by_hash: HashMap<(u64, String), Vec<SharedFile>> = HashMap::new();
+    for (hash, file) in storage::load_duplicate_files(config_dir, min_size)? {
+        by_hash.entry((file.size, hash)).or_default().push(file);
+    }
+
+    let mut groups: Vec<DuplicateGroup> = by_hash
+        .into_iter()
+        .map(|((size, hash), files)| DuplicateGroup {
+            content_hash: hash,
+            size,
+            wasted_bytes: size * (files.len() as u64 - 1),
+            files: primary_first(files),
+        })
+        .collect();
+    groups.sort_by(|a, b| b.wasted_bytes.cmp(&a.wasted_bytes).then_with(|| a.files.cmp(&b.files)));
+    Ok(groups)
+}
+
+/// Sets of hardlinks to one inode of at least `min_size` bytes, most shared
+/// space first.
+pub fn hardlink_groups(config_dir: &Path, min_size: u64) -> io::Result<Vec<HardlinkGroup>> {
+    let mut by_inode: HashMap<(u64, u64), Vec<SharedFile>> = HashMap::new();
+    for (inode, file) in storage::load_hardlinked_files(config_dir, min_size)? {
+        by_inode.entry(inode).or_default().push(file);
+    }
+
+    let mut groups: Vec<HardlinkGroup> = by_inode
+        .into_iter()
+        .map(|((device, inode), files)| {
+            let size = files[0].size;
+            HardlinkGroup {
+                device,
+                inode,
+                size,
+                shared_bytes: size * (files.len() as u64 - 1),
+                files: primary_first(files),
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.shared_bytes.cmp(&a.shared_bytes).then_with(|| a.files.cmp(&b.files)));
+    Ok(groups)
+}
+
+/// Paths of a set with the original or size-carrying file first, then by path.
+fn primary_first(mut files: Vec<SharedFile>) -> Vec<PathBuf> {
+    files.sort_by(|a, b| a.is_secondary.cmp(&b.is_secondary).then_with(|| a.path.cmp(&b.path)));
+    files.into_iter().map(|file| file.path).collect()
+}
+
+fn partial_hash(path: &Path, size: u64) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = Vec::with_capacity(PARTIAL_HASH_BYTES as usize);
+
+    file.by_ref().take(PARTIAL_HASH_BYTES).read_to_end(&mut buffer)?;
+    hasher.update(&buffer);
+    if size > 2 * PARTIAL_HASH_BYTES {
+        buffer.clear();
+        file.seek(SeekFrom::End(-(PARTIAL_HASH_BYTES as i64)))?;
+        file.take(PARTIAL_HASH_BYTES).read_to_end(&mut buffer)?;
+        hasher.update(&buffer);
+    }
+    Ok(hasher.finalize())
+}
+
+fn full_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut file = File::open(path)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0; READ_BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher.finalize())
+}
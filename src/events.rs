@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use serde_derive::Serialize;
+use tokio::sync::broadcast;
+use crate::ai_integration::AIAnalysisResult;
+use crate::config::Config;
+use crate::jobs::JobSnapshot;
+
+/// Events buffered per subscriber; a client that falls further behind skips
+/// ahead and is told how many it missed.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileChange {
+    Created,
+    Modified,
+    Deleted,
+}
+
+/// Everything the daemon pushes to `/events` subscribers.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// Sent when a scan changes state and periodically while it runs.
+    ScanProgress(JobSnapshot),
+    /// A file the watcher saw change on disk.
+    FileChanged { path: PathBuf, change: FileChange },
+    AiAnalysisCompleted { path: PathBuf, analysis: AIAnalysisResult },
+    ConfigChanged { config: Config },
+}
+
+impl Event {
+    /// The SSE `event:` name, which matches the `type` field of the payload.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::ScanProgress(_) => "scan_progress",
+            Event::FileChanged { .. } => "file_changed",
+            Event::AiAnalysisCompleted { .. } => "ai_analysis_completed",
+            Event::ConfigChanged { .. } => "config_changed",
+        }
+    }
+}
+
+fn bus() -> &'static broadcast::Sender<Event> {
+    static BUS: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    BUS.get_or_init(|| broadcast::channel(CHANNEL_CAPACITY).0)
+}
+
+/// Sends `event` to every current subscriber. Safe to call from any thread;
+/// events published while nobody listens are dropped.
+pub fn publish(event: Event) {
+    let _ = bus().send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    bus().subscribe()
+}
@@ -0,0 +1,139 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::Match;
+use crate::config::Config;
+
+/// Per-directory ignore files honoured when `respect_ignore_files` is set.
+const IGNORE_FILE_NAMES: [&str; 2] = [".gitignore", ".ddignore"];
+
+/// Compiled form of the `excluded_paths` setting.
+///
+/// Patterns use gitignore syntax anchored at the filesystem root: `/var/tmp`
+/// or `~/.cache` name one subtree, `*.tmp` and `**/node_modules` match at any
+/// depth, a trailing `/` only matches directories and a leading `!`
+/// re-includes something an earlier pattern excluded.
+pub struct ExclusionRules {
+    global: Gitignore,
+    respect_ignore_files: bool,
+}
+
+/// The `.gitignore`/`.ddignore` matchers in effect for one directory during a
+/// walk, innermost last. Cloning is cheap, so every directory carries its own.
+#[derive(Clone, Default)]
+pub struct IgnoreScope {
+    matchers: Vec<Arc<Gitignore>>,
+}
+
+impl ExclusionRules {
+    pub fn from_config(config: &Config) -> Self {
+        ExclusionRules::new(&config.excluded_paths, config.respect_ignore_files)
+    }
+
+    /// Builds the rules, skipping patterns that do not compile. Use
+    /// [`ExclusionRules::pattern_errors`] to report those.
+    pub fn new(patterns: &[String], respect_ignore_files: bool) -> Self {
+        let mut builder = GitignoreBuilder::new("/");
+        for pattern in patterns {
+            let _ = builder.add_line(None, &expand_home(pattern));
+        }
+        let global = builder.build().unwrap_or_else(|_| Gitignore::empty());
+        ExclusionRules { global, respect_ignore_files }
+    }
+
+    /// Returns the index and error message of every pattern that is invalid.
+    pub fn pattern_errors(patterns: &[String]) -> Vec<(usize, String)> {
+        patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(index, pattern)| {
+                let mut builder = GitignoreBuilder::new("/");
+                let result = builder
+                    .add_line(None, &expand_home(pattern))
+                    .map_err(|e| e.to_string())
+                    .and_then(|builder| builder.build().map_err(|e| e.to_string()));
+                result.err().map(|e| (index, e))
+            })
+            .collect()
+    }
+
+    /// Decides whether a walk should skip `path`, given the ignore files
+    /// collected on the way down. Explicit settings win over ignore files in
+    /// either direction, so a `!` pattern in the config can re-include
+    /// something a `.gitignore` hides.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool, scope: &IgnoreScope) -> bool {
+        match self.global.matched(path, is_dir) {
+            Match::Ignore(_) => return true,
+            Match::Whitelist(_) => return false,
+            Match::None => {}
+        }
+        for matcher in scope.matchers.iter().rev() {
+            match matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+
+    /// Checks an arbitrary path against the configured patterns, including its
+    /// ancestors, for callers that do not walk the tree top-down. Ignore files
+    /// are not consulted here. The caller supplies the kind of `path`, such as
+    /// the one it was indexed as, so nothing is read from disk.
+    pub fn is_path_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        // The filesystem root itself is never excluded; `/*` would otherwise match it
+        let ancestors: Vec<&Path> = path.ancestors()
+            .skip(1)
+            .filter(|ancestor| ancestor.parent().is_some())
+            .collect();
+        for ancestor in ancestors.iter().rev() {
+            if matches!(self.global.matched(ancestor, true), Match::Ignore(_)) {
+                return true;
+            }
+        }
+        path.parent().is_some() && matches!(self.global.matched(path, is_dir), Match::Ignore(_))
+    }
+
+    /// Returns the scope for the children of `dir`, picking up any ignore
+    /// files it contains.
+    pub fn enter_dir(&self, dir: &Path, parent: &IgnoreScope) -> IgnoreScope {
+        if !self.respect_ignore_files {
+            return IgnoreScope::default();
+        }
+
+        let mut scope = parent.clone();
+        for name in IGNORE_FILE_NAMES {
+            let ignore_path = dir.join(name);
+            if !ignore_path.is_file() {
+                continue;
+            }
+            let mut builder = GitignoreBuilder::new(dir);
+            if let Some(e) = builder.add(&ignore_path) {
+                eprintln!("Skipping invalid lines in {}: {}", ignore_path.display(), e);
+            }
+            if let Ok(matcher) = builder.build() {
+                if !matcher.is_empty() {
+                    scope.matchers.push(Arc::new(matcher));
+                }
+            }
+        }
+        scope
+    }
+}
+
+/// Expands a leading `~` (after an optional `!`) to the home directory.
+pub(crate) fn expand_home(pattern: &str) -> String {
+    let (negation, rest) = match pattern.strip_prefix('!') {
+        Some(rest) => ("!", rest),
+        None => ("", pattern),
+    };
+    let home = || dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+    if rest == "~" {
+        format!("{}{}", negation, home().display())
+    } else if let Some(relative) = rest.strip_prefix("~/") {
+        format!("{}{}", negation, home().join(relative).display())
+    } else {
+        pattern.to_string()
+    }
+}
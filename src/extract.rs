@@ -0,0 +1,169 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use chrono::{NaiveDate, NaiveDateTime};
+use exif::{Exif, In, Tag, Value};
+use serde_derive::{Serialize, Deserialize};
+use crate::document::{self, DocumentMetadata};
+use crate::media::{self, MediaMetadata};
+
+/// Format-specific metadata beyond what every file has, by kind of content.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ExtendedMetadata {
+    Image(ImageMetadata),
+    Audio(MediaMetadata),
+    Video(MediaMetadata),
+    Document(DocumentMetadata),
+}
+
+impl ExtendedMetadata {
+    /// Moment the content was captured, in the camera's local time.
+    pub fn captured_at(&self) -> Option<NaiveDateTime> {
+        match self {
+            ExtendedMetadata::Image(image) => image.captured_at,
+            ExtendedMetadata::Audio(_) | ExtendedMetadata::Video(_) | ExtendedMetadata::Document(_) => None,
+        }
+    }
+
+    /// Width and height in pixels.
+    pub fn dimensions(&self) -> Option<(u32, u32)> {
+        match self {
+            ExtendedMetadata::Image(image) => image.width.zip(image.height),
+            ExtendedMetadata::Video(video) => video.width.zip(video.height),
+            ExtendedMetadata::Audio(_) | ExtendedMetadata::Document(_) => None,
+        }
+    }
+
+    /// Container details of audio and video files.
+    pub fn media(&self) -> Option<&MediaMetadata> {
+        match self {
+            ExtendedMetadata::Audio(media) | ExtendedMetadata::Video(media) => Some(media),
+            ExtendedMetadata::Image(_) | ExtendedMetadata::Document(_) => None,
+        }
+    }
+}
+
+/// What the EXIF block of a photo tells about it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    /// `DateTimeOriginal`, or the digitized or file time when it is missing.
+    pub captured_at: Option<NaiveDateTime>,
+    /// Offset of `captured_at` from UTC, when the camera recorded it.
+    pub utc_offset_minutes: Option<i16>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// EXIF orientation, 1 to 8; 1 is upright.
+    pub orientation: Option<u16>,
+    pub gps: Option<GpsPosition>,
+}
+
+/// Where a photo was taken, in decimal degrees and metres above sea level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+/// Reads the extended metadata of formats that carry any. `None` for other
+/// formats and for files without readable metadata.
+pub fn extract(path: &Path, mime_type: &str) -> Option<ExtendedMetadata> {
+    match mime_type {
+        "image/jpeg" | "image/heic" | "image/avif" | "image/tiff" | "image/png" | "image/webp" => {
+            image_metadata(path).map(ExtendedMetadata::Image)
+        },
+        _ if mime_type.starts_with("audio/") || mime_type.starts_with("video/") => {
+            media::read(path, mime_type).map(|media| {
+                if media.has_video() { ExtendedMetadata::Video(media) } else { ExtendedMetadata::Audio(media) }
+            })
+        },
+        _ => document::read(path, mime_type).map(ExtendedMetadata::Document),
+    }
+}
+
+fn image_metadata(path: &Path) -> Option<ImageMetadata> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok()?;
+
+    let (captured_at, utc_offset_minutes) = [
+        (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+        (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+        (Tag::DateTime, Tag::OffsetTime),
+    ]
+        .into_iter()
+        .find_map(|(time, offset)| date_time(&exif, time, offset))
+        .map_or((None, None), |(captured_at, offset)| (Some(captured_at), offset));
+    let metadata = ImageMetadata {
+        captured_at,
+        utc_offset_minutes,
+        camera_make: text(&exif, Tag::Make),
+        camera_model: text(&exif, Tag::Model),
+        width: number(&exif, Tag::PixelXDimension).or_else(|| number(&exif, Tag::ImageWidth)),
+        height: number(&exif, Tag::PixelYDimension).or_else(|| number(&exif, Tag::ImageLength)),
+        orientation: number(&exif, Tag::Orientation).and_then(|orientation| u16::try_from(orientation).ok()),
+        gps: gps_position(&exif),
+    };
+    (metadata != ImageMetadata::default()).then_some(metadata)
+}
+
+fn date_time(exif: &Exif, tag: Tag, offset_tag: Tag) -> Option<(NaiveDateTime, Option<i16>)> {
+    let Value::Ascii(ref values) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let mut parsed = exif::DateTime::from_ascii(values.first()?).ok()?;
+    if let Some(Value::Ascii(offsets)) = exif.get_field(offset_tag, In::PRIMARY).map(|field| &field.value) {
+        if let Some(offset) = offsets.first() {
+            let _ = parsed.parse_offset(offset);
+        }
+    }
+    let captured_at = NaiveDate::from_ymd_opt(parsed.year.into(), parsed.month.into(), parsed.day.into())?
+        .and_hms_opt(parsed.hour.into(), parsed.minute.into(), parsed.second.into())?;
+    Some((captured_at, parsed.offset))
+}
+
+fn text(exif: &Exif, tag: Tag) -> Option<String> {
+    let Value::Ascii(ref values) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let text = String::from_utf8_lossy(values.first()?);
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn number(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif.get_field(tag, In::PRIMARY)?.value.get_uint(0)
+}
+
+fn gps_position(exif: &Exif) -> Option<GpsPosition> {
+    let latitude = degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+    let longitude = degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+    let altitude = match exif.get_field(Tag::GPSAltitude, In::PRIMARY).map(|field| &field.value) {
+        Some(Value::Rational(values)) if !values.is_empty() && values[0].denom != 0 => {
+            let below_sea_level = number(exif, Tag::GPSAltitudeRef) == Some(1);
+            let metres = values[0].to_f64();
+            Some(if below_sea_level { -metres } else { metres })
+        },
+        _ => None,
+    };
+    Some(GpsPosition { latitude, longitude, altitude })
+}
+
+/// A GPS coordinate stored as degrees, minutes and seconds, negated when its
+/// reference is `negative_ref` (south or west).
+fn degrees(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let Value::Rational(ref parts) = exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    if parts.len() < 3 || parts.iter().any(|part| part.denom == 0) {
+        return None;
+    }
+    let value = parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0;
+    let negative = matches!(
+        exif.get_field(ref_tag, In::PRIMARY).map(|field| &field.value),
+        Some(Value::Ascii(refs)) if refs.first().and_then(|r| r.first()) == Some(&negative_ref)
+    );
+    Some(if negative { -value } else { value })
+}
@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Duration, Utc};
+use serde_derive::Serialize;
+use crate::storage::{self, ChangeKind, Snapshot};
+
+/// Which two snapshots to compare and how to report the difference.
+#[derive(Debug, Clone, Default)]
+pub struct DiffQuery {
+    /// Root whose snapshots are compared; defaults to the most recently scanned one.
+    pub root: Option<PathBuf>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Picks the last snapshot taken at or before this time as `from`.
+    pub since: Option<DateTime<Utc>>,
+    /// Only report directories, categories and files whose size changed by
+    /// at least this many bytes.
+    pub min_change: u64,
+    pub direction: Direction,
+    /// Path components below the root that directories are grouped by.
+    pub depth: usize,
+    /// Maximum number of entries in each list of the result.
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Direction {
+    #[default]
+    Any,
+    Grew,
+    Shrank,
+}
+
+#[derive(Debug)]
+pub enum DiffError {
+    NoSnapshots,
+    NotFound(i64),
+    /// The two snapshots belong to different roots.
+    RootMismatch(i64, i64),
+    Io(io::Error),
+}
+
+impl fmt::Display for DiffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffError::NoSnapshots => write!(f, "not enough snapshots to compare"),
+            DiffError::NotFound(id) => write!(f, "snapshot {} not found", id),
+            DiffError::RootMismatch(from, to) => write!(f, "snapshots {} and {} are of different roots", from, to),
+            DiffError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for DiffError {
+    fn from(e: io::Error) -> Self {
+        DiffError::Io(e)
+    }
+}
+
+/// Net change of one file between the two snapshots.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileDelta {
+    pub path: PathBuf,
+    pub category: String,
+    pub kind: ChangeKind,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub bytes_delta: i64,
+}
+
+/// Changes summed over a directory or a category.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DiffGroup {
+    pub key: String,
+    pub bytes_delta: i64,
+    pub added: usize,
+    pub removed: usize,
+    pub grown: usize,
+    pub shrunk: usize,
+}
+
+impl DiffGroup {
+    fn add(&mut self, delta: &FileDelta) {
+        self.bytes_delta += delta.bytes_delta;
+        match delta.kind {
+            ChangeKind::Added => self.added += 1,
+            ChangeKind::Removed => self.removed += 1,
+            ChangeKind::Grown => self.grown += 1,
+            ChangeKind::Shrunk => self.shrunk += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotDiff {
+    pub from: Snapshot,
+    pub to: Snapshot,
+    pub total: DiffGroup,
+    pub by_directory: Vec<DiffGroup>,
+    pub by_category: Vec<DiffGroup>,
+    pub files: Vec<FileDelta>,
+}
+
+/// Parses `since` values such as `7d`, `12h`, `30m` or an RFC 3339 timestamp.
+pub fn parse_since(value: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let split = value.len().checked_sub(1)?;
+    let amount: i64 = value.get(..split)?.parse().ok()?;
+    let span = match value.get(split..)? {
+        "w" => Duration::try_weeks(amount)?,
+        "d" => Duration::try_days(amount)?,
+        "h" => Duration::try_hours(amount)?,
+        "m" => Duration::try_minutes(amount)?,
+        _ => return None,
+    };
+    now.checked_sub_signed(span)
+}
+
+/// Compares two snapshots of the same root. Without explicit ids the newest
+/// snapshot is compared with the one before it, or with the last one taken
+/// before `since`.
+pub fn diff(config_dir: &Path, query: &DiffQuery) -> Result<SnapshotDiff, DiffError> {
+    let all = storage::list_snapshots(config_dir, None)?;
+    let find = |id: i64| all.iter().find(|snapshot| snapshot.id == id).cloned().ok_or(DiffError::NotFound(id));
+
+    let root = match (&query.root, query.to, query.from) {
+        (Some(root), _, _) => root.clone(),
+        (None, Some(id), _) | (None, None, Some(id)) => find(id)?.root,
+        (None, None, None) => all.first().ok_or(DiffError::NoSnapshots)?.root.clone(),
+    };
+    // Newest first
+    let snapshots: Vec<&Snapshot> = all.iter().filter(|snapshot| snapshot.root == root).collect();
+
+    let to = match query.to {
+        Some(id) => find(id)?,
+        None => (*snapshots.first().ok_or(DiffError::NoSnapshots)?).clone(),
+    };
+    let from = match (query.from, query.since) {
+        (Some(id), _) => find(id)?,
+        (None, Some(since)) => snapshots.iter()
+            .find(|snapshot| snapshot.id < to.id && snapshot.taken_at <= since)
+            .or_else(|| snapshots.iter().rev().find(|snapshot| snapshot.id < to.id))
+            .map(|snapshot| (*snapshot).clone())
+            .ok_or(DiffError::NoSnapshots)?,
+        (None, None) => snapshots.iter()
+            .find(|snapshot| snapshot.id < to.id)
+            .map(|snapshot| (*snapshot).clone())
+            .ok_or(DiffError::NoSnapshots)?,
+    };
+    if from.root != to.root {
+        return Err(DiffError::RootMismatch(from.id, to.id));
+    }
+    let (from, to) = if from.id <= to.id { (from, to) } else { (to, from) };
+
+    let changes = storage::load_changes(config_dir, &root, from.id, to.id)?;
+    let deltas = net_changes(changes);
+
+    let mut total = DiffGroup { key: root.to_string_lossy().to_string(), ..Default::default() };
+    let mut directories: HashMap<PathBuf, DiffGroup> = HashMap::new();
+    let mut categories: HashMap<String, DiffGroup> = HashMap::new();
+    for delta in &deltas {
+        total.add(delta);
+        let directory = group_directory(&root, &delta.path, query.depth);
+        directories.entry(directory.clone())
+            .or_insert_with(|| DiffGroup { key: directory.to_string_lossy().to_string(), ..Default::default() })
+            .add(delta);
+        categories.entry(delta.category.clone())
+            .or_insert_with(|| DiffGroup { key: delta.category.clone(), ..Default::default() })
+            .add(delta);
+    }
+
+    let keep = |bytes_delta: i64| {
+        bytes_delta.unsigned_abs() >= query.min_change
+            && match query.direction {
+                Direction::Any => bytes_delta != 0,
+                Direction::Grew => bytes_delta > 0,
+                Direction::Shrank => bytes_delta < 0,
+            }
+    };
+    let select_groups = |groups: Vec<DiffGroup>| {
+        let mut groups: Vec<DiffGroup> = groups.into_iter().filter(|group| keep(group.bytes_delta)).collect();
+        groups.sort_by(|a, b| b.bytes_delta.unsigned_abs().cmp(&a.bytes_delta.unsigned_abs()).then_with(|| a.key.cmp(&b.key)));
+        groups.truncate(query.limit);
+        groups
+    };
+    let mut files: Vec<FileDelta> = deltas.into_iter().filter(|delta| keep(delta.bytes_delta)).collect();
+    files.sort_by(|a, b| b.bytes_delta.unsigned_abs().cmp(&a.bytes_delta.unsigned_abs()).then_with(|| a.path.cmp(&b.path)));
+    files.truncate(query.limit);
+
+    Ok(SnapshotDiff {
+        from,
+        to,
+        total,
+        by_directory: select_groups(directories.into_values().collect()),
+        by_category: select_groups(categories.into_values().collect()),
+        files,
+    })
+}
+
+/// Collapses the change log to one net change per file: the size before the
+/// first logged change against the size after the last one.
+fn net_changes(changes: Vec<storage::ChangeRecord>) -> Vec<FileDelta> {
+    let mut order = Vec::new();
+    let mut net: HashMap<PathBuf, (Option<u64>, Option<u64>, String)> = HashMap::new();
+    for change in changes {
+        match net.get_mut(&change.path) {
+            Some(entry) => {
+                entry.1 = change.new_size;
+                entry.2 = change.category;
+            },
+            None => {
+                order.push(change.path.clone());
+                net.insert(change.path, (change.old_size, change.new_size, change.category));
+            },
+        }
+    }
+
+    order.into_iter()
+        .filter_map(|path| {
+            let (old_size, new_size, category) = net.remove(&path)?;
+            let kind = ChangeKind::between(old_size, new_size)?;
+            Some(FileDelta {
+                bytes_delta: new_size.unwrap_or(0) as i64 - old_size.unwrap_or(0) as i64,
+                path,
+                category,
+                kind,
+                old_size,
+                new_size,
+            })
+        })
+        .collect()
+}
+
+/// The ancestor of `path` at most `depth` components below `root`.
+fn group_directory(root: &Path, path: &Path, depth: usize) -> PathBuf {
+    let parent = path.parent().unwrap_or(root);
+    match parent.strip_prefix(root) {
+        Ok(relative) => root.join(relative.components().take(depth).collect::<PathBuf>()),
+        Err(_) => parent.to_path_buf(),
+    }
+}
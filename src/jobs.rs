@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use serde_derive::{Serialize, Deserialize};
+use crate::config::Config;
+use crate::events::{self, Event};
+use crate::persist;
+use crate::scanner;
+use crate::scoring;
+
+/// Finished scans kept in `data/scan_history.json`.
+const HISTORY_LIMIT: usize = 100;
+/// Error messages kept per job; later errors are only counted.
+const ERROR_LIMIT: usize = 200;
+/// Minimum gap between progress events for a running job.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Id of jobs that are not registered and never reported.
+const UNTRACKED_ID: u64 = 0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Queued,
+    Running,
+    Paused,
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+impl JobState {
+    pub fn is_finished(self) -> bool {
+        matches!(self, JobState::Completed | JobState::Cancelled | JobState::Failed)
+    }
+}
+
+/// Point-in-time view of a job, as served by `/scans`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSnapshot {
+    pub id: u64,
+    pub roots: Vec<PathBuf>,
+    pub state: JobState,
+    pub files_seen: u64,
+    pub bytes_seen: u64,
+    /// Rough size of the scan, from the previous index or the drives' used space.
+    pub expected_bytes: u64,
+    pub current_dir: Option<PathBuf>,
+    pub eta_seconds: Option<u64>,
+    pub files_analyzed: u64,
+    pub files_unchanged: u64,
+    pub files_deleted: u64,
+    pub error_count: u64,
+    pub errors: Vec<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+pub enum JobError {
+    NotFound(u64),
+    AlreadyFinished(u64),
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::NotFound(id) => write!(f, "scan {} not found", id),
+            JobError::AlreadyFinished(id) => write!(f, "scan {} has already finished", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobAction {
+    Pause,
+    Resume,
+    Cancel,
+}
+
+/// Progress and control handle for one scan, shared with every scan worker.
+pub struct ScanJob {
+    pub id: u64,
+    roots: Vec<PathBuf>,
+    state: Mutex<JobState>,
+    /// Signalled when the job is resumed, cancelled or finishes.
+    changed: Condvar,
+    paused: AtomicBool,
+    cancelled: AtomicBool,
+    files_seen: AtomicU64,
+    bytes_seen: AtomicU64,
+    expected_bytes: AtomicU64,
+    files_analyzed: AtomicU64,
+    files_unchanged: AtomicU64,
+    files_deleted: AtomicU64,
+    current_dir: Mutex<Option<PathBuf>>,
+    error_count: AtomicU64,
+    errors: Mutex<Vec<String>>,
+    started_at: DateTime<Utc>,
+    finished_at: Mutex<Option<DateTime<Utc>>>,
+    last_published: Mutex<Option<Instant>>,
+}
+
+impl ScanJob {
+    fn new(id: u64, roots: Vec<PathBuf>) -> Self {
+        ScanJob {
+            id,
+            roots,
+            state: Mutex::new(JobState::Queued),
+            changed: Condvar::new(),
+            paused: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            files_seen: AtomicU64::new(0),
+            bytes_seen: AtomicU64::new(0),
+            expected_bytes: AtomicU64::new(0),
+            files_analyzed: AtomicU64::new(0),
+            files_unchanged: AtomicU64::new(0),
+            files_deleted: AtomicU64::new(0),
+            current_dir: Mutex::new(None),
+            error_count: AtomicU64::new(0),
+            errors: Mutex::new(Vec::new()),
+            started_at: Utc::now(),
+            finished_at: Mutex::new(None),
+            last_published: Mutex::new(None),
+        }
+    }
+
+    /// A job that is not registered anywhere, for internal scans such as the
+    /// watcher indexing a newly created directory.
+    pub fn untracked(roots: Vec<PathBuf>) -> Self {
+        let job = ScanJob::new(UNTRACKED_ID, roots);
+        *job.state.lock().unwrap() = JobState::Running;
+        job
+    }
+
+    pub fn roots(&self) -> &[PathBuf] {
+        &self.roots
+    }
+
+    pub fn set_expected_bytes(&self, bytes: u64) {
+        self.expected_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn enter_dir(&self, dir: &Path) {
+        *self.current_dir.lock().unwrap() = Some(dir.to_path_buf());
+        
+        let mut last_published = self.last_published.lock().unwrap();
+        if last_published.is_some_and(|at| at.elapsed() < PROGRESS_INTERVAL) {
+            return;
+        }
+        *last_published = Some(Instant::now());
+        drop(last_published);
+        self.publish();
+    }
+
+    pub fn record_file(&self, bytes: u64) {
+        self.files_seen.fetch_add(1, Ordering::Relaxed);
+        self.bytes_seen.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_outcome(&self, analyzed: usize, unchanged: usize, deleted: usize) {
+        self.files_analyzed.fetch_add(analyzed as u64, Ordering::Relaxed);
+        self.files_unchanged.fetch_add(unchanged as u64, Ordering::Relaxed);
+        self.files_deleted.fetch_add(deleted as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self, message: String) {
+        self.error_count.fetch_add(1, Ordering::Relaxed);
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() < ERROR_LIMIT {
+            errors.push(message);
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Called by workers between directories: blocks while the job is paused
+    /// and returns `false` once it has been cancelled.
+    pub fn checkpoint(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        while self.paused.load(Ordering::Relaxed) && !self.is_cancelled() {
+            state = self.changed.wait(state).unwrap();
+        }
+        !self.is_cancelled()
+    }
+
+    fn control(&self, action: JobAction) -> Result<(), JobError> {
+        let state = self.state.lock().unwrap();
+        if state.is_finished() {
+            return Err(JobError::AlreadyFinished(self.id));
+        }
+        match action {
+            JobAction::Pause => self.paused.store(true, Ordering::Relaxed),
+            JobAction::Resume => self.paused.store(false, Ordering::Relaxed),
+            JobAction::Cancel => self.cancelled.store(true, Ordering::Relaxed),
+        }
+        self.changed.notify_all();
+        drop(state);
+        self.publish();
+        Ok(())
+    }
+
+    /// Moves a queued job to running unless it has been paused meanwhile.
+    /// Checked under the state lock, so a pause cannot slip in between.
+    fn start(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if self.paused.load(Ordering::Relaxed) {
+            return false;
+        }
+        *state = JobState::Running;
+        drop(state);
+        self.publish();
+        true
+    }
+
+    fn set_state(&self, new_state: JobState) {
+        let mut state = self.state.lock().unwrap();
+        *state = new_state;
+        if new_state.is_finished() {
+            *self.finished_at.lock().unwrap() = Some(Utc::now());
+            *self.current_dir.lock().unwrap() = None;
+        }
+        self.changed.notify_all();
+        drop(state);
+        self.publish();
+    }
+
+    fn publish(&self) {
+        if self.id != UNTRACKED_ID {
+            events::publish(Event::ScanProgress(self.snapshot()));
+        }
+    }
+
+    pub fn snapshot(&self) -> JobSnapshot {
+        let mut state = *self.state.lock().unwrap();
+        if !state.is_finished() && self.paused.load(Ordering::Relaxed) {
+            state = JobState::Paused;
+        }
+        let bytes_seen = self.bytes_seen.load(Ordering::Relaxed);
+        let expected_bytes = self.expected_bytes.load(Ordering::Relaxed);
+        let eta_seconds = if state == JobState::Running && bytes_seen > 0 && expected_bytes > bytes_seen {
+            let elapsed = (Utc::now() - self.started_at).num_seconds().max(1) as f64;
+            Some((elapsed * (expected_bytes - bytes_seen) as f64 / bytes_seen as f64) as u64)
+        } else {
+            None
+        };
+
+        JobSnapshot {
+            id: self.id,
+            roots: self.roots.clone(),
+            state,
+            files_seen: self.files_seen.load(Ordering::Relaxed),
+            bytes_seen,
+            expected_bytes,
+            current_dir: self.current_dir.lock().unwrap().clone(),
+            eta_seconds,
+            files_analyzed: self.files_analyzed.load(Ordering::Relaxed),
+            files_unchanged: self.files_unchanged.load(Ordering::Relaxed),
+            files_deleted: self.files_deleted.load(Ordering::Relaxed),
+            error_count: self.error_count.load(Ordering::Relaxed),
+            errors: self.errors.lock().unwrap().clone(),
+            started_at: self.started_at,
+            finished_at: *self.finished_at.lock().unwrap(),
+        }
+    }
+}
+
+/// Registry of running scans plus the history of finished ones.
+pub struct JobManager {
+    next_id: AtomicU64,
+    active: Mutex<BTreeMap<u64, Arc<ScanJob>>>,
+    history: Mutex<VecDeque<JobSnapshot>>,
+    /// Held by the running job; scans of overlapping roots would race on the index.
+    run_lock: Mutex<()>,
+    history_path: PathBuf,
+    /// Set while a rescoring thread runs; see [`JobManager::rescore`].
+    rescoring: AtomicBool,
+    /// Set by rescoring requests; the running thread scores again once done.
+    rescore_requested: AtomicBool,
+}
+
+impl JobManager {
+    pub fn new(config_dir: &Path) -> Arc<Self> {
+        let history_path = config_dir.join("data").join("scan_history.json");
+        let history: VecDeque<JobSnapshot> = persist::read_json(&history_path).unwrap_or_default();
+        let next_id = history.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+
+        Arc::new(JobManager {
+            next_id: AtomicU64::new(next_id),
+            active: Mutex::new(BTreeMap::new()),
+            history: Mutex::new(history),
+            run_lock: Mutex::new(()),
+            history_path,
+            rescoring: AtomicBool::new(false),
+            rescore_requested: AtomicBool::new(false),
+        })
+    }
+
+    fn register(&self, roots: Vec<PathBuf>) -> Arc<ScanJob> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let job = Arc::new(ScanJob::new(id, roots));
+        self.active.lock().unwrap().insert(id, job.clone());
+        job.publish();
+        job
+    }
+
+    /// Queues a scan of `roots` on a background thread.
+    pub fn start(self: &Arc<Self>, roots: Vec<PathBuf>, config: Arc<Mutex<Config>>) -> JobSnapshot {
+        let job = self.register(roots);
+        let manager = self.clone();
+        let snapshot = job.snapshot();
+        thread::spawn(move || manager.execute(&job, config));
+        snapshot
+    }
+
+    /// Scans `roots` on the calling thread, registered like any other job.
+    pub fn run(&self, roots: Vec<PathBuf>, config: Arc<Mutex<Config>>) -> JobSnapshot {
+        let job = self.register(roots);
+        self.execute(&job, config)
+    }
+
+    fn execute(&self, job: &Arc<ScanJob>, config: Arc<Mutex<Config>>) -> JobSnapshot {
+        // A job paused while queued must not hold up the ones behind it, so
+        // the lock is only kept by a job that is not paused once it gets it
+        let running = loop {
+            if !job.checkpoint() {
+                break None;
+            }
+            let guard = self.run_lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if job.start() {
+                break Some(guard);
+            }
+        };
+
+        let final_state = if running.is_none() {
+            JobState::Cancelled
+        } else {
+            let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+                scanner::scan_drives(job.roots(), config, job)
+            }));
+            match outcome {
+                Ok(Ok(())) if job.is_cancelled() => JobState::Cancelled,
+                Ok(Ok(())) => JobState::Completed,
+                Ok(Err(e)) => {
+                    job.record_error(format!("Failed to save scan results: {}", e));
+                    JobState::Failed
+                },
+                Err(_) => {
+                    job.record_error("Scan aborted by an internal error".to_string());
+                    JobState::Failed
+                },
+            }
+        };
+        job.set_state(final_state);
+
+        let snapshot = job.snapshot();
+        self.active.lock().unwrap().remove(&job.id);
+        self.archive(snapshot.clone());
+        snapshot
+    }
+
+    fn archive(&self, snapshot: JobSnapshot) {
+        let mut history = self.history.lock().unwrap();
+        history.push_front(snapshot);
+        history.truncate(HISTORY_LIMIT);
+
+        if let Err(e) = persist::write_json(&self.history_path, &*history) {
+            eprintln!("Failed to save scan history: {}", e);
+        }
+    }
+
+    /// Scores the index again on a background thread, under the settings
+    /// current when it starts. Requests made while a run is under way are
+    /// folded into a single run after it.
+    pub fn rescore(self: &Arc<Self>, config: Arc<Mutex<Config>>) {
+        self.rescore_requested.store(true, Ordering::SeqCst);
+        if self.rescoring.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let manager = self.clone();
+        thread::spawn(move || loop {
+            while manager.rescore_requested.swap(false, Ordering::SeqCst) {
+                let settings = config.lock().unwrap().clone();
+                match scoring::update_index(&crate::get_config_dir(), &settings) {
+                    Ok(count) => println!("Updated the importance of {} files", count),
+                    Err(e) => eprintln!("Failed to update importance scores: {}", e),
+                }
+            }
+            manager.rescoring.store(false, Ordering::SeqCst);
+            // A request that came in after the last check but before the flag
+            // was cleared found the thread still running
+            if !manager.rescore_requested.load(Ordering::SeqCst) || manager.rescoring.swap(true, Ordering::SeqCst) {
+                break;
+            }
+        });
+    }
+
+    /// Running and queued jobs first, then finished ones, newest first.
+    pub fn list(&self) -> Vec<JobSnapshot> {
+        let mut jobs: Vec<JobSnapshot> = self.active.lock().unwrap()
+            .values()
+            .rev()
+            .map(|job| job.snapshot())
+            .collect();
+        jobs.extend(self.history.lock().unwrap().iter().cloned());
+        jobs
+    }
+
+    pub fn get(&self, id: u64) -> Option<JobSnapshot> {
+        if let Some(job) = self.active.lock().unwrap().get(&id) {
+            return Some(job.snapshot());
+        }
+        self.history.lock().unwrap().iter().find(|job| job.id == id).cloned()
+    }
+
+    pub fn control(&self, id: u64, action: JobAction) -> Result<JobSnapshot, JobError> {
+        let job = self.active.lock().unwrap().get(&id).cloned();
+        match job {
+            Some(job) => {
+                job.control(action)?;
+                Ok(job.snapshot())
+            },
+            None if self.get(id).is_some() => Err(JobError::AlreadyFinished(id)),
+            None => Err(JobError::NotFound(id)),
+        }
+    }
+}
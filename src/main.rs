@@ -1,21 +1,41 @@
 mod scanner;
 mod analyzer;
 mod storage;
+mod tree;
+mod treemap;
 mod ai_integration;
 mod api;
 mod config;
+mod document;
+mod duplicates;
+mod events;
+mod exclusion;
+mod extract;
+mod history;
+mod jobs;
+mod media;
+mod mounts;
+mod persist;
+mod runtime;
+mod scoring;
+mod sniff;
+mod taxonomy;
+#[cfg(test)]
+mod test_support;
+mod watcher;
 
 use std::sync::{Arc, Mutex};
 use std::env;
 use std::fs;
+use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::process::Command;
-use std::time::Duration;
 use std::net::TcpListener;
 
-use serde_json;
-use crate::config::Config;
-use dirs;
+use crate::config::{Config, CONFIG_FILE_NAME};
+use crate::runtime::{DaemonStatus, RuntimeState};
+
+const DEFAULT_PORT: u16 = 8080;
 
 fn ensure_installed_in_home() {
     let home = dirs::home_dir().expect("Could not find home directory");
@@ -28,12 +48,13 @@ fn ensure_installed_in_home() {
     if !target_path.exists() {
         let current_exe = env::current_exe().expect("Failed to get current executable");
         println!("Copying {} to {}", current_exe.display(), target_path.display());
-        fs::copy(&current_exe, &target_path)
+        fs::read(&current_exe)
+            .and_then(|binary| persist::write_atomic(&target_path, &binary))
             .expect("Failed to copy executable to home directory");
         #[cfg(unix)]
         {
             Command::new("chmod")
-                .args(&["+x", target_path.to_str().unwrap()])
+                .args(["+x", target_path.to_str().unwrap()])
                 .status()
                 .expect("Failed to set executable permissions");
         }
@@ -41,33 +62,7 @@ fn ensure_installed_in_home() {
 }
 
 fn find_available_port() -> u16 {
-    (10000..60000).find(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok()).unwrap_or(8080)
-}
-
-fn write_config_json(port: u16, pid: u32) {
-    let home = dirs::home_dir().expect("Could not find home directory");
-    let config_dir = home.join(".drivedriverb");
-    let config_path = config_dir.join("config.json");
-    let config = serde_json::json!({
-        "port": port,
-        "pid": pid,
-        "status": "running"
-    });
-    let _ = fs::create_dir_all(&config_dir);
-    let _ = fs::write(&config_path, serde_json::to_string_pretty(&config).unwrap());
-}
-
-fn read_config_json() -> Option<(u16, u32)> {
-    let home = dirs::home_dir().expect("Could not find home directory");
-    let config_path = home.join(".drivedriverb").join("config.json");
-    if let Ok(content) = fs::read_to_string(&config_path) {
-        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&content) {
-            let port = json.get("port").and_then(|v| v.as_u64()).unwrap_or(8080) as u16;
-            let pid = json.get("pid").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-            return Some((port, pid));
-        }
-    }
-    None
+    (10000..60000).find(|port| TcpListener::bind(("127.0.0.1", *port)).is_ok()).unwrap_or(DEFAULT_PORT)
 }
 
 fn main() {
@@ -83,24 +78,22 @@ fn main() {
     }
 
     // Parse port from arguments if provided
-    let mut port = 8080; // Default port
+    let mut port = None;
     let mut i = 1;
     while i < args.len() {
-        if args[i] == "--port" || args[i] == "-p" {
-            if i + 1 < args.len() {
-                if let Ok(p) = args[i + 1].parse::<u16>() {
-                    port = p;
-                    println!("Using port: {}", port);
-                }
-                i += 2;
-                continue;
+        if (args[i] == "--port" || args[i] == "-p") && i + 1 < args.len() {
+            if let Ok(p) = args[i + 1].parse::<u16>() {
+                port = Some(p);
+                println!("Using port: {}", p);
             }
+            i += 2;
+            continue;
         }
         i += 1;
     }
     
     match args[1].as_str() {
-        "start" => run_backend(port, false),
+        "start" => run_backend(port.unwrap_or(DEFAULT_PORT), false),
         "stop" => stop_backend(),
         "verbose" => verbose_mode(port),
         "help" => usage(),
@@ -136,7 +129,12 @@ fn usage() {
     println!("  drivedriverb stop");
 }
 
-fn verbose_mode(port: u16) {
+fn verbose_mode(port: Option<u16>) {
+    // Without an explicit port, look for the daemon where it said it was running
+    let port = port
+        .or_else(|| RuntimeState::load(&get_config_dir()).filter(|state| state.is_running()).map(|state| state.port))
+        .unwrap_or(DEFAULT_PORT);
+
     // First check if server is already running
     if is_server_running(port) {
         println!("Backend is already running on port {}.", port);
@@ -153,7 +151,7 @@ fn verbose_mode(port: u16) {
 fn is_server_running(port: u16) -> bool {
     // Try to connect to health endpoint
     if let Ok(output) = Command::new("curl")
-        .args(&["-s", &format!("http://localhost:{}/health", port)])
+        .args(["-s", &format!("http://localhost:{}/health", port)])
         .output() {
         return output.status.success() && !output.stdout.is_empty();
     }
@@ -163,30 +161,58 @@ fn is_server_running(port: u16) -> bool {
 fn display_server_status(port: u16) {
     println!("Starting status monitoring. Press Ctrl+C to exit.");
     
-    // Continuously poll and display server status
-    loop {
-        if let Ok(output) = Command::new("curl")
-            .args(&["-s", &format!("http://localhost:{}/status", port)])
-            .output() {
-            if output.status.success() {
-                if let Ok(status_str) = String::from_utf8(output.stdout) {
-                    // Clear screen and print new status
-                    println!("\x1B[2J\x1B[1;1H"); // ANSI clear screen
-                    println!("DriveDriver Backend Status (Ctrl+C to exit):");
-                    println!("----------------------------------------");
-                    println!("{}", status_str);
-                }
-            } else {
-                println!("Lost connection to server. Exiting.");
-                break;
-            }
-        } else {
+    // The event stream stays open indefinitely, so no request timeout
+    let client = match reqwest::blocking::Client::builder().timeout(None).build() {
+        Ok(client) => client,
+        Err(e) => {
+            println!("Failed to create HTTP client: {}. Exiting.", e);
+            return;
+        }
+    };
+    
+    if let Ok(status) = client.get(format!("http://localhost:{}/status", port)).send().and_then(|r| r.text()) {
+        println!("DriveDriver Backend Status (Ctrl+C to exit):");
+        println!("----------------------------------------");
+        println!("{}", status);
+    }
+    
+    // Subscribe to pushed events instead of polling
+    let response = match client.get(format!("http://localhost:{}/events", port)).send() {
+        Ok(response) if response.status().is_success() => response,
+        _ => {
             println!("Failed to connect to server. Exiting.");
+            return;
+        }
+    };
+    for line in BufReader::new(response).lines() {
+        let Ok(line) = line else {
             break;
+        };
+        if let Some(data) = line.strip_prefix("data: ") {
+            if let Ok(event) = serde_json::from_str::<serde_json::Value>(data) {
+                print_event(&event);
+            }
         }
-        
-        // Wait before next status update
-        std::thread::sleep(Duration::from_secs(2));
+    }
+    println!("Lost connection to server. Exiting.");
+}
+
+fn print_event(event: &serde_json::Value) {
+    let text = |key: &str| event[key].as_str().unwrap_or_default().to_string();
+    match event["type"].as_str() {
+        Some("scan_progress") => println!(
+            "[scan {}] {} - {} files, {} bytes{}",
+            event["id"],
+            text("state"),
+            event["files_seen"],
+            event["bytes_seen"],
+            event["current_dir"].as_str().map(|dir| format!(" - {}", dir)).unwrap_or_default()
+        ),
+        Some("file_changed") => println!("[file] {} {}", text("change"), text("path")),
+        Some("ai_analysis_completed") => println!("[ai] analyzed {}", text("path")),
+        Some("config_changed") => println!("[config] configuration updated"),
+        Some("lagged") => println!("[events] skipped {} events", event["skipped"]),
+        _ => {},
     }
 }
 
@@ -196,9 +222,20 @@ fn run_backend(port: u16, verbose: bool) {
     }
     
     // Initialize configuration
-    let config_path = get_config_dir().join("config.json");
-    let config = Config::load_or_create(&config_path);
+    let config_dir = get_config_dir();
+    if let Err(e) = runtime::migrate_legacy_config(&config_dir) {
+        eprintln!("Failed to migrate legacy runtime state: {}", e);
+    }
+    let config_path = config_dir.join(CONFIG_FILE_NAME);
+    let config = match Config::load_or_create(&config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", config_path.display(), e);
+            std::process::exit(1);
+        }
+    };
     let config = Arc::new(Mutex::new(config));
+    let jobs = jobs::JobManager::new(&config_dir);
     
     let port = if port == 0 {
         find_available_port()
@@ -206,78 +243,71 @@ fn run_backend(port: u16, verbose: bool) {
         port
     };
 
-    // Write PID file so that the stop command can locate this process
-    let pid = std::process::id();
-    write_config_json(port, pid);
+    // Record the PID so that the stop command can locate this process
+    let mut state = RuntimeState {
+        port,
+        pid: std::process::id(),
+        status: DaemonStatus::Running,
+    };
+    if let Err(e) = state.save(&config_dir) {
+        eprintln!("Failed to write runtime state: {}", e);
+    }
     
-    // Start initial scan in a separate thread
+    // Start initial scan in a separate thread, then keep the index current
     let scan_config = config.clone();
+    let scan_jobs = jobs.clone();
     let scan_handle = std::thread::spawn(move || {
-        scanner::start_initial_scan(scan_config);
+        scanner::start_initial_scan(scan_config.clone(), &scan_jobs);
+        let drives = scanner::get_all_drives(&scan_config.lock().unwrap());
+        watcher::watch_drives(scan_config, drives, scan_jobs);
     });
     
     // Start API server in the main thread
     let api_config = config.clone();
-    api::start_server(api_config, port, verbose);
+    api::start_server(api_config, jobs, port, verbose);
     
     // Wait for scan to complete
     if let Err(e) = scan_handle.join() {
         eprintln!("Error joining scan thread: {:?}", e);
     }
     
-    // Clean up PID file on exit
-    let _ = fs::remove_file(get_config_dir().join("drivedriver.pid"));
+    state.status = DaemonStatus::Stopped;
+    let _ = state.save(&config_dir);
 }
 
 fn stop_backend() {
-    let pid_path = get_config_dir().join("drivedriver.pid");
-    if !pid_path.exists() {
-        println!("No running backend found.");
-        return;
-    }
-    
-    let content = match fs::read_to_string(&pid_path) {
-        Ok(content) => content,
-        Err(_) => {
-            println!("Failed to read PID file.");
+    let config_dir = get_config_dir();
+    let mut state = match RuntimeState::load(&config_dir) {
+        Some(state) if state.is_running() => state,
+        _ => {
+            println!("No running backend found.");
             return;
         }
     };
-    
-    let parts: Vec<&str> = content.trim().split('\n').collect();
-    let pid_str = parts[0];
+    let pid_str = state.pid.to_string();
     
     #[cfg(unix)]
-    {
+    let status = {
         // On Unix, use the kill command.
-        let status = Command::new("kill")
-            .arg(pid_str)
+        Command::new("kill")
+            .arg(&pid_str)
             .status()
-            .expect("Failed to execute kill command");
-        if status.success() {
-            let _ = fs::remove_file(pid_path);
-        }
-    }
+            .expect("Failed to execute kill command")
+    };
     
     #[cfg(windows)]
-    {
+    let status = {
         // On Windows use taskkill.
-        let status = Command::new("taskkill")
-            .args(&["/PID", pid_str, "/F"])
+        Command::new("taskkill")
+            .args(["/PID", &pid_str, "/F"])
             .status()
-            .expect("Failed to execute taskkill command");
-        if status.success() {
-            let _ = fs::remove_file(pid_path);
-        }
-    }
+            .expect("Failed to execute taskkill command")
+    };
 
-    // Also update config.json status
-    let home = dirs::home_dir().expect("Could not find home directory");
-    let config_path = home.join(".drivedriverb").join("config.json");
-    if let Ok(config) = fs::read_to_string(&config_path) {
-        if let Ok(mut json) = serde_json::from_str::<serde_json::Value>(&config) {
-            json["status"] = serde_json::json!("stopped");
-            let _ = fs::write(&config_path, serde_json::to_string_pretty(&json).unwrap());
+    if status.success() {
+        state.status = DaemonStatus::Stopped;
+        if let Err(e) = state.save(&config_dir) {
+            eprintln!("Failed to update runtime state: {}", e);
         }
     }
 }
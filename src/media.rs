@@ -0,0 +1,900 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use serde_derive::{Serialize, Deserialize};
+
+/// Largest header or tag block read into memory. Bigger ones, such as tags
+/// carrying large cover art, are skipped.
+const MAX_BLOCK_BYTES: u64 = 16 * 1024 * 1024;
+/// Bytes searched for the first MPEG audio frame after the ID3 tag.
+const MPEG_SEARCH_BYTES: u64 = 64 * 1024;
+/// Bytes read from either end of an Ogg file: the start for the stream
+/// headers, the end for the last page's sample position.
+const OGG_HEAD_BYTES: u64 = 1024 * 1024;
+const OGG_TAIL_BYTES: u64 = 64 * 1024;
+/// Top-level Matroska elements visited before giving up on finding tags.
+const MAX_MATROSKA_ELEMENTS: usize = 100_000;
+
+// Matroska element IDs
+const EBML_HEADER: u32 = 0x1A45DFA3;
+const SEGMENT: u32 = 0x18538067;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const DURATION: u32 = 0x4489;
+const TITLE: u32 = 0x7BA9;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const TAGS: u32 = 0x1254C367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63C0;
+const TARGET_TYPE_VALUE: u32 = 0x68CA;
+const SIMPLE_TAG: u32 = 0x67C8;
+const TAG_NAME: u32 = 0x45A3;
+const TAG_STRING: u32 = 0x4487;
+
+/// What the container headers and tags of a recording tell about it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaMetadata {
+    pub duration_seconds: Option<f64>,
+    /// Average bits per second, declared by the stream or worked out from
+    /// the file size and duration.
+    pub bitrate: Option<u32>,
+    /// Short codec names such as `h264`, `aac` or `flac`.
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+impl MediaMetadata {
+    pub fn has_video(&self) -> bool {
+        self.video_codec.is_some() || self.width.is_some()
+    }
+}
+
+/// Reads the headers and tags of MP3, FLAC, Ogg, MP4/QuickTime and
+/// Matroska/WebM files. `None` for other formats and unreadable files.
+pub fn read(path: &Path, mime_type: &str) -> Option<MediaMetadata> {
+    let mut file = File::open(path).ok()?;
+    let file_size = file.metadata().ok()?.len();
+    let mut metadata = match mime_type {
+        "audio/mpeg" => mpeg_audio(&mut file, file_size),
+        "audio/flac" => flac(&mut file, file_size),
+        "audio/ogg" => ogg(&mut file, file_size),
+        "audio/mp4" | "video/mp4" | "video/quicktime" | "video/3gpp" => iso_media(&mut file, file_size),
+        "video/x-matroska" | "video/webm" => matroska(&mut file, file_size),
+        _ => None,
+    }?;
+    if metadata.bitrate.is_none() {
+        metadata.bitrate = metadata.duration_seconds
+            .filter(|duration| *duration > 0.0)
+            .map(|duration| (file_size as f64 * 8.0 / duration) as u32);
+    }
+    (metadata != MediaMetadata::default()).then_some(metadata)
+}
+
+/// Up to `length` bytes from `offset`; fewer at the end of the file.
+fn read_at(file: &mut File, offset: u64, length: u64) -> Option<Vec<u8>> {
+    if length > MAX_BLOCK_BYTES {
+        return None;
+    }
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut buffer = Vec::with_capacity(length as usize);
+    file.by_ref().take(length).read_to_end(&mut buffer).ok()?;
+    Some(buffer)
+}
+
+fn be_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn le_u16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+/// A tag value without padding; `None` when nothing is left.
+fn tag_text(text: &str) -> Option<String> {
+    let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// Sets `field` unless an earlier tag already did.
+fn fill(field: &mut Option<String>, value: Option<String>) {
+    if field.is_none() {
+        *field = value;
+    }
+}
+
+fn mpeg_audio(file: &mut File, file_size: u64) -> Option<MediaMetadata> {
+    let mut metadata = MediaMetadata::default();
+    let head = read_at(file, 0, 10)?;
+    let mut audio_start = 0;
+    if head.len() == 10 && head.starts_with(b"ID3") {
+        let tag_size = syncsafe(&head[6..10]) as u64;
+        let footer = if head[5] & 0x10 != 0 { 10 } else { 0 };
+        audio_start = 10 + tag_size + footer;
+        if let Some(tag) = read_at(file, 10, tag_size) {
+            id3v2(&tag, head[3], head[5], &mut metadata);
+        }
+    }
+    let mut audio_end = file_size;
+    if file_size >= audio_start + 128 {
+        if let Some(trailer) = read_at(file, file_size - 128, 128).filter(|trailer| trailer.starts_with(b"TAG")) {
+            audio_end -= 128;
+            id3v1(&trailer, &mut metadata);
+        }
+    }
+
+    let window = read_at(file, audio_start, MPEG_SEARCH_BYTES)?;
+    if let Some((offset, frame)) = first_mpeg_frame(&window) {
+        let audio_bytes = audio_end.saturating_sub(audio_start + offset as u64);
+        metadata.audio_codec = Some("mp3".to_string());
+        metadata.sample_rate = Some(frame.sample_rate);
+        metadata.channels = Some(if frame.mono { 1 } else { 2 });
+        match vbr_frame_count(&window[offset..], &frame) {
+            Some(frames) if frames > 0 => {
+                let duration = frames as f64 * frame.samples as f64 / frame.sample_rate as f64;
+                metadata.duration_seconds = Some(duration);
+                metadata.bitrate = Some((audio_bytes as f64 * 8.0 / duration) as u32);
+            },
+            // Constant bitrate: every frame has the first one's
+            _ => {
+                metadata.duration_seconds = Some(audio_bytes as f64 * 8.0 / frame.bitrate as f64);
+                metadata.bitrate = Some(frame.bitrate);
+            },
+        }
+    }
+    Some(metadata)
+}
+
+/// ID3v2 sizes use seven bits per byte.
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0, |size, byte| size << 7 | (*byte & 0x7f) as u32)
+}
+
+/// Reads title, artist and album frames of an ID3v2.2, 2.3 or 2.4 tag body.
+fn id3v2(tag: &[u8], major: u8, flags: u8, metadata: &mut MediaMetadata) -> Option<()> {
+    let mut at = 0;
+    if flags & 0x40 != 0 && major >= 3 {
+        // Extended header: its size excludes the size field in 2.3 only
+        at = if major == 3 { be_u32(tag, 0)? as usize + 4 } else { syncsafe(tag.get(0..4)?) as usize };
+    }
+    let (id_length, header_length) = if major == 2 { (3, 6) } else { (4, 10) };
+    while let Some(header) = tag.get(at..at + header_length) {
+        // Padding follows the last frame
+        if header[0] == 0 {
+            break;
+        }
+        let size = match major {
+            2 => u32::from_be_bytes([0, header[3], header[4], header[5]]),
+            3 => be_u32(header, 4)?,
+            _ => syncsafe(&header[4..8]),
+        } as usize;
+        let body = tag.get(at + header_length..at + header_length + size)?;
+        let field = match &header[..id_length] {
+            b"TIT2" | b"TT2" => Some(&mut metadata.title),
+            b"TPE1" | b"TP1" => Some(&mut metadata.artist),
+            b"TALB" | b"TAL" => Some(&mut metadata.album),
+            _ => None,
+        };
+        if let Some(field) = field {
+            fill(field, id3_text(body));
+        }
+        at += header_length + size;
+    }
+    Some(())
+}
+
+/// Decodes a text frame: an encoding byte, then Latin-1, UTF-16 or UTF-8.
+/// Of several values only the first is kept.
+fn id3_text(body: &[u8]) -> Option<String> {
+    let (&encoding, text) = body.split_first()?;
+    let decoded: String = match encoding {
+        0 => text.iter().map(|&byte| byte as char).collect(),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xfe, 0xff, rest @ ..] => (true, rest),
+                [0xff, 0xfe, rest @ ..] => (false, rest),
+                _ => (encoding == 2, text),
+            };
+            let units: Vec<u16> = text.chunks_exact(2)
+                .map(|pair| if big_endian { u16::from_be_bytes([pair[0], pair[1]]) } else { u16::from_le_bytes([pair[0], pair[1]]) })
+                .collect();
+            String::from_utf16_lossy(&units)
+        },
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    tag_text(decoded.split('\0').next()?)
+}
+
+/// The fixed-width Latin-1 fields of an ID3v1 trailer, used where the
+/// ID3v2 tag has none.
+fn id3v1(trailer: &[u8], metadata: &mut MediaMetadata) {
+    let field = |range: std::ops::Range<usize>| {
+        let text: String = trailer.get(range)?.iter().map(|&byte| byte as char).collect();
+        tag_text(text.split('\0').next()?)
+    };
+    fill(&mut metadata.title, field(3..33));
+    fill(&mut metadata.artist, field(33..63));
+    fill(&mut metadata.album, field(63..93));
+}
+
+/// An MPEG audio Layer III frame header.
+struct MpegFrame {
+    mpeg1: bool,
+    /// Bits per second.
+    bitrate: u32,
+    sample_rate: u32,
+    /// Samples per channel in each frame.
+    samples: u32,
+    mono: bool,
+    length: usize,
+}
+
+fn mpeg_frame(bytes: &[u8]) -> Option<MpegFrame> {
+    const BITRATES_MPEG1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+    const BITRATES_MPEG2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+    let header = bytes.get(0..4)?;
+    if header[0] != 0xff || header[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+    // Version 3 is MPEG-1, 2 is MPEG-2, 0 is MPEG-2.5; layer 1 is Layer III
+    let version = (header[1] >> 3) & 3;
+    let layer = (header[1] >> 1) & 3;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let rate_index = ((header[2] >> 2) & 3) as usize;
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || rate_index == 3 {
+        return None;
+    }
+    let mpeg1 = version == 3;
+    let bitrate = if mpeg1 { BITRATES_MPEG1[bitrate_index] } else { BITRATES_MPEG2[bitrate_index] } * 1000;
+    let sample_rate = [44100, 48000, 32000][rate_index] >> match version { 3 => 0, 2 => 1, _ => 2 };
+    let samples = if mpeg1 { 1152 } else { 576 };
+    let padding = ((header[2] >> 1) & 1) as u32;
+    Some(MpegFrame {
+        mpeg1,
+        bitrate,
+        sample_rate,
+        samples,
+        mono: header[3] >> 6 == 3,
+        length: (samples / 8 * bitrate / sample_rate + padding) as usize,
+    })
+}
+
+/// The first frame header that is followed by another one, which rules out
+/// stray sync bytes; a frame running past the window is taken as is.
+fn first_mpeg_frame(window: &[u8]) -> Option<(usize, MpegFrame)> {
+    (0..window.len().saturating_sub(4)).find_map(|offset| {
+        let frame = mpeg_frame(&window[offset..])?;
+        match window.get(offset + frame.length..) {
+            Some(next) if next.len() >= 4 => mpeg_frame(next).map(|_| (offset, frame)),
+            _ => Some((offset, frame)),
+        }
+    })
+}
+
+/// Number of frames declared by a Xing, Info or VBRI header in the first
+/// frame, which variable bitrate encoders write.
+fn vbr_frame_count(first: &[u8], frame: &MpegFrame) -> Option<u32> {
+    let side_info = match (frame.mpeg1, frame.mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    let xing = 4 + side_info;
+    match first.get(xing..xing + 4) {
+        Some(b"Xing") | Some(b"Info") => {
+            let flags = be_u32(first, xing + 4)?;
+            if flags & 1 == 0 {
+                return None;
+            }
+            be_u32(first, xing + 8)
+        },
+        _ if first.get(36..40) == Some(b"VBRI") => be_u32(first, 36 + 14),
+        _ => None,
+    }
+}
+
+fn flac(file: &mut File, file_size: u64) -> Option<MediaMetadata> {
+    if read_at(file, 0, 4)? != b"fLaC" {
+        return None;
+    }
+    let mut metadata = MediaMetadata { audio_codec: Some("flac".to_string()), ..Default::default() };
+    let mut at = 4;
+    loop {
+        let header = read_at(file, at, 4)?;
+        let length = u32::from_be_bytes([0, *header.get(1)?, *header.get(2)?, *header.get(3)?]) as u64;
+        match header[0] & 0x7f {
+            0 => {
+                let info = read_at(file, at + 4, length)?;
+                flac_stream_info(&info, &mut metadata);
+            },
+            4 => {
+                if let Some(block) = read_at(file, at + 4, length) {
+                    vorbis_comments(&block, &mut metadata);
+                }
+            },
+            _ => {},
+        }
+        at += 4 + length;
+        if header[0] & 0x80 != 0 || at >= file_size {
+            break;
+        }
+    }
+    if let Some(duration) = metadata.duration_seconds {
+        metadata.bitrate = Some((file_size.saturating_sub(at) as f64 * 8.0 / duration) as u32);
+    }
+    Some(metadata)
+}
+
+fn flac_stream_info(info: &[u8], metadata: &mut MediaMetadata) -> Option<()> {
+    // 20 bits sample rate, 3 bits channels - 1, 5 bits bits per sample - 1,
+    // 36 bits total samples
+    let packed = be_u64(info, 10)?;
+    let sample_rate = (packed >> 44) as u32;
+    let total_samples = packed & 0xf_ffff_ffff;
+    metadata.channels = Some(((packed >> 41) & 7) as u8 + 1);
+    if sample_rate > 0 {
+        metadata.sample_rate = Some(sample_rate);
+        if total_samples > 0 {
+            metadata.duration_seconds = Some(total_samples as f64 / sample_rate as f64);
+        }
+    }
+    Some(())
+}
+
+/// Reads a Vorbis comment block as used by FLAC, Vorbis and Opus:
+/// little-endian length-prefixed vendor string and `KEY=value` pairs.
+fn vorbis_comments(data: &[u8], metadata: &mut MediaMetadata) -> Option<()> {
+    let vendor_length = le_u32(data, 0)? as usize;
+    let mut at = 4 + vendor_length;
+    let count = le_u32(data, at)?;
+    at += 4;
+    for _ in 0..count {
+        let length = le_u32(data, at)? as usize;
+        let comment = String::from_utf8_lossy(data.get(at + 4..at + 4 + length)?);
+        at += 4 + length;
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        let field = match key.to_ascii_uppercase().as_str() {
+            "TITLE" => &mut metadata.title,
+            "ARTIST" => &mut metadata.artist,
+            "ALBUM" => &mut metadata.album,
+            _ => continue,
+        };
+        fill(field, tag_text(value));
+    }
+    Some(())
+}
+
+fn ogg(file: &mut File, file_size: u64) -> Option<MediaMetadata> {
+    let head = read_at(file, 0, OGG_HEAD_BYTES)?;
+    let (serial, packets) = ogg_packets(&head, 2)?;
+    let identification = packets.first()?;
+    let comments = packets.get(1);
+    let mut metadata = MediaMetadata::default();
+
+    // Sample positions count at this rate, after this many priming samples
+    let (granule_rate, pre_skip) = if identification.starts_with(b"\x01vorbis") {
+        let sample_rate = le_u32(identification, 12)?;
+        metadata.audio_codec = Some("vorbis".to_string());
+        metadata.channels = identification.get(11).copied();
+        metadata.sample_rate = Some(sample_rate);
+        // Nominal bitrate; zero or negative when the encoder did not set it
+        metadata.bitrate = le_u32(identification, 20).filter(|bitrate| (1..=i32::MAX as u32).contains(bitrate));
+        if let Some(comments) = comments.and_then(|packet| packet.strip_prefix(b"\x03vorbis")) {
+            vorbis_comments(comments, &mut metadata);
+        }
+        (sample_rate, 0)
+    } else if identification.starts_with(b"OpusHead") {
+        metadata.audio_codec = Some("opus".to_string());
+        metadata.channels = identification.get(9).copied();
+        metadata.sample_rate = le_u32(identification, 12).filter(|rate| *rate > 0);
+        if let Some(comments) = comments.and_then(|packet| packet.strip_prefix(b"OpusTags")) {
+            vorbis_comments(comments, &mut metadata);
+        }
+        (48_000, le_u16(identification, 10)?)
+    } else {
+        return None;
+    };
+
+    // The last page of the stream carries the position of its last sample
+    let tail_start = file_size.saturating_sub(OGG_TAIL_BYTES);
+    let tail = read_at(file, tail_start, file_size - tail_start)?;
+    let last_position = tail.windows(4)
+        .enumerate()
+        .rev()
+        .filter(|(_, window)| *window == b"OggS")
+        .find_map(|(at, _)| {
+            let page = tail.get(at..at + 27)?;
+            if le_u32(page, 14)? != serial {
+                return None;
+            }
+            le_u64(page, 6).filter(|position| *position != u64::MAX)
+        });
+    if let Some(position) = last_position.filter(|_| granule_rate > 0) {
+        let samples = position.saturating_sub(pre_skip.into());
+        metadata.duration_seconds = Some(samples as f64 / granule_rate as f64).filter(|duration| *duration > 0.0);
+    }
+    Some(metadata)
+}
+
+/// The first `wanted` packets of the first logical stream in `data`, with
+/// that stream's serial number. Packets cut off by the end of `data` are
+/// left out.
+fn ogg_packets(data: &[u8], wanted: usize) -> Option<(u32, Vec<Vec<u8>>)> {
+    let mut serial = None;
+    let mut packets = Vec::new();
+    let mut packet = Vec::new();
+    let mut at = 0;
+    while packets.len() < wanted {
+        let Some(header) = data.get(at..at + 27).filter(|header| header.starts_with(b"OggS")) else {
+            break;
+        };
+        let page_serial = le_u32(header, 14)?;
+        let segment_count = header[26] as usize;
+        let Some(lacing) = data.get(at + 27..at + 27 + segment_count) else {
+            break;
+        };
+        let mut body_at = at + 27 + segment_count;
+        let page_end = body_at + lacing.iter().map(|length| *length as usize).sum::<usize>();
+        if page_end > data.len() {
+            break;
+        }
+        if *serial.get_or_insert(page_serial) == page_serial {
+            // A packet ends with the first segment shorter than 255 bytes
+            for &length in lacing {
+                packet.extend_from_slice(&data[body_at..body_at + length as usize]);
+                body_at += length as usize;
+                if length < 255 {
+                    packets.push(std::mem::take(&mut packet));
+                }
+            }
+        }
+        at = page_end;
+    }
+    Some((serial?, packets))
+}
+
+fn iso_media(file: &mut File, file_size: u64) -> Option<MediaMetadata> {
+    // Find the movie box among the top-level boxes; it may follow the media data
+    let mut at = 0;
+    let movie = loop {
+        let header = read_at(file, at, 16)?;
+        let (size, header_length) = match be_u32(&header, 0)? {
+            0 => (file_size - at, 8),
+            1 => (be_u64(&header, 8)?, 16),
+            size => (size as u64, 8),
+        };
+        if size < header_length {
+            return None;
+        }
+        if header.get(4..8)? == b"moov" {
+            break read_at(file, at + header_length, size - header_length)?;
+        }
+        // A box running past the end, or far enough to wrap, ends the search
+        at = at.checked_add(size).filter(|next| *next < file_size)?;
+    };
+
+    let mut metadata = MediaMetadata::default();
+    if let Some(header) = child_box(&movie, b"mvhd") {
+        let (timescale, duration) = if header.first() == Some(&1) {
+            (be_u32(header, 20)?, be_u64(header, 24)?)
+        } else {
+            (be_u32(header, 12)?, be_u32(header, 16)?.into())
+        };
+        if timescale > 0 && duration > 0 {
+            metadata.duration_seconds = Some(duration as f64 / timescale as f64);
+        }
+    }
+
+    for (_, track) in boxes(&movie).filter(|(kind, _)| *kind == b"trak") {
+        let Some(media) = child_box(track, b"mdia") else {
+            continue;
+        };
+        let handler = child_box(media, b"hdlr").and_then(|handler| handler.get(8..12));
+        // First sample description, starting with its size and format
+        let entry = child_box(media, b"minf")
+            .and_then(|info| child_box(info, b"stbl"))
+            .and_then(|table| child_box(table, b"stsd"))
+            .and_then(|descriptions| descriptions.get(8..))
+            .unwrap_or_default();
+        let codec = entry.get(4..8).map(iso_codec);
+        match handler {
+            Some(b"vide") if metadata.video_codec.is_none() => {
+                metadata.video_codec = codec;
+                // Display size from the track header, coded size otherwise
+                let display = child_box(track, b"tkhd").and_then(|header| {
+                    let width = be_u32(header, header.len().checked_sub(8)?)? >> 16;
+                    let height = be_u32(header, header.len() - 4)? >> 16;
+                    Some((width, height)).filter(|(width, height)| *width > 0 && *height > 0)
+                });
+                let coded = be_u16(entry, 32).zip(be_u16(entry, 34)).map(|(width, height)| (width.into(), height.into()));
+                if let Some((width, height)) = display.or(coded) {
+                    metadata.width = Some(width);
+                    metadata.height = Some(height);
+                }
+            },
+            Some(b"soun") if metadata.audio_codec.is_none() => {
+                metadata.audio_codec = codec;
+                metadata.channels = be_u16(entry, 24).and_then(|channels| u8::try_from(channels).ok()).filter(|channels| *channels > 0);
+                metadata.sample_rate = be_u16(entry, 32).map(u32::from).filter(|rate| *rate > 0);
+            },
+            _ => {},
+        }
+    }
+
+    // iTunes-style tags: udta/meta/ilst, each item holding a `data` box
+    let meta = child_box(&movie, b"udta").and_then(|user_data| child_box(user_data, b"meta"));
+    // `meta` is a full box in MP4 but not in QuickTime
+    let meta = meta.map(|meta| if meta.get(4..8) == Some(b"hdlr") { meta } else { meta.get(4..).unwrap_or_default() });
+    if let Some(items) = meta.and_then(|meta| child_box(meta, b"ilst")) {
+        for (kind, item) in boxes(items) {
+            let field = match kind {
+                b"\xa9nam" => &mut metadata.title,
+                b"\xa9ART" => &mut metadata.artist,
+                b"\xa9alb" => &mut metadata.album,
+                _ => continue,
+            };
+            // Type and locale come before the value
+            let value = child_box(item, b"data").and_then(|data| data.get(8..));
+            fill(field, value.and_then(|value| tag_text(&String::from_utf8_lossy(value))));
+        }
+    }
+    Some(metadata)
+}
+
+/// The boxes in `data` as type and body.
+fn boxes(data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> + '_ {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let kind = data.get(at + 4..at + 8)?;
+        let (header_length, size) = match be_u32(data, at)? {
+            0 => (8, data.len() - at),
+            1 => (16, usize::try_from(be_u64(data, at + 8)?).ok()?),
+            size => (8, size as usize),
+        };
+        let body = data.get(at + header_length..at.checked_add(size)?)?;
+        at += size;
+        Some((kind, body))
+    })
+}
+
+fn child_box<'a>(data: &'a [u8], kind: &[u8]) -> Option<&'a [u8]> {
+    boxes(data).find(|(found, _)| *found == kind).map(|(_, body)| body)
+}
+
+fn iso_codec(format: &[u8]) -> String {
+    let name = match format {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"av01" => "av1",
+        b"vp09" => "vp9",
+        b"vp08" => "vp8",
+        b"mp4v" => "mpeg4",
+        b"mp4a" => "aac",
+        b"alac" => "alac",
+        b"ac-3" => "ac3",
+        b"ec-3" => "eac3",
+        b"Opus" => "opus",
+        b"fLaC" => "flac",
+        b".mp3" => "mp3",
+        other => return String::from_utf8_lossy(other).trim().to_lowercase(),
+    };
+    name.to_string()
+}
+
+fn matroska(file: &mut File, file_size: u64) -> Option<MediaMetadata> {
+    let (id, size, header_length) = element_header(&read_at(file, 0, 16)?)?;
+    if id != EBML_HEADER {
+        return None;
+    }
+    let mut at = header_length as u64 + size?;
+    let (id, size, header_length) = element_header(&read_at(file, at, 16)?)?;
+    if id != SEGMENT {
+        return None;
+    }
+    at += header_length as u64;
+    let segment_end = size.map_or(file_size, |size| at.saturating_add(size)).min(file_size);
+
+    let mut metadata = MediaMetadata::default();
+    let mut timestamp_scale = 1_000_000;
+    let mut duration = None;
+    let mut tags = Vec::new();
+    // Walk the segment's children, skipping clusters of media data, until
+    // one of unknown size makes the rest unreachable
+    for _ in 0..MAX_MATROSKA_ELEMENTS {
+        if at >= segment_end {
+            break;
+        }
+        let Some((id, Some(size), header_length)) = read_at(file, at, 16).and_then(|header| element_header(&header)) else {
+            break;
+        };
+        let body_at = at + header_length as u64;
+        if matches!(id, INFO | TRACKS | TAGS) {
+            if let Some(body) = read_at(file, body_at, size) {
+                match id {
+                    INFO => {
+                        for (id, value) in elements(&body) {
+                            match id {
+                                TIMESTAMP_SCALE => timestamp_scale = ebml_uint(value),
+                                DURATION => duration = ebml_float(value),
+                                TITLE => fill(&mut metadata.title, ebml_string(value)),
+                                _ => {},
+                            }
+                        }
+                    },
+                    TRACKS => matroska_tracks(&body, &mut metadata),
+                    _ => matroska_tags(&body, &mut tags),
+                }
+            }
+        }
+        at = body_at.saturating_add(size);
+    }
+
+    metadata.duration_seconds = duration
+        .map(|duration| duration * timestamp_scale as f64 / 1e9)
+        .filter(|duration| *duration > 0.0);
+
+    // Track-level tags (target 30) come first. A title at album level (50)
+    // names the album of music and the film itself otherwise.
+    tags.sort_by_key(|(level, _, _)| *level);
+    let has_video = metadata.has_video();
+    for (level, name, value) in tags {
+        let field = match name.as_str() {
+            "ARTIST" => &mut metadata.artist,
+            "TITLE" if level >= 50 && !has_video => &mut metadata.album,
+            "TITLE" => &mut metadata.title,
+            _ => continue,
+        };
+        fill(field, Some(value));
+    }
+    Some(metadata)
+}
+
+fn matroska_tracks(tracks: &[u8], metadata: &mut MediaMetadata) {
+    for (_, entry) in elements(tracks).filter(|(id, _)| *id == TRACK_ENTRY) {
+        let mut track_type = 0;
+        let mut codec = None;
+        let mut video: &[u8] = &[];
+        let mut audio: &[u8] = &[];
+        for (id, value) in elements(entry) {
+            match id {
+                TRACK_TYPE => track_type = ebml_uint(value),
+                CODEC_ID => codec = Some(matroska_codec(value)),
+                VIDEO => video = value,
+                AUDIO => audio = value,
+                _ => {},
+            }
+        }
+        match track_type {
+            1 if metadata.video_codec.is_none() => {
+                metadata.video_codec = codec;
+                for (id, value) in elements(video) {
+                    match id {
+                        PIXEL_WIDTH => metadata.width = u32::try_from(ebml_uint(value)).ok(),
+                        PIXEL_HEIGHT => metadata.height = u32::try_from(ebml_uint(value)).ok(),
+                        _ => {},
+                    }
+                }
+            },
+            2 if metadata.audio_codec.is_none() => {
+                metadata.audio_codec = codec;
+                for (id, value) in elements(audio) {
+                    match id {
+                        SAMPLING_FREQUENCY => metadata.sample_rate = ebml_float(value).map(|rate| rate as u32),
+                        CHANNELS => metadata.channels = u8::try_from(ebml_uint(value)).ok(),
+                        _ => {},
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Collects simple tags as target level, upper-case name and value.
+fn matroska_tags(body: &[u8], tags: &mut Vec<(u64, String, String)>) {
+    for (_, tag) in elements(body).filter(|(id, _)| *id == TAG) {
+        let level = elements(tag)
+            .find(|(id, _)| *id == TARGETS)
+            .and_then(|(_, targets)| elements(targets).find(|(id, _)| *id == TARGET_TYPE_VALUE))
+            .map_or(50, |(_, value)| ebml_uint(value));
+        for (_, simple) in elements(tag).filter(|(id, _)| *id == SIMPLE_TAG) {
+            let field = |wanted: u32| elements(simple).find(|(id, _)| *id == wanted).and_then(|(_, value)| ebml_string(value));
+            if let (Some(name), Some(value)) = (field(TAG_NAME), field(TAG_STRING)) {
+                tags.push((level, name.to_ascii_uppercase(), value));
+            }
+        }
+    }
+}
+
+fn matroska_codec(id: &[u8]) -> String {
+    let id = String::from_utf8_lossy(id);
+    let id = id.trim_end_matches('\0');
+    let name = match id {
+        "V_MPEG4/ISO/AVC" => "h264",
+        "V_MPEGH/ISO/HEVC" => "hevc",
+        "V_AV1" => "av1",
+        "V_VP9" => "vp9",
+        "V_VP8" => "vp8",
+        "A_OPUS" => "opus",
+        "A_VORBIS" => "vorbis",
+        "A_FLAC" => "flac",
+        "A_AC3" => "ac3",
+        "A_EAC3" => "eac3",
+        "A_DTS" => "dts",
+        "A_MPEG/L3" => "mp3",
+        _ if id.starts_with("A_AAC") => "aac",
+        other => return other.split_once('_').map_or(other, |(_, rest)| rest).to_lowercase(),
+    };
+    name.to_string()
+}
+
+/// Reads an EBML element header: the ID, the body size (`None` when it is
+/// unknown) and the header's length.
+fn element_header(bytes: &[u8]) -> Option<(u32, Option<u64>, usize)> {
+    let id_length = bytes.first()?.leading_zeros() as usize + 1;
+    if id_length > 4 {
+        return None;
+    }
+    let id = bytes.get(..id_length)?.iter().fold(0u32, |id, byte| id << 8 | *byte as u32);
+    let first = *bytes.get(id_length)?;
+    let size_length = first.leading_zeros() as usize + 1;
+    if size_length > 8 {
+        return None;
+    }
+    let size = bytes.get(id_length + 1..id_length + size_length)?
+        .iter()
+        .fold(first as u64 & (0xff >> size_length), |size, byte| size << 8 | *byte as u64);
+    // All value bits set means the size is unknown
+    let unknown = size == (1 << (7 * size_length)) - 1;
+    Some((id, (!unknown).then_some(size), id_length + size_length))
+}
+
+/// The child elements in `data` as ID and body.
+fn elements(data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> + '_ {
+    let mut at = 0;
+    std::iter::from_fn(move || {
+        let (id, size, header_length) = element_header(data.get(at..)?)?;
+        let end = (at + header_length).checked_add(usize::try_from(size?).ok()?)?;
+        let body = data.get(at + header_length..end)?;
+        at = end;
+        Some((id, body))
+    })
+}
+
+fn ebml_uint(value: &[u8]) -> u64 {
+    value.iter().fold(0, |number, byte| number << 8 | *byte as u64)
+}
+
+fn ebml_float(value: &[u8]) -> Option<f64> {
+    match value.len() {
+        4 => Some(f32::from_be_bytes(value.try_into().ok()?).into()),
+        8 => Some(f64::from_be_bytes(value.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn ebml_string(value: &[u8]) -> Option<String> {
+    tag_text(&String::from_utf8_lossy(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    fn iso_box(kind: &[u8], body: &[u8]) -> Vec<u8> {
+        let mut bytes = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(kind);
+        bytes.extend_from_slice(body);
+        bytes
+    }
+
+    #[test]
+    fn reads_movie_duration_after_other_boxes() {
+        // Version 0 movie header: flags, creation and modification times,
+        // then a timescale of 1000 and a duration of 90 500
+        let mut header = vec![0; 12];
+        header.extend_from_slice(&1000u32.to_be_bytes());
+        header.extend_from_slice(&90_500u32.to_be_bytes());
+        let mut file = iso_box(b"ftyp", b"isom\0\0\0\0");
+        file.extend(iso_box(b"mdat", &[0; 64]));
+        file.extend(iso_box(b"moov", &iso_box(b"mvhd", &header)));
+        let dir = TempDir::new();
+        let path = dir.file("movie.mp4", &file);
+
+        let metadata = read(&path, "video/mp4").unwrap();
+        assert_eq!(metadata.duration_seconds, Some(90.5));
+    }
+
+    #[test]
+    fn stops_at_a_box_size_that_wraps() {
+        let mut file = iso_box(b"ftyp", b"isom\0\0\0\0");
+        file.extend_from_slice(&1u32.to_be_bytes());
+        file.extend_from_slice(b"free");
+        file.extend_from_slice(&(u64::MAX - 8).to_be_bytes());
+        file.extend(iso_box(b"moov", &[]));
+        let dir = TempDir::new();
+        let path = dir.file("wraps.mp4", &file);
+
+        assert_eq!(read(&path, "video/mp4"), None);
+    }
+
+    #[test]
+    fn parses_mpeg_layer_three_header() {
+        // MPEG-1 Layer III, 128 kbit/s, 44.1 kHz, joint stereo
+        let frame = mpeg_frame(&[0xff, 0xfb, 0x90, 0x64]).unwrap();
+        assert!(frame.mpeg1);
+        assert!(!frame.mono);
+        assert_eq!((frame.bitrate, frame.sample_rate, frame.samples), (128_000, 44_100, 1152));
+        assert_eq!(frame.length, 417);
+        // Layer II is not handled
+        assert!(mpeg_frame(&[0xff, 0xfd, 0x90, 0x64]).is_none());
+    }
+
+    #[test]
+    fn reads_flac_stream_info_and_comments() {
+        let packed: u64 = 44_100 << 44 | 1 << 41 | 15 << 36 | 441_000;
+        let mut info = vec![0; 10];
+        info.extend_from_slice(&packed.to_be_bytes());
+        let mut metadata = MediaMetadata::default();
+        flac_stream_info(&info, &mut metadata).unwrap();
+        assert_eq!(metadata.sample_rate, Some(44_100));
+        assert_eq!(metadata.channels, Some(2));
+        assert_eq!(metadata.duration_seconds, Some(10.0));
+
+        let mut comments = 3u32.to_le_bytes().to_vec();
+        comments.extend_from_slice(b"enc");
+        comments.extend_from_slice(&3u32.to_le_bytes());
+        for comment in [&b"TITLE=Song "[..], b"artist=Band", b"GENRE=Rock"] {
+            comments.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comments.extend_from_slice(comment);
+        }
+        vorbis_comments(&comments, &mut metadata).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("Song"));
+        assert_eq!(metadata.artist.as_deref(), Some("Band"));
+        assert_eq!(metadata.album, None);
+    }
+
+    #[test]
+    fn reads_ebml_element_headers() {
+        assert_eq!(element_header(&[0x1a, 0x45, 0xdf, 0xa3, 0x84]), Some((EBML_HEADER, Some(4), 5)));
+        // Segment of unknown size
+        let segment = [0x18, 0x53, 0x80, 0x67, 0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        assert_eq!(element_header(&segment), Some((SEGMENT, None, 12)));
+        assert_eq!(element_header(&[0x00, 0x81]), None);
+    }
+}
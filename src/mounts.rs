@@ -0,0 +1,543 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_derive::Serialize;
+use crate::config::Config;
+use crate::storage;
+
+/// Kernel filesystems that never hold user files; they are skipped whatever
+/// `skip_fs_types` says.
+const PSEUDO_FS_TYPES: &[&str] = &[
+    "autofs", "binfmt_misc", "bpf", "cgroup", "cgroup2", "configfs", "debugfs", "devpts",
+    "devtmpfs", "efivarfs", "fusectl", "hugetlbfs", "mqueue", "nsfs", "proc", "pstore",
+    "rpc_pipefs", "securityfs", "sysfs", "tracefs",
+];
+
+/// Mount points below these are runtime state rather than user data.
+const PSEUDO_PREFIXES: &[&str] = &["/proc", "/sys", "/dev", "/run"];
+
+/// Access to the kernel's view of mounts and block devices. Everything is
+/// read through this so that the parsing can run against a fixture tree.
+pub trait SystemReader {
+    fn read_to_string(&self, path: &Path) -> io::Result<String>;
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf>;
+    /// File names in a directory.
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>>;
+}
+
+/// Reads the running system's `/proc`, `/sys` and `/dev`.
+pub struct HostReader;
+
+impl SystemReader for HostReader {
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.file_name().to_string_lossy().to_string()))
+            .collect()
+    }
+}
+
+/// One line of `/proc/self/mountinfo`.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    /// `major:minor` of the mounted filesystem. Bind mounts share it with
+    /// their source.
+    pub device: String,
+    /// Directory of the filesystem that appears at the mount point; `/`
+    /// unless this is a bind mount of a subdirectory.
+    pub fs_root: PathBuf,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+    /// What was mounted, e.g. `/dev/sda1` or `server:/export`.
+    pub source: String,
+    pub read_only: bool,
+}
+
+/// A scan root as served by `/drives` and `/status/live`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DriveDetail {
+    pub mount_point: String,
+    /// Mount source, usually the device node.
+    pub device: String,
+    /// Kernel name of the backing block device, such as `sda1` or `nvme0n1p2`.
+    pub device_name: Option<String>,
+    pub fs_type: String,
+    pub label: Option<String>,
+    pub uuid: Option<String>,
+    pub read_only: bool,
+    pub total_space: u64,
+    pub available_space: u64,
+    pub used_space: u64,
+    pub total_inodes: u64,
+    pub free_inodes: u64,
+    pub is_removable: bool,
+}
+
+/// Which mounts to scan and which directories a walk must not descend into.
+#[derive(Debug, Clone, Default)]
+pub struct MountPlan {
+    /// One root per distinct filesystem, minus those a parent root already
+    /// walks into because it does not stay on its own filesystem.
+    pub roots: Vec<PathBuf>,
+    /// Mount points of skipped filesystems and of bind mounts whose content
+    /// is scanned under another path.
+    pub boundaries: HashSet<PathBuf>,
+}
+
+/// Where a walk from one root has to stop.
+#[derive(Debug, Clone, Default)]
+pub struct RootLimits {
+    /// Mount points the walk must not enter.
+    pub boundaries: HashSet<PathBuf>,
+    /// Device of the root when the walk stays on its filesystem.
+    pub root_device: Option<u64>,
+    /// Other roots and boundaries below the root; what lies under them is
+    /// indexed by another walk or not at all.
+    pub nested: Vec<PathBuf>,
+}
+
+impl RootLimits {
+    /// Whether the directory lies past a mount boundary of this root.
+    pub fn leaves_root(&self, path: &Path, entry: &fs::DirEntry) -> bool {
+        if self.boundaries.contains(path) {
+            return true;
+        }
+        self.root_device.is_some_and(|device| {
+            entry.metadata().is_ok_and(|metadata| storage::file_identity(&metadata).0 != device)
+        })
+    }
+
+    /// Whether an indexed file on `device` is outside what a walk of this
+    /// root reaches, so the walk not finding it says nothing about it. Files
+    /// indexed before devices were recorded have device 0.
+    pub fn excludes(&self, path: &Path, device: u64) -> bool {
+        self.nested.iter().any(|nested| path.starts_with(nested))
+            || self.root_device.is_some_and(|root_device| device != 0 && device != root_device)
+    }
+}
+
+impl MountPlan {
+    /// Works out the scan roots from the current mount table.
+    pub fn current(config: &Config) -> Self {
+        Self::from_mounts(read_mounts(&HostReader), config)
+    }
+
+    /// The limits of a walk from `root`, which may be one of the roots or a
+    /// directory below one.
+    pub fn limits_of(&self, root: &Path, config: &Config) -> RootLimits {
+        let mut boundaries = self.boundaries.clone();
+        boundaries.remove(root);
+        let root_device = match config.same_file_system(root) {
+            true => fs::metadata(root).ok().map(|metadata| storage::file_identity(&metadata).0),
+            false => None,
+        };
+        let nested = self.roots.iter()
+            .chain(&self.boundaries)
+            .filter(|path| path.as_path() != root && path.starts_with(root))
+            .cloned()
+            .collect();
+        RootLimits { boundaries, root_device, nested }
+    }
+
+    fn from_mounts(mounts: Vec<Mount>, config: &Config) -> Self {
+        // A later mount on the same point hides the earlier one
+        let mut visible: Vec<Mount> = Vec::new();
+        for mount in mounts {
+            visible.retain(|earlier| earlier.mount_point != mount.mount_point);
+            visible.push(mount);
+        }
+
+        let mut plan = MountPlan::default();
+        let mut candidates = Vec::new();
+        for mount in visible {
+            if is_skipped(&mount, config) {
+                plan.boundaries.insert(mount.mount_point);
+            } else {
+                candidates.push(mount);
+            }
+        }
+
+        // Of several mounts of one filesystem, keep the one showing the most
+        // of it; the others are bind mounts of content scanned there
+        candidates.sort_by(|a, b| {
+            depth(&a.fs_root).cmp(&depth(&b.fs_root))
+                .then_with(|| depth(&a.mount_point).cmp(&depth(&b.mount_point)))
+                .then_with(|| a.mount_point.cmp(&b.mount_point))
+        });
+        let mut kept = HashSet::new();
+        let mut roots = Vec::new();
+        for mount in candidates {
+            if kept.insert(mount.device) {
+                roots.push(mount.mount_point);
+            } else {
+                plan.boundaries.insert(mount.mount_point);
+            }
+        }
+
+        // Roots inside one that crosses filesystems are walked from there
+        roots.sort();
+        let crossing: Vec<PathBuf> = roots.iter()
+            .filter(|root| !config.same_file_system(root))
+            .cloned()
+            .collect();
+        roots.retain(|root| !crossing.iter().any(|parent| parent != root && root.starts_with(parent)));
+
+        if roots.is_empty() {
+            roots.push(PathBuf::from("/"));
+        }
+        plan.roots = roots;
+        plan
+    }
+}
+
+fn is_skipped(mount: &Mount, config: &Config) -> bool {
+    PSEUDO_FS_TYPES.contains(&mount.fs_type.as_str())
+        || config.skip_fs_types.contains(&mount.fs_type)
+        || PSEUDO_PREFIXES.iter().any(|prefix| mount.mount_point.starts_with(prefix))
+}
+
+fn depth(path: &Path) -> usize {
+    path.components().count()
+}
+
+/// Reads the mount table of this process. Empty where there is none.
+pub fn read_mounts(reader: &dyn SystemReader) -> Vec<Mount> {
+    match reader.read_to_string(Path::new("/proc/self/mountinfo")) {
+        Ok(content) => content.lines().filter_map(parse_mountinfo_line).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Describes each of `roots` from the mount table, sysfs and `statvfs`.
+pub fn drive_details(roots: &[PathBuf]) -> Vec<DriveDetail> {
+    let reader = HostReader;
+    let mounts = read_mounts(&reader);
+    roots.iter()
+        .map(|root| {
+            let mut detail = describe_drive(&reader, &mounts, root);
+            if let Ok(stat) = nix::sys::statvfs::statvfs(root) {
+                detail.total_space = stat.blocks() * stat.fragment_size();
+                detail.available_space = stat.blocks_available() * stat.fragment_size();
+                detail.used_space = (stat.blocks() - stat.blocks_free()) * stat.fragment_size();
+                detail.total_inodes = stat.files();
+                detail.free_inodes = stat.files_free();
+            }
+            detail
+        })
+        .collect()
+}
+
+/// Everything about the drive at `root` except the space figures. The mount
+/// that holds `root` supplies device, type and read-only flag; the block
+/// device behind it, found through `/sys/dev/block`, supplies the rest.
+pub fn describe_drive(reader: &dyn SystemReader, mounts: &[Mount], root: &Path) -> DriveDetail {
+    // The last mount wins where several share a mount point
+    let mount = mounts.iter()
+        .filter(|mount| root.starts_with(&mount.mount_point))
+        .max_by_key(|mount| depth(&mount.mount_point));
+    let block_device = mount.and_then(|mount| block_device_name(reader, &mount.device));
+    let disk = block_device.as_deref().map(|name| whole_disk(reader, name));
+    let sys_flag = |name: &str, file: &str| {
+        reader.read_to_string(&Path::new("/sys/class/block").join(name).join(file))
+            .is_ok_and(|value| value.trim() == "1")
+    };
+
+    DriveDetail {
+        mount_point: root.to_string_lossy().to_string(),
+        device: mount.map(|mount| mount.source.clone()).unwrap_or_default(),
+        device_name: block_device.clone(),
+        fs_type: mount.map(|mount| mount.fs_type.clone()).unwrap_or_else(|| "unknown".to_string()),
+        label: block_device.as_deref().and_then(|name| disk_link(reader, "by-label", name)),
+        uuid: block_device.as_deref().and_then(|name| disk_link(reader, "by-uuid", name)),
+        read_only: mount.is_some_and(|mount| mount.read_only)
+            || block_device.as_deref().is_some_and(|name| sys_flag(name, "ro")),
+        total_space: 0,
+        available_space: 0,
+        used_space: 0,
+        total_inodes: 0,
+        free_inodes: 0,
+        is_removable: disk.as_deref().is_some_and(|disk| is_removable(reader, disk)),
+    }
+}
+
+/// Kernel name of the block device with the given `major:minor`, or `None`
+/// for filesystems without one.
+fn block_device_name(reader: &dyn SystemReader, device: &str) -> Option<String> {
+    let target = reader.read_link(&Path::new("/sys/dev/block").join(device)).ok()?;
+    Some(target.file_name()?.to_string_lossy().to_string())
+}
+
+/// The disk a partition belongs to, or the device itself. Device-mapper and
+/// md devices are followed to the first device they are built on.
+fn whole_disk(reader: &dyn SystemReader, name: &str) -> String {
+    let mut name = name.to_string();
+    // Bounded in case of an unexpected loop in sysfs
+    for _ in 0..8 {
+        let class = Path::new("/sys/class/block").join(&name);
+        if reader.read_to_string(&class.join("partition")).is_ok() {
+            match reader.read_link(&class).ok().and_then(|target| Some(target.parent()?.file_name()?.to_string_lossy().to_string())) {
+                Some(disk) => name = disk,
+                None => break,
+            }
+        } else if let Some(slave) = reader.list_dir(&class.join("slaves")).ok().and_then(|slaves| slaves.into_iter().min()) {
+            name = slave;
+        } else {
+            break;
+        }
+    }
+    name
+}
+
+/// Whether the kernel flags the disk as removable media, or it hangs off a
+/// USB bus, which covers external drives that do not set the flag.
+fn is_removable(reader: &dyn SystemReader, disk: &str) -> bool {
+    let removable = reader.read_to_string(&Path::new("/sys/block").join(disk).join("removable"))
+        .is_ok_and(|value| value.trim() == "1");
+    removable || reader.read_link(&Path::new("/sys/class/block").join(disk))
+        .is_ok_and(|target| target.components().any(|component| component.as_os_str().to_string_lossy().starts_with("usb")))
+}
+
+/// Name of the `/dev/disk/<kind>` link that points at `device`, such as its
+/// label or UUID.
+fn disk_link(reader: &dyn SystemReader, kind: &str, device: &str) -> Option<String> {
+    let dir = Path::new("/dev/disk").join(kind);
+    reader.list_dir(&dir).ok()?
+        .into_iter()
+        .find(|link| {
+            reader.read_link(&dir.join(link))
+                .is_ok_and(|target| target.file_name().is_some_and(|name| name == device))
+        })
+        .map(|link| unescape_udev(&link))
+}
+
+/// udev writes unsafe characters in link names as `\xNN`.
+fn unescape_udev(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let code = bytes.get(index..index + 4)
+            .filter(|escape| escape.starts_with(b"\\x"))
+            .and_then(|escape| std::str::from_utf8(&escape[2..]).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match code {
+            Some(code) => {
+                unescaped.push(code);
+                index += 4;
+            },
+            None => {
+                unescaped.push(bytes[index]);
+                index += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+/// Parses `id parent major:minor root mount_point options [optional...] - type source super_options`.
+fn parse_mountinfo_line(line: &str) -> Option<Mount> {
+    let (mount_fields, fs_fields) = line.split_once(" - ")?;
+    let mount_fields: Vec<&str> = mount_fields.split_whitespace().collect();
+    let mut fs_fields = fs_fields.split_whitespace();
+    let options = mount_fields.get(5)?;
+    Some(Mount {
+        device: mount_fields.get(2)?.to_string(),
+        fs_root: PathBuf::from(unescape(mount_fields.get(3)?)),
+        mount_point: PathBuf::from(unescape(mount_fields.get(4)?)),
+        fs_type: fs_fields.next()?.to_string(),
+        source: unescape(fs_fields.next().unwrap_or("none")),
+        read_only: options.split(',').any(|option| option == "ro"),
+    })
+}
+
+/// Undoes the octal escapes (`\040` for a space and so on) the kernel uses
+/// in mount tables.
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut unescaped = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let digits = bytes.get(index + 1..index + 4).filter(|digits| digits.iter().all(|b| (b'0'..=b'7').contains(b)));
+        if let (b'\\', Some(digits)) = (bytes[index], digits) {
+            unescaped.push(digits.iter().fold(0u8, |code, b| code.wrapping_mul(8).wrapping_add(b - b'0')));
+            index += 4;
+        } else {
+            unescaped.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8_lossy(&unescaped).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const MOUNTINFO: &str = "\
+22 1 8:2 / / rw,relatime shared:1 - ext4 /dev/sda2 rw
+23 22 0:21 / /proc rw,nosuid,nodev,noexec - proc proc rw
+24 22 8:3 / /home rw,relatime shared:2 - ext4 /dev/sda3 rw
+25 22 0:40 / /tmp rw,nosuid - tmpfs tmpfs rw
+26 22 8:3 /alice/media /srv/media ro,relatime - ext4 /dev/sda3 rw
+27 22 8:17 / /media/usb\\040stick rw,relatime - vfat /dev/sdb1 rw
+";
+
+    /// Files, symlinks and directory listings standing in for `/proc`,
+    /// `/sys` and `/dev`.
+    #[derive(Default)]
+    struct Fixture {
+        files: HashMap<PathBuf, String>,
+        links: HashMap<PathBuf, PathBuf>,
+        dirs: HashMap<PathBuf, Vec<String>>,
+    }
+
+    impl Fixture {
+        fn file(mut self, path: &str, content: &str) -> Self {
+            self.files.insert(PathBuf::from(path), content.to_string());
+            self
+        }
+
+        fn link(mut self, path: &str, target: &str) -> Self {
+            self.links.insert(PathBuf::from(path), PathBuf::from(target));
+            let (dir, name) = path.rsplit_once('/').unwrap();
+            self.dirs.entry(PathBuf::from(dir)).or_default().push(name.to_string());
+            self
+        }
+    }
+
+    fn missing() -> io::Error {
+        io::Error::from(io::ErrorKind::NotFound)
+    }
+
+    impl SystemReader for Fixture {
+        fn read_to_string(&self, path: &Path) -> io::Result<String> {
+            self.files.get(path).cloned().ok_or_else(missing)
+        }
+
+        fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+            self.links.get(path).cloned().ok_or_else(missing)
+        }
+
+        fn list_dir(&self, path: &Path) -> io::Result<Vec<String>> {
+            self.dirs.get(path).cloned().ok_or_else(missing)
+        }
+    }
+
+    fn sysfs() -> Fixture {
+        Fixture::default()
+            .file("/proc/self/mountinfo", MOUNTINFO)
+            .link("/sys/dev/block/8:3", "../../devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda/sda3")
+            .link("/sys/class/block/sda3", "../../devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda/sda3")
+            .link("/sys/class/block/sda", "../../devices/pci0000:00/0000:00:17.0/ata1/host0/target0:0:0/0:0:0:0/block/sda")
+            .file("/sys/class/block/sda3/partition", "3\n")
+            .file("/sys/class/block/sda3/ro", "0\n")
+            .file("/sys/block/sda/removable", "0\n")
+            .link("/dev/disk/by-label/Home\\x20Disk", "../../sda3")
+            .link("/dev/disk/by-uuid/0b1c5e2f-8d4a-4c3e-9f1a-2b7d6e0c4a91", "../../sda3")
+            .link("/sys/dev/block/8:17", "../../devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb/sdb1")
+            .link("/sys/class/block/sdb1", "../../devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb/sdb1")
+            .link("/sys/class/block/sdb", "../../devices/pci0000:00/0000:00:14.0/usb2/2-1/2-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb")
+            .file("/sys/class/block/sdb1/partition", "1\n")
+            .file("/sys/block/sdb/removable", "0\n")
+    }
+
+    #[test]
+    fn parses_mountinfo_lines() {
+        let mounts = read_mounts(&sysfs());
+        assert_eq!(mounts.len(), 6);
+        let bind = &mounts[4];
+        assert_eq!(bind.device, "8:3");
+        assert_eq!(bind.fs_root, Path::new("/alice/media"));
+        assert_eq!(bind.mount_point, Path::new("/srv/media"));
+        assert!(bind.read_only);
+        let usb = &mounts[5];
+        assert_eq!(usb.mount_point, Path::new("/media/usb stick"));
+        assert_eq!((usb.fs_type.as_str(), usb.source.as_str()), ("vfat", "/dev/sdb1"));
+        assert!(!usb.read_only);
+        assert!(parse_mountinfo_line("22 1 8:2 / / rw").is_none());
+    }
+
+    #[test]
+    fn unescapes_mount_and_udev_names() {
+        assert_eq!(unescape("/mnt/a\\040b\\011c"), "/mnt/a b\tc");
+        assert_eq!(unescape("/mnt/trailing\\04"), "/mnt/trailing\\04");
+        assert_eq!(unescape_udev("My\\x20Drive\\x2fx"), "My Drive/x");
+        assert_eq!(unescape_udev("plain\\xzz"), "plain\\xzz");
+    }
+
+    #[test]
+    fn plans_one_root_per_filesystem() {
+        let plan = MountPlan::from_mounts(read_mounts(&sysfs()), &Config::default());
+        assert_eq!(plan.roots, [PathBuf::from("/"), PathBuf::from("/home"), PathBuf::from("/media/usb stick")]);
+        let boundaries: HashSet<PathBuf> = ["/proc", "/tmp", "/srv/media"].iter().map(PathBuf::from).collect();
+        assert_eq!(plan.boundaries, boundaries);
+    }
+
+    #[test]
+    fn crossing_root_absorbs_the_roots_below_it() {
+        let mut config = Config::default();
+        config.root_options.insert("/".to_string(), crate::config::RootOptions { same_file_system: false });
+        let plan = MountPlan::from_mounts(read_mounts(&sysfs()), &config);
+        assert_eq!(plan.roots, [PathBuf::from("/")]);
+
+        let limits = plan.limits_of(Path::new("/"), &config);
+        assert_eq!(limits.root_device, None);
+        assert!(limits.excludes(Path::new("/srv/media/song.flac"), 0));
+        assert!(!limits.excludes(Path::new("/home/alice/notes.txt"), 2051));
+    }
+
+    #[test]
+    fn limits_leave_nested_roots_to_their_own_walk() {
+        let limits = RootLimits {
+            boundaries: HashSet::new(),
+            root_device: Some(2050),
+            nested: vec![PathBuf::from("/home")],
+        };
+        assert!(limits.excludes(Path::new("/home/alice/notes.txt"), 2050));
+        assert!(limits.excludes(Path::new("/var/lib/disk.img"), 2051));
+        assert!(!limits.excludes(Path::new("/homework/notes.txt"), 2050));
+        // Entries from before devices were recorded
+        assert!(!limits.excludes(Path::new("/var/log/syslog"), 0));
+    }
+
+    #[test]
+    fn describes_a_partition_from_sysfs() {
+        let reader = sysfs();
+        let detail = describe_drive(&reader, &read_mounts(&reader), Path::new("/home"));
+        assert_eq!(detail.device, "/dev/sda3");
+        assert_eq!(detail.device_name.as_deref(), Some("sda3"));
+        assert_eq!(detail.fs_type, "ext4");
+        assert_eq!(detail.label.as_deref(), Some("Home Disk"));
+        assert_eq!(detail.uuid.as_deref(), Some("0b1c5e2f-8d4a-4c3e-9f1a-2b7d6e0c4a91"));
+        assert!(!detail.read_only);
+        assert!(!detail.is_removable);
+    }
+
+    #[test]
+    fn drives_on_a_usb_bus_are_removable() {
+        let reader = sysfs();
+        assert_eq!(whole_disk(&reader, "sdb1"), "sdb");
+        let detail = describe_drive(&reader, &read_mounts(&reader), Path::new("/media/usb stick"));
+        assert_eq!(detail.device_name.as_deref(), Some("sdb1"));
+        assert!(detail.is_removable);
+        assert_eq!(detail.label, None);
+    }
+
+    #[test]
+    fn follows_device_mapper_to_the_disk_below() {
+        let reader = Fixture::default()
+            .link("/sys/class/block/dm-0/slaves/nvme0n1p3", "../../nvme0n1p3")
+            .file("/sys/class/block/nvme0n1p3/partition", "3\n")
+            .link("/sys/class/block/nvme0n1p3", "../../devices/pci0000:00/0000:00:1d.0/nvme/nvme0/nvme0n1/nvme0n1p3");
+        assert_eq!(whole_disk(&reader, "dm-0"), "nvme0n1");
+    }
+}
@@ -0,0 +1,180 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use chrono::Utc;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Extension of the checksum written next to files saved with [`write_checked`].
+const CHECKSUM_EXTENSION: &str = "b3";
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Keeps concurrent [`write_checked`] calls from pairing one write's contents
+/// with another's checksum, and [`read_checked`] from seeing half a write.
+static CHECKED_WRITES: Mutex<()> = Mutex::new(());
+
+/// Replaces `path` with `contents` so that readers and crashes only ever see
+/// the old or the new file: the data goes to a temporary file in the same
+/// directory, is flushed to disk, and is then renamed over the target.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let file_name = path.file_name().ok_or_else(|| io::Error::other("path has no file name"))?;
+    // Unique per write, since threads may replace the same file concurrently
+    let temp_path = dir.join(format!(
+        ".{}.{}-{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = (|| {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        // Persist the rename itself
+        #[cfg(unix)]
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Like [`write_atomic`], and also records a checksum that [`read_checked`]
+/// verifies. Meant for state the daemon owns, not files users edit by hand.
+pub fn write_checked(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let _guard = CHECKED_WRITES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    write_atomic(path, contents)?;
+    write_atomic(&checksum_path(path), blake3::hash(contents).to_hex().as_bytes())
+}
+
+pub fn write_json<T: Serialize + ?Sized>(path: &Path, value: &T) -> io::Result<()> {
+    write_checked(path, serde_json::to_string_pretty(value)?.as_bytes())
+}
+
+/// Reads a file saved with [`write_checked`]. A file whose checksum does not
+/// match is quarantined and reported as missing, unless the checksum predates
+/// it; then the write was cut short before the checksum was replaced, and the
+/// checksum is brought up to date. Files from older builds without a checksum
+/// are accepted as they are.
+pub fn read_checked(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    // A write in progress would look like one cut short
+    let _guard = CHECKED_WRITES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let contents = match fs::read(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let checksum_path = checksum_path(path);
+    match fs::read_to_string(&checksum_path) {
+        Ok(expected) if expected.trim() != blake3::hash(&contents).to_hex().as_str() => {
+            // The checksum is written after the data, so one older than the data
+            // belongs to the previous contents: a crash came in between
+            if is_older(&checksum_path, path)? {
+                write_atomic(&checksum_path, blake3::hash(&contents).to_hex().as_bytes())?;
+                return Ok(Some(contents));
+            }
+            quarantine(path, "checksum mismatch");
+            Ok(None)
+        },
+        Ok(_) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Some(contents)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Whether `path` was last modified before `other`.
+fn is_older(path: &Path, other: &Path) -> io::Result<bool> {
+    Ok(fs::metadata(path)?.modified()? < fs::metadata(other)?.modified()?)
+}
+
+/// Loads JSON saved with [`write_json`]. Missing, corrupt and unparseable
+/// files all yield `None`; the latter two are quarantined first so that the
+/// next write starts from a clean slate.
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    let contents = match read_checked(path) {
+        Ok(contents) => contents?,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    match serde_json::from_slice(&contents) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            quarantine(path, &e.to_string());
+            None
+        }
+    }
+}
+
+/// Moves a damaged file, and its checksum if any, aside as
+/// `<name>.corrupt-<timestamp>` so it can be inspected later.
+pub fn quarantine(path: &Path, reason: &str) {
+    let suffix = format!("corrupt-{}", Utc::now().format("%Y%m%dT%H%M%S"));
+    let target = with_suffix(path, &suffix);
+    match fs::rename(path, &target) {
+        Ok(()) => eprintln!("Quarantined {} ({}) as {}", path.display(), reason, target.display()),
+        Err(e) => eprintln!("Failed to quarantine {} ({}): {}", path.display(), reason, e),
+    }
+    let checksum = checksum_path(path);
+    if checksum.exists() {
+        let _ = fs::rename(&checksum, with_suffix(&checksum, &suffix));
+    }
+}
+
+fn checksum_path(path: &Path) -> PathBuf {
+    with_suffix(path, CHECKSUM_EXTENSION)
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+    use crate::test_support::TempDir;
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        File::options().write(true).open(path).unwrap().set_modified(time).unwrap();
+    }
+
+    #[test]
+    fn accepts_data_whose_checksum_write_was_cut_short() {
+        let dir = TempDir::new();
+        let path = dir.path().join("state.json");
+        write_checked(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        let now = SystemTime::now();
+        set_modified(&checksum_path(&path), now - Duration::from_secs(10));
+        set_modified(&path, now);
+
+        assert_eq!(read_checked(&path).unwrap().as_deref(), Some(&b"new"[..]));
+        assert_eq!(fs::read_to_string(checksum_path(&path)).unwrap(), blake3::hash(b"new").to_hex().as_str());
+    }
+
+    #[test]
+    fn quarantines_data_changed_after_its_checksum() {
+        let dir = TempDir::new();
+        let path = dir.path().join("state.json");
+        write_checked(&path, b"old").unwrap();
+        fs::write(&path, b"bad").unwrap();
+        let now = SystemTime::now();
+        set_modified(&path, now - Duration::from_secs(10));
+        set_modified(&checksum_path(&path), now);
+
+        assert_eq!(read_checked(&path).unwrap(), None);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,78 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_derive::{Serialize, Deserialize};
+use crate::config::CONFIG_FILE_NAME;
+use crate::persist;
+
+/// File in the config directory that records the running daemon.
+pub const RUNTIME_FILE_NAME: &str = "runtime.json";
+
+/// Keys older builds wrote into the settings file alongside user configuration.
+const LEGACY_RUNTIME_KEYS: [&str; 3] = ["port", "pid", "status"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DaemonStatus {
+    Running,
+    Stopped,
+}
+
+/// Process bookkeeping for the daemon, kept apart from user settings so that
+/// starting or stopping the backend never touches `config.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuntimeState {
+    pub port: u16,
+    pub pid: u32,
+    pub status: DaemonStatus,
+}
+
+impl RuntimeState {
+    pub fn path(config_dir: &Path) -> PathBuf {
+        config_dir.join(RUNTIME_FILE_NAME)
+    }
+
+    pub fn load(config_dir: &Path) -> Option<Self> {
+        persist::read_json(&Self::path(config_dir))
+    }
+
+    pub fn save(&self, config_dir: &Path) -> io::Result<()> {
+        persist::write_json(&Self::path(config_dir), self)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.status == DaemonStatus::Running
+    }
+}
+
+/// Moves `port`/`pid`/`status` out of a settings file written by an older
+/// build into the runtime state file, leaving only user settings behind.
+pub fn migrate_legacy_config(config_dir: &Path) -> io::Result<()> {
+    let config_path = config_dir.join(CONFIG_FILE_NAME);
+    let content = match fs::read_to_string(&config_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // Unparseable files are left alone so the config loader can report them
+    let Ok(serde_json::Value::Object(mut settings)) = serde_json::from_str::<serde_json::Value>(&content) else {
+        return Ok(());
+    };
+    if !LEGACY_RUNTIME_KEYS.iter().any(|key| settings.contains_key(*key)) {
+        return Ok(());
+    }
+
+    let runtime: serde_json::Map<String, serde_json::Value> = LEGACY_RUNTIME_KEYS
+        .iter()
+        .filter_map(|key| settings.remove(*key).map(|value| (key.to_string(), value)))
+        .collect();
+
+    if !RuntimeState::path(config_dir).exists() {
+        if let Ok(state) = serde_json::from_value::<RuntimeState>(serde_json::Value::Object(runtime)) {
+            state.save(config_dir)?;
+        }
+    }
+
+    println!("Moved runtime state out of {} into {}", config_path.display(), RUNTIME_FILE_NAME);
+    persist::write_atomic(&config_path, serde_json::to_string_pretty(&settings)?.as_bytes())
+}
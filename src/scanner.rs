@@ -1,86 +1,500 @@
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use walkdir::WalkDir;
-use std::collections::HashMap;
-use crate::analyzer::analyze_file;
-use crate::storage::FileMetadata;
-use crate::config::Config;
+use std::collections::{HashMap, HashSet};
+use rayon::prelude::*;
+use crate::analyzer::{analyze_file, recategorize};
+use crate::ai_integration;
+use crate::storage::{self, DirectoryTotals, FileMetadata};
+use crate::config::{Config, ScanMode};
+use crate::duplicates;
+use crate::exclusion::{ExclusionRules, IgnoreScope};
+use crate::jobs::{JobManager, ScanJob};
+use crate::mounts::{MountPlan, RootLimits};
+use crate::scoring;
 
+#[derive(Default)]
 pub struct ScanResult {
     pub total_files: usize,
     pub total_size: u64,
+    pub total_allocated_size: u64,
     pub file_types: HashMap<String, usize>,
+    /// Entries that need persisting: new and modified files plus tombstones
+    /// for files that vanished. Unchanged files are counted but not repeated here.
     pub metadata: HashMap<PathBuf, FileMetadata>,
+    pub reused_files: usize,
+    pub analyzed_files: usize,
+    pub deleted_files: usize,
+    /// Totals of every directory walked. Each directory holds only its own
+    /// files until [`roll_up_directories`] adds in its subdirectories.
+    pub directories: HashMap<PathBuf, DirectoryTotals>,
+    /// Files with more than one link, by device and inode. Their bytes are
+    /// left out of the totals until [`settle_hardlinks`] counts each inode once.
+    pub hardlinks: HashMap<(u64, u64), Vec<LinkedFile>>,
+    /// Roots and mount boundaries below the scanned root, whose contents
+    /// other scans own.
+    pub nested_roots: Vec<PathBuf>,
+    /// Indexed files the walk now skips, because of the settings or an
+    /// ignore file. They are dropped from the index rather than tombstoned.
+    pub excluded: Vec<PathBuf>,
 }
 
-pub fn start_initial_scan(config: Arc<Mutex<Config>>) {
+/// One path of a hardlinked inode met during the walk.
+pub struct LinkedFile {
+    pub path: PathBuf,
+    pub size: u64,
+    pub allocated_size: u64,
+}
+
+impl ScanResult {
+    fn merge(&mut self, other: ScanResult) {
+        self.total_files += other.total_files;
+        self.total_size += other.total_size;
+        self.total_allocated_size += other.total_allocated_size;
+        for (ext, count) in other.file_types {
+            *self.file_types.entry(ext).or_insert(0) += count;
+        }
+        self.metadata.extend(other.metadata);
+        self.reused_files += other.reused_files;
+        self.analyzed_files += other.analyzed_files;
+        self.deleted_files += other.deleted_files;
+        self.directories.extend(other.directories);
+        self.excluded.extend(other.excluded);
+        for (key, links) in other.hardlinks {
+            self.hardlinks.entry(key).or_default().extend(links);
+        }
+    }
+}
+
+/// Settings snapshot shared by every worker of one scan, so that workers
+/// never contend on the config mutex.
+struct ScanContext<'a> {
+    job: &'a ScanJob,
+    config: Config,
+    rules: ExclusionRules,
+    limits: RootLimits,
+    /// Index from earlier scans, used to skip files that have not changed.
+    previous: Arc<HashMap<PathBuf, FileMetadata>>,
+    seen: Mutex<HashSet<PathBuf>>,
+    /// Directories that could not be listed; what is indexed below them is
+    /// kept as it was.
+    unreadable: Mutex<Vec<PathBuf>>,
+    /// Excluded directories, and excluded files that are still indexed.
+    skipped: Mutex<HashSet<PathBuf>>,
+    result: Mutex<ScanResult>,
+}
+
+pub fn start_initial_scan(config: Arc<Mutex<Config>>, jobs: &JobManager) {
     println!("Starting initial scan of all drives...");
     
     // Get all drives
-    let drives = get_all_drives();
+    let drives = get_all_drives(&config.lock().unwrap());
+    jobs.run(drives, config);
+}
+
+/// Scans the job's roots and stores each result as it completes. A cancelled
+/// job keeps what it analyzed but records no tombstones or statistics, since
+/// it did not see the whole tree.
+pub fn scan_drives(drives: &[PathBuf], shared_config: Arc<Mutex<Config>>, job: &ScanJob) -> io::Result<()> {
+    let config = shared_config.lock().unwrap().clone();
+    // Picks up pins and repositories changed since the last scan, and drops
+    // the repositories the last model looked up
+    scoring::Model::rebuild(&config);
+    let previous = load_previous_index();
+    job.set_expected_bytes(estimate_scan_size(drives, &previous));
+    let save_error = Mutex::new(None);
     
-    // Start scanning each drive
-    for drive in drives {
-        scan_drive(&drive, config.clone());
+    // Independent mounts are scanned side by side on the same pool, so a busy
+    // drive lends idle workers to the others
+    build_pool(&config).install(|| {
+        drives.par_iter().for_each(|drive| {
+            let result = scan_in_pool(drive, &config, previous.clone(), &IgnoreScope::default(), job, false);
+            job.record_outcome(result.analyzed_files, result.reused_files, result.deleted_files);
+            let config_dir = crate::get_config_dir();
+            let saved = if job.is_cancelled() {
+                storage::upsert_file_metadata(&config_dir, &result.metadata)
+            } else {
+                storage::save_scan_result(&config_dir, drive, &result)
+            };
+            if let Err(e) = saved {
+                save_error.lock().unwrap().get_or_insert(e);
+            }
+        });
+        
+        // Copies can live on different drives, so duplicates are matched
+        // across the whole index once every drive is stored
+        if !job.is_cancelled() && save_error.lock().unwrap().is_none() {
+            match duplicates::update_index(&crate::get_config_dir()) {
+                Ok(count) => println!("Found {} duplicate files", count),
+                Err(e) => job.record_error(format!("Duplicate detection failed: {}", e)),
+            }
+            // Scores depend on duplicates and on how long ago files changed
+            match scoring::update_index(&crate::get_config_dir(), &config) {
+                Ok(count) => println!("Updated the importance of {} files", count),
+                Err(e) => job.record_error(format!("Scoring failed: {}", e)),
+            }
+            // The model is slow and remote, so it never runs on scan workers
+            if config.use_ai_analysis {
+                ai_integration::analyze_in_background(shared_config);
+            }
+        }
+    });
+    
+    match save_error.into_inner().unwrap() {
+        Some(e) => Err(e),
+        None => Ok(()),
     }
 }
 
-pub fn scan_drive(drive_path: &Path, config: Arc<Mutex<Config>>) -> ScanResult {
+/// Re-indexes one directory below a scanned root, e.g. one that was just
+/// created or moved into place. Only the file index is updated; the drive
+/// statistics are left to full scans.
+pub fn rescan_subtree(dir: &Path, config: &Config, parent_scope: &IgnoreScope) -> ScanResult {
+    let previous = load_previous_index();
+    let job = ScanJob::untracked(vec![dir.to_path_buf()]);
+    let result = build_pool(config).install(|| scan_in_pool(dir, config, previous, parent_scope, &job, true));
+    let config_dir = crate::get_config_dir();
+    let stored = storage::upsert_file_metadata(&config_dir, &result.metadata)
+        .and_then(|_| storage::remove_file_metadata(&config_dir, &result.excluded))
+        .and_then(|_| storage::save_directories(&config_dir, dir, &result.nested_roots, &result.directories));
+    if let Err(e) = stored {
+        eprintln!("Failed to store index for {:?}: {}", dir, e);
+    }
+    result
+}
+
+/// Expected bytes for progress reporting: what the previous index holds under
+/// the roots, or the space in use on them for a first scan.
+fn estimate_scan_size(drives: &[PathBuf], previous: &HashMap<PathBuf, FileMetadata>) -> u64 {
+    let indexed: u64 = previous.values()
+        .filter(|meta| !meta.is_deleted && drives.iter().any(|drive| meta.path.starts_with(drive)))
+        .map(|meta| meta.size)
+        .sum();
+    if indexed > 0 {
+        return indexed;
+    }
+    drives.iter()
+        .filter_map(|drive| nix::sys::statvfs::statvfs(drive).ok())
+        .map(|stat| (stat.blocks() - stat.blocks_free()) * stat.fragment_size())
+        .sum()
+}
+
+fn load_previous_index() -> Arc<HashMap<PathBuf, FileMetadata>> {
+    let metadata = storage::load_file_metadata(&crate::get_config_dir()).unwrap_or_else(|e| {
+        eprintln!("Failed to load previous index, analyzing every file: {}", e);
+        HashMap::new()
+    });
+    Arc::new(metadata)
+}
+
+/// Worker pool sized by `scan_mode` and `max_concurrent_scans`.
+fn build_pool(config: &Config) -> rayon::ThreadPool {
+    let threads = match config.scan_mode {
+        ScanMode::Sequential => 1,
+        ScanMode::Concurrent => config.max_concurrent_scans.max(1),
+    };
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|index| format!("drivedriver-scan-{}", index))
+        .build()
+        .expect("Failed to create scan thread pool")
+}
+
+/// Scans one drive on the current rayon pool. Every directory becomes its own
+/// task, and idle workers steal pending directories from busy ones. A
+/// `partial` scan walks only part of a root, such as one new directory.
+fn scan_in_pool(
+    drive_path: &Path,
+    config: &Config,
+    previous: Arc<HashMap<PathBuf, FileMetadata>>,
+    parent_scope: &IgnoreScope,
+    job: &ScanJob,
+    partial: bool,
+) -> ScanResult {
     println!("Scanning drive: {:?}", drive_path);
     
-    let mut result = ScanResult {
-        total_files: 0,
-        total_size: 0,
-        file_types: HashMap::new(),
-        metadata: HashMap::new(),
+    let rules = ExclusionRules::from_config(config);
+    if rules.is_path_excluded(drive_path, true) {
+        println!("Skipping excluded drive: {:?}", drive_path);
+        return ScanResult::default();
+    }
+    
+    let context = ScanContext {
+        job,
+        config: config.clone(),
+        rules,
+        limits: MountPlan::current(config).limits_of(drive_path, config),
+        previous,
+        seen: Mutex::new(HashSet::new()),
+        unreadable: Mutex::new(Vec::new()),
+        skipped: Mutex::new(HashSet::new()),
+        result: Mutex::new(ScanResult::default()),
     };
+    let root_scope = context.rules.enter_dir(drive_path, parent_scope);
+    rayon::scope(|s| scan_directory(s, drive_path.to_path_buf(), root_scope, &context));
     
-    // Walk the directory tree
-    for entry in WalkDir::new(drive_path)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
+    let seen = context.seen.into_inner().unwrap();
+    let unreadable = context.unreadable.into_inner().unwrap();
+    let skipped = context.skipped.into_inner().unwrap();
+    let mut result = context.result.into_inner().unwrap();
+    result.nested_roots = context.limits.nested.clone();
+    settle_hardlinks(&mut result, &context.previous, partial.then_some(drive_path));
+    roll_up_directories(&mut result.directories, drive_path);
+    
+    // Anything indexed under this drive that the walk did not reach is gone,
+    // unless the walk was cut short or stopped short of it at a mount
+    for (path, meta) in context.previous.iter().filter(|_| !job.is_cancelled()) {
+        if meta.is_deleted || !path.starts_with(drive_path) || seen.contains(path) {
+            continue;
+        }
+        if context.limits.excludes(path, meta.device) {
+            continue;
+        }
+        // A directory that could not be read says nothing about its contents
+        if unreadable.iter().any(|dir| path.starts_with(dir)) {
+            continue;
+        }
+        // Excluded files did not vanish; they leave the index altogether
+        if path.ancestors().any(|ancestor| skipped.contains(ancestor)) {
+            result.excluded.push(path.clone());
+            continue;
+        }
+        let mut tombstone = meta.clone();
+        tombstone.is_deleted = true;
+        result.metadata.insert(path.clone(), tombstone);
+        result.deleted_files += 1;
+    }
+    
+    println!(
+        "Finished scanning {:?}: {} files, {} bytes, {} allocated ({} analyzed, {} unchanged, {} deleted)",
+        drive_path, result.total_files, result.total_size, result.total_allocated_size,
+        result.analyzed_files, result.reused_files, result.deleted_files
+    );
+    
+    result
+}
+
+fn scan_directory<'s>(s: &rayon::Scope<'s>, dir: PathBuf, scope: IgnoreScope, context: &'s ScanContext<'s>) {
+    let job = context.job;
+    if !job.checkpoint() {
+        return;
+    }
+    job.enter_dir(&dir);
+    
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            job.record_error(format!("{}: {}", dir.display(), e));
+            context.unreadable.lock().unwrap().push(dir);
+            return;
+        }
+    };
+    
+    // Accumulate locally and merge once per directory rather than once per file
+    let mut local = ScanResult::default();
+    let mut own = DirectoryTotals::default();
+    let mut seen = Vec::new();
+    let mut skipped = Vec::new();
+    
+    for entry in entries.filter_map(|e| e.ok()) {
+        // Symlinks are never followed
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
         let path = entry.path();
         
-        // Skip excluded paths
-        let config_guard = config.lock().unwrap();
-        if config_guard.is_path_excluded(path) {
+        if file_type.is_dir() {
+            if context.rules.is_excluded(&path, true, &scope) {
+                skipped.push(path);
+                continue;
+            }
+            if context.limits.leaves_root(&path, &entry) {
+                continue;
+            }
+            let child_scope = context.rules.enter_dir(&path, &scope);
+            s.spawn(move |s| scan_directory(s, path, child_scope, context));
+            continue;
+        }
+        if !file_type.is_file() {
+            continue;
+        }
+        if context.rules.is_excluded(&path, false, &scope) {
+            if context.previous.contains_key(&path) {
+                skipped.push(path);
+            }
             continue;
         }
-        drop(config_guard);
         
-        let metadata = match std::fs::metadata(path) {
+        let metadata = match entry.metadata() {
             Ok(md) => md,
-            Err(_) => continue,
+            Err(e) => {
+                job.record_error(format!("{}: {}", path.display(), e));
+                continue;
+            }
         };
         
         // Update scan statistics
-        result.total_files += 1;
-        result.total_size += metadata.len();
+        job.record_file(metadata.len());
+        local.total_files += 1;
+        let allocated_size = storage::allocated_size_of(&metadata);
+        let (device, inode, link_count) = storage::file_identity(&metadata);
+        if link_count > 1 {
+            own.add_link(storage::modified_timestamp(&metadata));
+            local.hardlinks.entry((device, inode)).or_default().push(LinkedFile {
+                path: path.clone(),
+                size: metadata.len(),
+                allocated_size,
+            });
+        } else {
+            local.total_size += metadata.len();
+            local.total_allocated_size += allocated_size;
+            own.add_file(metadata.len(), allocated_size, storage::modified_timestamp(&metadata));
+        }
         
         // Get file extension and update file types count
         if let Some(ext) = path.extension() {
             let ext_str = ext.to_string_lossy().to_lowercase();
-            *result.file_types.entry(ext_str).or_insert(0) += 1;
+            *local.file_types.entry(ext_str).or_insert(0) += 1;
+        }
+        
+        seen.push(path.clone());
+        
+        // Reuse the previous entry when size, mtime and inode all match
+        if let Some(previous) = context.previous.get(&path).filter(|previous| previous.is_unchanged(&metadata)) {
+            // Category rules may have changed since
+            if let Some(recategorized) = recategorize(previous, &metadata, &context.config) {
+                local.metadata.insert(path, recategorized);
+            }
+            local.reused_files += 1;
+            continue;
         }
         
         // Analyze the file
-        let file_metadata = analyze_file(path, &metadata);
-        result.metadata.insert(path.to_path_buf(), file_metadata);
+        let file_metadata = analyze_file(&path, &metadata, &context.config);
+        local.analyzed_files += 1;
+        local.metadata.insert(path, file_metadata);
     }
     
-    // Store results
+    local.directories.insert(dir, own);
+    context.seen.lock().unwrap().extend(seen);
+    if !skipped.is_empty() {
+        context.skipped.lock().unwrap().extend(skipped);
+    }
+    context.result.lock().unwrap().merge(local);
+}
+
+/// Counts each hardlinked inode once, against the lexicographically smallest
+/// of its paths, and points the other paths at that one. Entries whose owner
+/// changed since the last scan are queued for saving even if the file itself
+/// did not change.
+///
+/// A walk of only `partial` sees some of an inode's links. When an indexed
+/// link outside it already carries the inode's size, the walked links point
+/// at that one and add nothing, as single updates do in the index.
+fn settle_hardlinks(result: &mut ScanResult, previous: &HashMap<PathBuf, FileMetadata>, partial: Option<&Path>) {
+    let mut stored_owners: HashMap<(u64, u64), &PathBuf> = HashMap::new();
+    if let Some(walked) = partial {
+        let owners = previous.values().filter(|meta| {
+            !meta.is_deleted && meta.link_count > 1 && meta.hardlink_of.is_none() && !meta.path.starts_with(walked)
+        });
+        for meta in owners {
+            let owner = stored_owners.entry((meta.device, meta.inode)).or_insert(&meta.path);
+            *owner = (*owner).min(&meta.path);
+        }
+    }
+    
+    for (inode, mut links) in std::mem::take(&mut result.hardlinks) {
+        links.sort_by(|a, b| a.path.cmp(&b.path));
+        let stored_owner = stored_owners.get(&inode).copied();
+        let owner = &links[0];
+        if stored_owner.is_none() {
+            result.total_size += owner.size;
+            result.total_allocated_size += owner.allocated_size;
+            if let Some(totals) = owner.path.parent().and_then(|parent| result.directories.get_mut(parent)) {
+                totals.size += owner.size;
+                totals.allocated_size += owner.allocated_size;
+            }
+        }
+        let owner = stored_owner.unwrap_or(&owner.path);
+        
+        for (index, link) in links.iter().enumerate() {
+            let hardlink_of = (index > 0 || stored_owner.is_some()).then(|| owner.clone());
+            if let Some(meta) = result.metadata.get_mut(&link.path) {
+                meta.hardlink_of = hardlink_of;
+            } else if let Some(meta) = previous.get(&link.path).filter(|meta| meta.hardlink_of != hardlink_of) {
+                let mut meta = meta.clone();
+                meta.hardlink_of = hardlink_of;
+                result.metadata.insert(link.path.clone(), meta);
+            }
+        }
+    }
+}
+
+/// Turns per-directory totals into recursive ones by adding every directory
+/// below `root` into each of its ancestors up to `root`.
+fn roll_up_directories(directories: &mut HashMap<PathBuf, DirectoryTotals>, root: &Path) {
+    let own: Vec<(PathBuf, DirectoryTotals)> = directories.iter()
+        .map(|(path, totals)| (path.clone(), totals.clone()))
+        .collect();
+    for (path, totals) in own {
+        for ancestor in path.ancestors().skip(1).take_while(|ancestor| ancestor.starts_with(root)) {
+            if let Some(ancestor_totals) = directories.get_mut(ancestor) {
+                ancestor_totals.add_subdirectory(&totals);
+            }
+        }
+    }
+}
+
+/// Reacts to a configuration update. Files that fall under newly added
+/// exclusions are dropped from the index right away rather than lingering
+/// until the next full scan.
+pub fn apply_config_change(old: &Config, new: &Config) {
+    if old.excluded_paths == new.excluded_paths {
+        return;
+    }
+    let config = new.clone();
+    std::thread::spawn(move || prune_excluded(&config));
+}
+
+fn prune_excluded(config: &Config) {
     let config_dir = crate::get_config_dir();
-    let _ = crate::storage::save_scan_result(&config_dir, &result);
+    let metadata = match storage::load_file_metadata(&config_dir) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            eprintln!("Failed to load file metadata for exclusion update: {}", e);
+            return;
+        }
+    };
     
-    result
+    let rules = ExclusionRules::from_config(config);
+    let excluded: Vec<PathBuf> = metadata.into_keys()
+        .filter(|path| rules.is_path_excluded(path, false))
+        .collect();
+    if !excluded.is_empty() {
+        match storage::remove_file_metadata(&config_dir, &excluded) {
+            Ok(removed) => println!("Removed {} newly excluded files from the index", removed),
+            Err(e) => eprintln!("Failed to save file metadata after exclusion update: {}", e),
+        }
+    }
+    
+    // Directory totals above the excluded files stay stale until the next scan
+    let excluded_dirs: Vec<PathBuf> = storage::load_directory_paths(&config_dir)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|path| rules.is_path_excluded(path, true))
+        .collect();
+    if let Err(e) = storage::remove_directories(&config_dir, &excluded_dirs) {
+        eprintln!("Failed to drop excluded directories from the index: {}", e);
+    }
 }
 
-pub fn get_all_drives() -> Vec<PathBuf> {
+/// Roots to scan: one per distinct local filesystem on Linux, see [`MountPlan`].
+pub fn get_all_drives(config: &Config) -> Vec<PathBuf> {
     #[cfg(target_os = "windows")]
     {
+        let _ = config;
         let mut drives = Vec::new();
         for letter in b'A'..=b'Z' {
             let drive = PathBuf::from(format!("{}:\\", letter as char));
@@ -92,6 +506,7 @@ pub fn get_all_drives() -> Vec<PathBuf> {
     }
     #[cfg(target_os = "macos")]
     {
+        let _ = config;
         let mut drives = Vec::new();
         if let Ok(entries) = std::fs::read_dir("/Volumes") {
             for entry in entries.filter_map(|e| e.ok()) {
@@ -102,23 +517,6 @@ pub fn get_all_drives() -> Vec<PathBuf> {
     }
     #[cfg(target_os = "linux")]
     {
-        let mut drives = Vec::new();
-        if let Ok(content) = std::fs::read_to_string("/proc/mounts") {
-            for line in content.lines() {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 2 {
-                    let mount_point = parts[1];
-                    // Only include real block devices, skip pseudo filesystems
-                    if !mount_point.starts_with("/proc") && !mount_point.starts_with("/sys") && !mount_point.starts_with("/dev") && !mount_point.starts_with("/run") {
-                        drives.push(PathBuf::from(mount_point));
-                    }
-                }
-            }
-        }
-        // Add root filesystem if not already present
-        if !drives.iter().any(|p| p == Path::new("/")) {
-            drives.push(PathBuf::from("/"));
-        }
-        drives
+        MountPlan::current(config).roots
     }
 }
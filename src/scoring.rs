@@ -0,0 +1,610 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde_derive::{Serialize, Deserialize};
+use crate::config::Config;
+use crate::exclusion::expand_home;
+use crate::jobs::JobManager;
+use crate::storage::{self, FileMetadata};
+
+/// The model last built by [`Model::for_config`].
+static CURRENT: Mutex<Option<Arc<Model>>> = Mutex::new(None);
+
+/// The `scoring` setting. A file's importance is the sum of each factor's
+/// value times its weight, rounded and clamped to 0-100. Values run from 0
+/// to 1, or from -1 to 1 for `location`; a negative weight turns a factor
+/// around and 0 switches it off.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScoringConfig {
+    /// How recently the file was modified.
+    pub recency: DecayFactor,
+    /// How recently the file was read. Filesystems keep only the last access
+    /// time, so this stands in for how often it is used.
+    pub access: DecayFactor,
+    pub category: CategoryFactor,
+    /// Where the file lives, such as Documents rather than Downloads.
+    pub location: LocationFactor,
+    pub size: SizeFactor,
+    /// 1 for copies of another file.
+    pub duplicate: PlainFactor,
+    /// 1 for files in the work tree of a git repository.
+    pub git: PlainFactor,
+    /// The importance level the AI analysis gave, scaled by its confidence.
+    pub ai: PlainFactor,
+    /// 1 for pinned files and files in pinned directories.
+    pub pin: PlainFactor,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        let location = |paths: &[&str], value| LocationRule {
+            paths: paths.iter().map(|path| path.to_string()).collect(),
+            value,
+        };
+        ScoringConfig {
+            recency: DecayFactor { weight: 30.0, half_life_days: default_half_life() },
+            access: DecayFactor { weight: 10.0, half_life_days: default_half_life() },
+            category: CategoryFactor { weight: 25.0, values: default_category_values() },
+            location: LocationFactor {
+                weight: 20.0,
+                rules: vec![
+                    location(&[
+                        "**/.cache/**", "**/Cache/**", "**/node_modules/**", "**/.Trash/**", "**/.local/share/Trash/**",
+                        "~/Library/Caches/**", "/tmp/**", "/var/tmp/**", "/var/cache/**",
+                    ], -1.0),
+                    location(&["~/Documents/**"], 1.0),
+                    location(&["~/Desktop/**"], 0.75),
+                    location(&["~/Music/**", "~/Pictures/**", "~/Videos/**"], 0.5),
+                    location(&["~/Downloads/**"], -0.25),
+                ],
+            },
+            size: SizeFactor { weight: 5.0, small_bytes: default_small_bytes(), large_bytes: default_large_bytes() },
+            duplicate: PlainFactor { weight: -15.0 },
+            git: PlainFactor { weight: 10.0 },
+            ai: PlainFactor { weight: 15.0 },
+            pin: PlainFactor { weight: 100.0 },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlainFactor {
+    pub weight: f64,
+}
+
+/// 1 for a moment that is now, halving every `half_life_days`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DecayFactor {
+    pub weight: f64,
+    #[serde(default = "default_half_life")]
+    pub half_life_days: f64,
+}
+
+/// Value of each category; a subcategory without one takes its parent's,
+/// and categories without either are 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CategoryFactor {
+    pub weight: f64,
+    #[serde(default = "default_category_values")]
+    pub values: BTreeMap<String, f64>,
+}
+
+/// Value of the first rule whose globs match the path, or 0.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocationFactor {
+    pub weight: f64,
+    #[serde(default)]
+    pub rules: Vec<LocationRule>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocationRule {
+    /// Globs against the full path, with the syntax of category rules.
+    pub paths: Vec<String>,
+    pub value: f64,
+}
+
+/// 0 up to `small_bytes`, 1 from `large_bytes`, logarithmic in between.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SizeFactor {
+    pub weight: f64,
+    #[serde(default = "default_small_bytes")]
+    pub small_bytes: u64,
+    #[serde(default = "default_large_bytes")]
+    pub large_bytes: u64,
+}
+
+fn default_half_life() -> f64 {
+    30.0
+}
+
+fn default_small_bytes() -> u64 {
+    4 * 1024
+}
+
+fn default_large_bytes() -> u64 {
+    1024 * 1024 * 1024
+}
+
+fn default_category_values() -> BTreeMap<String, f64> {
+    [
+        ("document", 1.0),
+        ("spreadsheet", 0.8),
+        ("presentation", 0.8),
+        ("image", 0.6),
+        ("video", 0.6),
+        ("audio", 0.4),
+        ("application", 0.2),
+    ]
+        .into_iter()
+        .map(|(category, value)| (category.to_string(), value))
+        .collect()
+}
+
+impl ScoringConfig {
+    /// Returns the setting path and error message of every invalid value.
+    pub fn errors(&self) -> Vec<(String, String)> {
+        let mut errors = Vec::new();
+        let weights = [
+            ("recency", self.recency.weight),
+            ("access", self.access.weight),
+            ("category", self.category.weight),
+            ("location", self.location.weight),
+            ("size", self.size.weight),
+            ("duplicate", self.duplicate.weight),
+            ("git", self.git.weight),
+            ("ai", self.ai.weight),
+            ("pin", self.pin.weight),
+        ];
+        for (factor, weight) in weights {
+            if !(-100.0..=100.0).contains(&weight) {
+                errors.push((format!("{}.weight", factor), "must be between -100 and 100".to_string()));
+            }
+        }
+        for (factor, decay) in [("recency", &self.recency), ("access", &self.access)] {
+            if !(decay.half_life_days.is_finite() && decay.half_life_days > 0.0) {
+                errors.push((format!("{}.half_life_days", factor), "must be greater than 0".to_string()));
+            }
+        }
+        for (category, value) in &self.category.values {
+            if !(0.0..=1.0).contains(value) {
+                errors.push((format!("category.values.{}", category), "must be between 0 and 1".to_string()));
+            }
+        }
+        for (index, rule) in self.location.rules.iter().enumerate() {
+            let message = match compile_paths(&rule.paths) {
+                Err(e) => e,
+                Ok(_) if !(-1.0..=1.0).contains(&rule.value) => "value must be between -1 and 1".to_string(),
+                Ok(_) => continue,
+            };
+            errors.push((format!("location.rules[{}]", index), message));
+        }
+        if self.size.small_bytes == 0 || self.size.small_bytes >= self.size.large_bytes {
+            errors.push(("size".to_string(), "small_bytes must be at least 1 and below large_bytes".to_string()));
+        }
+        errors
+    }
+}
+
+fn compile_paths(patterns: &[String]) -> Result<GlobSet, String> {
+    if patterns.is_empty() {
+        return Err("paths must not be empty".to_string());
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(&expand_home(pattern))
+            .literal_separator(true)
+            .build()
+            .map_err(|e| e.to_string())?;
+        builder.add(glob);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+/// How a file scores, factor by factor, as served by `/importance`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub path: PathBuf,
+    pub score: u8,
+    /// Sum of the points before rounding and clamping.
+    pub total: f64,
+    pub factors: Vec<Contribution>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Contribution {
+    pub factor: &'static str,
+    pub weight: f64,
+    pub value: f64,
+    /// `weight * value`.
+    pub points: f64,
+    pub reason: String,
+}
+
+/// What one factor found in a file.
+struct Reading {
+    value: f64,
+    reason: String,
+}
+
+/// One signal of the model. Adding a factor means implementing this,
+/// giving it a field in [`ScoringConfig`] and a place in [`Model::new`].
+trait Factor: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn weight(&self) -> f64;
+    fn evaluate(&self, file: &FileMetadata, now: DateTime<Utc>) -> Reading;
+}
+
+/// The factors built from a [`ScoringConfig`] and the stored pins.
+pub struct Model {
+    settings: ScoringConfig,
+    factors: Vec<Box<dyn Factor>>,
+}
+
+impl Model {
+    /// The model for `config`, built again only when its settings change.
+    pub fn for_config(config: &Config) -> Arc<Model> {
+        let mut current = CURRENT.lock().unwrap();
+        match current.as_ref() {
+            Some(model) if model.settings == config.scoring => model.clone(),
+            _ => {
+                let model = Arc::new(Model::new(&config.scoring));
+                *current = Some(model.clone());
+                model
+            },
+        }
+    }
+
+    /// Builds the model afresh, picking up pin changes and repositories
+    /// created since the last build.
+    pub fn rebuild(config: &Config) -> Arc<Model> {
+        let model = Arc::new(Model::new(&config.scoring));
+        *CURRENT.lock().unwrap() = Some(model.clone());
+        model
+    }
+
+    fn new(settings: &ScoringConfig) -> Self {
+        let pins = match storage::load_pins(&crate::get_config_dir()) {
+            Ok(pins) => pins.into_iter().map(|pin| pin.path).collect(),
+            Err(e) => {
+                eprintln!("Failed to load pins: {}", e);
+                Vec::new()
+            }
+        };
+        let locations = settings.location.rules.iter()
+            .filter_map(|rule| Some((compile_paths(&rule.paths).ok()?, rule.clone())))
+            .collect();
+        let factors: Vec<Box<dyn Factor>> = vec![
+            Box::new(Decay {
+                name: "recency",
+                settings: settings.recency.clone(),
+                time: |file| file.modified,
+                verb: "modified",
+            }),
+            Box::new(Decay {
+                name: "access",
+                settings: settings.access.clone(),
+                time: |file| file.last_accessed,
+                verb: "last accessed",
+            }),
+            Box::new(Category(settings.category.clone())),
+            Box::new(Location { weight: settings.location.weight, rules: locations }),
+            Box::new(Size(settings.size.clone())),
+            Box::new(Duplicate(settings.duplicate.weight)),
+            Box::new(Git { weight: settings.git.weight, repositories: Mutex::new(HashMap::new()) }),
+            Box::new(Ai(settings.ai.weight)),
+            Box::new(Pin { weight: settings.pin.weight, pins }),
+        ];
+        Model { settings: settings.clone(), factors }
+    }
+
+    pub fn explain(&self, file: &FileMetadata, now: DateTime<Utc>) -> Explanation {
+        let factors: Vec<Contribution> = self.factors.iter()
+            .map(|factor| {
+                let weight = factor.weight();
+                let reading = match weight == 0.0 {
+                    true => Reading { value: 0.0, reason: "switched off".to_string() },
+                    false => factor.evaluate(file, now),
+                };
+                Contribution {
+                    factor: factor.name(),
+                    weight,
+                    value: reading.value,
+                    // Not -0 for negative weights
+                    points: if reading.value == 0.0 { 0.0 } else { weight * reading.value },
+                    reason: reading.reason,
+                }
+            })
+            .collect();
+        let total = factors.iter().map(|contribution| contribution.points).sum::<f64>();
+        Explanation {
+            path: file.path.clone(),
+            score: total.round().clamp(0.0, 100.0) as u8,
+            total,
+            factors,
+        }
+    }
+
+    pub fn score(&self, file: &FileMetadata, now: DateTime<Utc>) -> u8 {
+        self.explain(file, now).score
+    }
+}
+
+struct Decay {
+    name: &'static str,
+    settings: DecayFactor,
+    time: fn(&FileMetadata) -> DateTime<Utc>,
+    verb: &'static str,
+}
+
+impl Factor for Decay {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn weight(&self) -> f64 {
+        self.settings.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, now: DateTime<Utc>) -> Reading {
+        // Times in the future count as now
+        let days = ((now - (self.time)(file)).num_seconds() as f64 / 86_400.0).max(0.0);
+        let reason = match days.floor() as u64 {
+            0 => format!("{} today", self.verb),
+            1 => format!("{} 1 day ago", self.verb),
+            days => format!("{} {} days ago", self.verb, days),
+        };
+        Reading { value: 0.5_f64.powf(days / self.settings.half_life_days), reason }
+    }
+}
+
+struct Category(CategoryFactor);
+
+impl Factor for Category {
+    fn name(&self) -> &'static str {
+        "category"
+    }
+
+    fn weight(&self) -> f64 {
+        self.0.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        let mut category = file.category.as_str();
+        loop {
+            if let Some(value) = self.0.values.get(category) {
+                return Reading { value: *value, reason: format!("category {}", category) };
+            }
+            match category.rsplit_once('/') {
+                Some((parent, _)) => category = parent,
+                None => return Reading { value: 0.0, reason: format!("no value for category {}", file.category) },
+            }
+        }
+    }
+}
+
+struct Location {
+    weight: f64,
+    rules: Vec<(GlobSet, LocationRule)>,
+}
+
+impl Factor for Location {
+    fn name(&self) -> &'static str {
+        "location"
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        for (globs, rule) in &self.rules {
+            if let Some(index) = globs.matches(&file.path).first() {
+                return Reading { value: rule.value, reason: format!("matches {}", rule.paths[*index]) };
+            }
+        }
+        Reading { value: 0.0, reason: "no location rule matches".to_string() }
+    }
+}
+
+struct Size(SizeFactor);
+
+impl Factor for Size {
+    fn name(&self) -> &'static str {
+        "size"
+    }
+
+    fn weight(&self) -> f64 {
+        self.0.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        let (small, large) = (self.0.small_bytes as f64, self.0.large_bytes as f64);
+        let value = if file.size == 0 {
+            0.0
+        } else {
+            ((file.size as f64).ln() - small.ln()) / (large.ln() - small.ln())
+        };
+        Reading { value: value.clamp(0.0, 1.0), reason: format!("{} bytes", file.size) }
+    }
+}
+
+struct Duplicate(f64);
+
+impl Factor for Duplicate {
+    fn name(&self) -> &'static str {
+        "duplicate"
+    }
+
+    fn weight(&self) -> f64 {
+        self.0
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        match &file.duplicate_of {
+            Some(original) if file.is_duplicate => Reading { value: 1.0, reason: format!("copy of {}", original.display()) },
+            _ => Reading { value: 0.0, reason: "not a copy".to_string() },
+        }
+    }
+}
+
+struct Git {
+    weight: f64,
+    /// Repository each directory seen so far belongs to, if any. Every scan
+    /// starts from a new model, so this only grows for the length of one.
+    repositories: Mutex<HashMap<PathBuf, Option<PathBuf>>>,
+}
+
+impl Git {
+    /// The lock is only held for lookups and inserts, never across a stat,
+    /// so that scan workers scoring files in parallel do not queue up on it.
+    fn repository_of(&self, path: &Path) -> Option<PathBuf> {
+        let mut unknown = Vec::new();
+        let mut repository = None;
+        for dir in path.ancestors().skip(1) {
+            let known = self.repositories.lock().unwrap().get(dir).cloned();
+            if let Some(known) = known {
+                repository = known;
+                break;
+            }
+            unknown.push(dir);
+            // A file rather than a directory in worktrees and submodules
+            if dir.join(".git").exists() {
+                repository = Some(dir.to_path_buf());
+                break;
+            }
+        }
+        if !unknown.is_empty() {
+            let mut repositories = self.repositories.lock().unwrap();
+            for dir in unknown {
+                repositories.insert(dir.to_path_buf(), repository.clone());
+            }
+        }
+        repository
+    }
+}
+
+impl Factor for Git {
+    fn name(&self) -> &'static str {
+        "git"
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        if file.path.components().any(|component| component == Component::Normal(".git".as_ref())) {
+            return Reading { value: 0.0, reason: "part of a repository's own data".to_string() };
+        }
+        match self.repository_of(&file.path) {
+            Some(repository) => Reading { value: 1.0, reason: format!("in the repository at {}", repository.display()) },
+            None => Reading { value: 0.0, reason: "not in a git repository".to_string() },
+        }
+    }
+}
+
+struct Ai(f64);
+
+impl Factor for Ai {
+    fn name(&self) -> &'static str {
+        "ai"
+    }
+
+    fn weight(&self) -> f64 {
+        self.0
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        let Some(analysis) = &file.ai_analysis else {
+            return Reading { value: 0.0, reason: "not analyzed by AI".to_string() };
+        };
+        let level = match analysis.importance_level.trim().to_lowercase().as_str() {
+            "high" => 1.0,
+            "medium" => 0.5,
+            "low" => 0.0,
+            _ => {
+                return Reading {
+                    value: 0.0,
+                    reason: format!("AI rating '{}' is not low, medium or high", analysis.importance_level),
+                }
+            }
+        };
+        let confidence = f64::from(analysis.confidence_score).clamp(0.0, 1.0);
+        Reading {
+            value: level * confidence,
+            reason: format!("AI rated it {} with confidence {:.2}", analysis.importance_level, confidence),
+        }
+    }
+}
+
+struct Pin {
+    weight: f64,
+    pins: Vec<PathBuf>,
+}
+
+impl Factor for Pin {
+    fn name(&self) -> &'static str {
+        "pin"
+    }
+
+    fn weight(&self) -> f64 {
+        self.weight
+    }
+
+    fn evaluate(&self, file: &FileMetadata, _now: DateTime<Utc>) -> Reading {
+        match self.pins.iter().find(|pin| file.path.starts_with(pin)) {
+            Some(pin) if *pin == file.path => Reading { value: 1.0, reason: "pinned".to_string() },
+            Some(pin) => Reading { value: 1.0, reason: format!("pinned through {}", pin.display()) },
+            None => Reading { value: 0.0, reason: "not pinned".to_string() },
+        }
+    }
+}
+
+/// Importance of `file` under the current settings and pins.
+pub fn importance(file: &FileMetadata, config: &Config) -> u8 {
+    Model::for_config(config).score(file, Utc::now())
+}
+
+pub fn explain(file: &FileMetadata, config: &Config) -> Explanation {
+    Model::for_config(config).explain(file, Utc::now())
+}
+
+/// Scores the whole stored index again and saves the entries whose score
+/// changed. Scores drift as files age, and duplicates, pins and settings
+/// change without the files being analyzed again. Returns the number of
+/// entries updated.
+pub fn update_index(config_dir: &Path, config: &Config) -> io::Result<usize> {
+    let model = Model::rebuild(config);
+    let now = Utc::now();
+    let changed: Vec<(PathBuf, u8)> = storage::load_file_metadata(config_dir)?
+        .into_values()
+        .filter(|meta| !meta.is_deleted)
+        .filter_map(|meta| {
+            let score = model.score(&meta, now);
+            (score != meta.importance_score).then_some((meta.path, score))
+        })
+        .collect();
+    if !changed.is_empty() {
+        storage::set_importance_scores(config_dir, &changed)?;
+    }
+    Ok(changed.len())
+}
+
+/// Reacts to a configuration update by scoring the index under the new
+/// settings.
+pub fn apply_config_change(old: &Config, new: &Config, jobs: &Arc<JobManager>, config: Arc<Mutex<Config>>) {
+    if old.scoring != new.scoring {
+        jobs.rescore(config);
+    }
+}
@@ -0,0 +1,358 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Bytes read from the start of a file to identify it.
+const SNIFF_BYTES: u64 = 8 * 1024;
+/// Archives with more entries than this are reported as plain ZIP rather
+/// than searched for a document layout.
+const MAX_ZIP_ENTRIES: usize = 10_000;
+
+pub const OCTET_STREAM: &str = "application/octet-stream";
+const ZIP: &str = "application/zip";
+const OLE_STORAGE: &str = "application/x-ole-storage";
+
+/// Result of looking at a file's first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sniffed {
+    /// A binary signature identified the format.
+    Format(&'static str),
+    /// No signature, but the bytes read look like text.
+    Text,
+    /// Neither: binary data of an unknown format.
+    Unknown,
+}
+
+/// Identifies the file from its leading bytes, looking inside ZIP containers
+/// to tell office documents, Java archives and the like from plain archives.
+pub fn sniff(path: &Path) -> io::Result<Sniffed> {
+    let mut head = Vec::with_capacity(SNIFF_BYTES as usize);
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut head)?;
+    Ok(match magic(&head) {
+        Some(ZIP) => Sniffed::Format(zip_container(path).unwrap_or(ZIP)),
+        Some(mime) => Sniffed::Format(mime),
+        None if looks_like_text(&head) => Sniffed::Text,
+        None => Sniffed::Unknown,
+    })
+}
+
+/// Binary signatures, checked in order.
+fn magic(head: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, signature: &[u8]| head.get(offset..offset + signature.len()) == Some(signature);
+
+    let mime = match head {
+        _ if at(0, b"%PDF-") => "application/pdf",
+        _ if at(0, b"\x89PNG\r\n\x1a\n") => "image/png",
+        _ if at(0, b"\xff\xd8\xff") => "image/jpeg",
+        _ if at(0, b"GIF87a") || at(0, b"GIF89a") => "image/gif",
+        _ if at(0, b"RIFF") && at(8, b"WEBP") => "image/webp",
+        _ if at(0, b"RIFF") && at(8, b"WAVE") => "audio/wav",
+        _ if at(0, b"RIFF") && at(8, b"AVI ") => "video/x-msvideo",
+        _ if at(0, b"II*\0") || at(0, b"MM\0*") => "image/tiff",
+        _ if at(0, b"8BPS") => "image/vnd.adobe.photoshop",
+        _ if at(4, b"ftyp") => iso_media(head.get(8..12).unwrap_or_default()),
+        _ if at(0, b"\x1a\x45\xdf\xa3") => {
+            if head.windows(4).take(64).any(|window| window == b"webm") { "video/webm" } else { "video/x-matroska" }
+        },
+        _ if at(0, b"OggS") => "audio/ogg",
+        _ if at(0, b"fLaC") => "audio/flac",
+        _ if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") || at(0, b"\xff\xf2") => "audio/mpeg",
+        _ if at(0, b"\x7fELF") => "application/x-elf",
+        _ if at(0, b"\xfe\xed\xfa\xce") || at(0, b"\xfe\xed\xfa\xcf")
+            || at(0, b"\xce\xfa\xed\xfe") || at(0, b"\xcf\xfa\xed\xfe") => "application/x-mach-binary",
+        // Universal Mach-O binaries and Java classes share a signature; the
+        // next word is a small architecture count in the former
+        _ if at(0, b"\xca\xfe\xba\xbe") => match head.get(4..8) {
+            Some(&[0, 0, 0, count]) if count < 20 => "application/x-mach-binary",
+            _ => "application/java-vm",
+        },
+        _ if at(0, b"MZ") => "application/vnd.microsoft.portable-executable",
+        _ if at(0, b"\0asm") => "application/wasm",
+        _ if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") => ZIP,
+        _ if at(0, b"\x1f\x8b") => "application/gzip",
+        _ if at(0, b"BZh") => "application/x-bzip2",
+        _ if at(0, b"\xfd7zXZ\0") => "application/x-xz",
+        _ if at(0, b"\x28\xb5\x2f\xfd") => "application/zstd",
+        _ if at(0, b"7z\xbc\xaf\x27\x1c") => "application/x-7z-compressed",
+        _ if at(0, b"Rar!\x1a\x07") => "application/vnd.rar",
+        _ if at(257, b"ustar") => "application/x-tar",
+        _ if at(0, b"SQLite format 3\0") => "application/vnd.sqlite3",
+        _ if at(0, b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1") => OLE_STORAGE,
+        _ if at(0, b"{\\rtf") => "application/rtf",
+        _ if at(0, b"%!PS") => "application/postscript",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// ISO base media files (MP4 and relatives) by their major brand.
+fn iso_media(brand: &[u8]) -> &'static str {
+    match brand {
+        b"qt  " => "video/quicktime",
+        b"M4A " | b"M4B " => "audio/mp4",
+        b"heic" | b"heix" | b"mif1" | b"msf1" => "image/heic",
+        b"avif" => "image/avif",
+        b"3gp4" | b"3gp5" | b"3gp6" => "video/3gpp",
+        _ => "video/mp4",
+    }
+}
+
+/// Tells ZIP-based formats apart by the entries they are required to have.
+fn zip_container(path: &Path) -> Option<&'static str> {
+    let mut archive = zip::ZipArchive::new(File::open(path).ok()?).ok()?;
+    if archive.len() > MAX_ZIP_ENTRIES {
+        return None;
+    }
+    // ODF and EPUB name their type in an uncompressed `mimetype` entry
+    if let Ok(mut entry) = archive.by_name("mimetype") {
+        let mut declared = String::new();
+        entry.by_ref().take(128).read_to_string(&mut declared).ok()?;
+        return Some(match declared.trim() {
+            "application/vnd.oasis.opendocument.text" => "application/vnd.oasis.opendocument.text",
+            "application/vnd.oasis.opendocument.spreadsheet" => "application/vnd.oasis.opendocument.spreadsheet",
+            "application/vnd.oasis.opendocument.presentation" => "application/vnd.oasis.opendocument.presentation",
+            "application/vnd.oasis.opendocument.graphics" => "application/vnd.oasis.opendocument.graphics",
+            "application/epub+zip" => "application/epub+zip",
+            _ => ZIP,
+        });
+    }
+    let has = |name: &str| archive.index_for_name(name).is_some();
+    let mime = match () {
+        _ if has("word/document.xml") => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        _ if has("xl/workbook.xml") => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        _ if has("ppt/presentation.xml") => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        _ if has("AndroidManifest.xml") => "application/vnd.android.package-archive",
+        _ if has("META-INF/MANIFEST.MF") => "application/java-archive",
+        _ => ZIP,
+    };
+    Some(mime)
+}
+
+/// Text in any ASCII-compatible encoding: no NUL bytes, and UTF-8 apart
+/// from a sequence cut off at the end of the sample.
+fn looks_like_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Whether files of this type are text, as opposed to a binary format.
+pub fn is_text(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json" | "application/xml" | "application/yaml" | "application/toml"
+                | "application/sql" | "application/x-sh" | "application/rtf"
+                | "application/postscript" | "image/svg+xml"
+        )
+}
+
+/// MIME type conventionally used for files with this extension.
+pub fn mime_for_extension(extension: &str) -> Option<&'static str> {
+    let mime = match extension {
+        // Images
+        "jpg" | "jpeg" | "jpe" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "tif" | "tiff" => "image/tiff",
+        "heic" | "heif" => "image/heic",
+        "avif" => "image/avif",
+        "svg" => "image/svg+xml",
+        "ico" => "image/vnd.microsoft.icon",
+        "psd" => "image/vnd.adobe.photoshop",
+        // Audio
+        "mp3" => "audio/mpeg",
+        "wav" => "audio/wav",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "flac" => "audio/flac",
+        "aac" => "audio/aac",
+        "m4a" | "m4b" => "audio/mp4",
+        // Video
+        "mp4" | "m4v" => "video/mp4",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "mkv" => "video/x-matroska",
+        "webm" => "video/webm",
+        "wmv" => "video/x-ms-wmv",
+        "flv" => "video/x-flv",
+        "3gp" => "video/3gpp",
+        // Documents
+        "pdf" => "application/pdf",
+        "txt" | "text" | "log" => "text/plain",
+        "md" | "markdown" => "text/markdown",
+        "rtf" => "application/rtf",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "odt" => "application/vnd.oasis.opendocument.text",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ods" => "application/vnd.oasis.opendocument.spreadsheet",
+        "csv" => "text/csv",
+        "tsv" => "text/tab-separated-values",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "odp" => "application/vnd.oasis.opendocument.presentation",
+        "odg" => "application/vnd.oasis.opendocument.graphics",
+        "epub" => "application/epub+zip",
+        "ps" | "eps" => "application/postscript",
+        // Archives
+        "zip" => ZIP,
+        "gz" | "tgz" => "application/gzip",
+        "bz2" | "tbz2" => "application/x-bzip2",
+        "xz" | "txz" => "application/x-xz",
+        "zst" => "application/zstd",
+        "7z" => "application/x-7z-compressed",
+        "rar" => "application/vnd.rar",
+        "tar" => "application/x-tar",
+        "jar" => "application/java-archive",
+        "apk" => "application/vnd.android.package-archive",
+        // Executables and binaries
+        "exe" | "dll" => "application/vnd.microsoft.portable-executable",
+        "so" | "o" => "application/x-elf",
+        "dylib" => "application/x-mach-binary",
+        "class" => "application/java-vm",
+        "wasm" => "application/wasm",
+        "msi" => OLE_STORAGE,
+        "deb" => "application/vnd.debian.binary-package",
+        "rpm" => "application/x-rpm",
+        "dmg" => "application/x-apple-diskimage",
+        "iso" => "application/x-iso9660-image",
+        "db" | "sqlite" | "sqlite3" => "application/vnd.sqlite3",
+        // Source code and markup
+        "rs" => "text/x-rust",
+        "c" | "h" => "text/x-c",
+        "cc" | "cpp" | "cxx" | "hpp" => "text/x-c++",
+        "go" => "text/x-go",
+        "java" => "text/x-java",
+        "kt" => "text/x-kotlin",
+        "py" => "text/x-python",
+        "rb" => "text/x-ruby",
+        "js" | "mjs" | "cjs" => "text/javascript",
+        "ts" => "text/x-typescript",
+        "sh" | "bash" => "application/x-sh",
+        "html" | "htm" => "text/html",
+        "css" => "text/css",
+        "xml" => "application/xml",
+        "json" => "application/json",
+        "yaml" | "yml" => "application/yaml",
+        "toml" => "application/toml",
+        "ini" | "cfg" | "conf" => "text/plain",
+        "sql" => "application/sql",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+/// Whether content detected as `sniffed` may carry an extension meaning
+/// `expected`. Generic containers are compatible with the formats built on
+/// them, so a `.jar` that is a plain ZIP is fine, but a `.docx` that turns out
+/// to be a spreadsheet is not.
+pub fn is_compatible(expected: &str, sniffed: &str) -> bool {
+    // Password-protected Office Open XML documents are stored in an OLE container
+    if expected == sniffed || (sniffed == OLE_STORAGE && expected.starts_with("application/vnd.openxmlformats-officedocument.")) {
+        return true;
+    }
+    match (family(expected), family(sniffed)) {
+        (Some((expected_family, expected_generic)), Some((sniffed_family, sniffed_generic))) => {
+            expected_family == sniffed_family && (expected_generic || sniffed_generic)
+        },
+        _ => false,
+    }
+}
+
+/// Container family of a MIME type, and whether it is the family's generic
+/// member.
+fn family(mime: &str) -> Option<(&'static str, bool)> {
+    let family = match mime {
+        ZIP => ("zip", true),
+        "application/java-archive"
+        | "application/vnd.android.package-archive"
+        | "application/epub+zip" => ("zip", false),
+        _ if mime.starts_with("application/vnd.openxmlformats-officedocument.")
+            || mime.starts_with("application/vnd.oasis.opendocument.") => ("zip", false),
+        OLE_STORAGE => ("ole", true),
+        "application/msword" | "application/vnd.ms-excel" | "application/vnd.ms-powerpoint" => ("ole", false),
+        // Players handle all of these alike, and brands are used loosely
+        "video/mp4" | "video/quicktime" | "audio/mp4" | "video/3gpp" => ("iso-media", true),
+        "video/x-matroska" | "video/webm" => ("matroska", true),
+        "audio/ogg" => ("ogg", true),
+        _ => return None,
+    };
+    Some(family)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::test_support::TempDir;
+
+    fn zip_with(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, contents) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn recognizes_signatures() {
+        assert_eq!(magic(b"%PDF-1.7\n"), Some("application/pdf"));
+        assert_eq!(magic(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(magic(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(magic(b"\0\0\0\x20ftypqt  \0\0\0\0"), Some("video/quicktime"));
+        assert_eq!(magic(b"\0\0\0\x20ftypheic\0\0\0\0"), Some("image/heic"));
+        assert_eq!(magic(b"\0\0\0\x20ftypisom\0\0\0\0"), Some("video/mp4"));
+        assert_eq!(magic(b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01\x42\x82\x84webm"), Some("video/webm"));
+        assert_eq!(magic(b"\xca\xfe\xba\xbe\0\0\0\x02"), Some("application/x-mach-binary"));
+        assert_eq!(magic(b"\xca\xfe\xba\xbe\0\0\0\x34"), Some("application/java-vm"));
+        let mut tar = vec![0; 512];
+        tar[257..262].copy_from_slice(b"ustar");
+        assert_eq!(magic(&tar), Some("application/x-tar"));
+        assert_eq!(magic(b"hello"), None);
+    }
+
+    #[test]
+    fn tells_text_from_binary() {
+        assert!(looks_like_text("grüße\n".as_bytes()));
+        // A multibyte character cut off by the sample size
+        assert!(looks_like_text(&"grüße".as_bytes()[..3]));
+        assert!(!looks_like_text(b"text\0with nul"));
+        assert!(!looks_like_text(b"\xff\xfe invalid"));
+    }
+
+    #[test]
+    fn looks_inside_zip_containers() {
+        let dir = TempDir::new();
+        let docx = dir.file("report.docx", &zip_with(&[("[Content_Types].xml", b"<Types/>"), ("word/document.xml", b"<w:document/>")]));
+        let odt = dir.file("letter.odt", &zip_with(&[("mimetype", b"application/vnd.oasis.opendocument.text"), ("content.xml", b"<office/>")]));
+        let plain = dir.file("notes.zip", &zip_with(&[("notes.txt", b"hello")]));
+
+        assert_eq!(
+            sniff(&docx).unwrap(),
+            Sniffed::Format("application/vnd.openxmlformats-officedocument.wordprocessingml.document")
+        );
+        assert_eq!(sniff(&odt).unwrap(), Sniffed::Format("application/vnd.oasis.opendocument.text"));
+        assert_eq!(sniff(&plain).unwrap(), Sniffed::Format(ZIP));
+    }
+
+    #[test]
+    fn containers_are_compatible_with_their_formats() {
+        assert!(is_compatible("application/java-archive", ZIP));
+        assert!(is_compatible("application/vnd.openxmlformats-officedocument.wordprocessingml.document", OLE_STORAGE));
+        assert!(is_compatible("video/mp4", "video/quicktime"));
+        assert!(!is_compatible(
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+        ));
+        assert!(!is_compatible("image/jpeg", "image/png"));
+    }
+}
@@ -1,12 +1,17 @@
 use std::path::{Path, PathBuf};
 use std::collections::HashMap;
 use serde_derive::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use std::fs;
 use std::io;
+use std::sync::Mutex;
+use std::time::Duration;
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction, TransactionBehavior};
 use crate::ai_integration::AIAnalysisResult;
+use crate::extract::ExtendedMetadata;
+use crate::persist;
 use crate::scanner::ScanResult;
-use serde_json;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileMetadata {
@@ -14,127 +19,1406 @@ pub struct FileMetadata {
     pub file_name: String,
     pub extension: String,
     pub size: u64,
+    /// Space the file occupies on disk, which differs from `size` for sparse
+    /// and compressed files.
+    #[serde(default)]
+    pub allocated_size: u64,
     #[serde(with = "chrono::serde::ts_seconds")]
     pub created: DateTime<Utc>,
-    #[serde(with = "chrono::serde::ts_seconds")]
+    /// Kept to the nanosecond, so that a rewrite within the same second still
+    /// counts as a change.
+    #[serde(with = "precise_time")]
     pub modified: DateTime<Utc>,
     pub category: String,
     pub mime_type: String,
+    /// The file's content is of a different type than its extension claims.
+    #[serde(default)]
+    pub extension_mismatch: bool,
     pub importance_score: u8,
     #[serde(with = "chrono::serde::ts_seconds")]
     pub last_accessed: DateTime<Utc>,
     pub is_duplicate: bool,
     pub duplicate_of: Option<PathBuf>,
     pub ai_analysis: Option<AIAnalysisResult>,
+    /// Format-specific details such as a photo's EXIF data.
+    #[serde(default)]
+    pub extended: Option<ExtendedMetadata>,
+    #[serde(default)]
+    pub device: u64,
+    #[serde(default)]
+    pub inode: u64,
+    /// Number of hardlinks to the inode, this path included.
+    #[serde(default)]
+    pub link_count: u64,
+    /// Path of the link that carries the inode's size when several indexed
+    /// paths share it: the lexicographically smallest one. `None` for that
+    /// path itself and for files with a single link.
+    #[serde(default)]
+    pub hardlink_of: Option<PathBuf>,
+    /// Full-content hash, computed only for files that share their size and
+    /// partial hash with another file.
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Set when a rescan no longer finds the file; the entry is kept as a tombstone.
+    #[serde(default)]
+    pub is_deleted: bool,
+    /// [`crate::analyzer::ANALYSIS_VERSION`] of the analysis that produced the entry.
+    #[serde(default)]
+    pub analysis_version: u32,
+}
+
+impl FileMetadata {
+    /// Whether the file on disk still matches this entry, so a rescan can
+    /// reuse it instead of analyzing the file again.
+    pub fn is_unchanged(&self, system_metadata: &fs::Metadata) -> bool {
+        !self.is_deleted
+            && self.analysis_version == crate::analyzer::ANALYSIS_VERSION
+            && self.size == system_metadata.len()
+            && self.allocated_size == allocated_size_of(system_metadata)
+            && (self.device, self.inode, self.link_count) == file_identity(system_metadata)
+            && system_metadata.modified()
+                .map(|modified| DateTime::<Utc>::from(modified) == self.modified)
+                .unwrap_or(false)
+    }
+}
+
+/// Serde for [`FileMetadata::modified`]: RFC 3339 with nanoseconds. Entries
+/// written by older builds hold whole Unix seconds and still load; they no
+/// longer match the file's time and are analyzed again on the next scan.
+mod precise_time {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    #[derive(serde_derive::Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Precise(DateTime<Utc>),
+        Seconds(i64),
+    }
+
+    pub fn serialize<S: Serializer>(time: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&time.to_rfc3339_opts(SecondsFormat::Nanos, true))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        match Stored::deserialize(deserializer)? {
+            Stored::Precise(time) => Ok(time),
+            Stored::Seconds(seconds) => DateTime::from_timestamp(seconds, 0)
+                .ok_or_else(|| serde::de::Error::custom(format!("timestamp {} is out of range", seconds))),
+        }
+    }
+}
+
+/// Which of a file's two sizes to report, sum and sort by.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SizeMode {
+    /// The length of the file's contents.
+    #[default]
+    Apparent,
+    /// The blocks allocated for it on disk.
+    Allocated,
+}
+
+/// Recursive totals of one directory.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DirectoryTotals {
+    pub size: u64,
+    pub allocated_size: u64,
+    pub file_count: u64,
+    /// Subdirectories at any depth.
+    pub dir_count: u64,
+    /// Unix time of the most recently modified file inside, 0 when empty.
+    pub newest_modified: i64,
+}
+
+impl DirectoryTotals {
+    pub fn add_file(&mut self, size: u64, allocated_size: u64, modified: i64) {
+        self.size += size;
+        self.allocated_size += allocated_size;
+        self.file_count += 1;
+        self.newest_modified = self.newest_modified.max(modified);
+    }
+
+    /// Counts a hardlinked file whose bytes are added separately, to the
+    /// directory of the link that carries them.
+    pub fn add_link(&mut self, modified: i64) {
+        self.file_count += 1;
+        self.newest_modified = self.newest_modified.max(modified);
+    }
+
+    pub fn add_subdirectory(&mut self, child: &DirectoryTotals) {
+        self.size += child.size;
+        self.allocated_size += child.allocated_size;
+        self.file_count += child.file_count;
+        self.dir_count += child.dir_count + 1;
+        self.newest_modified = self.newest_modified.max(child.newest_modified);
+    }
+}
+
+pub fn modified_timestamp(system_metadata: &fs::Metadata) -> i64 {
+    system_metadata.modified()
+        .map(|modified| DateTime::<Utc>::from(modified).timestamp())
+        .unwrap_or(0)
+}
+
+/// Allocated size in bytes: `st_blocks` counts 512-byte units regardless of
+/// the filesystem's block size. Platforms without it report the length.
+pub fn allocated_size_of(system_metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        system_metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        system_metadata.len()
+    }
+}
+
+/// Device, inode and link count. Zeros where the platform has no inodes, so
+/// every file counts as a single link there.
+pub fn file_identity(system_metadata: &fs::Metadata) -> (u64, u64, u64) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (system_metadata.dev(), system_metadata.ino(), system_metadata.nlink())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = system_metadata;
+        (0, 0, 0)
+    }
+}
+
+/// SQLite database in the data directory that holds the file index.
+pub const INDEX_FILE_NAME: &str = "index.db";
+pub const STATS_FILE_NAME: &str = "latest_stats.json";
+/// Snapshots kept per scanned root; older ones and their change log are dropped.
+const SNAPSHOT_LIMIT: usize = 500;
+
+/// Whether the index has passed its integrity check in this process.
+static INDEX_VERIFIED: Mutex<bool> = Mutex::new(false);
+
+/// Format `captured_at` is stored in, which sorts chronologically as text.
+const CAPTURED_AT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// Schema steps; entry `n` upgrades a database from `user_version` n to n + 1.
+const MIGRATIONS: [&str; 8] = [
+    "
+    CREATE TABLE files (
+        path TEXT PRIMARY KEY,
+        parent TEXT NOT NULL,
+        name TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        category TEXT NOT NULL COLLATE NOCASE,
+        modified INTEGER NOT NULL,
+        importance INTEGER NOT NULL,
+        content_hash TEXT,
+        is_deleted INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX files_parent ON files (parent);
+    CREATE INDEX files_size ON files (size);
+    CREATE INDEX files_category ON files (category);
+    CREATE INDEX files_modified ON files (modified);
+    CREATE INDEX files_content_hash ON files (content_hash);
+    ",
+    "
+    CREATE TABLE snapshots (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        root TEXT NOT NULL,
+        taken_at INTEGER NOT NULL,
+        total_files INTEGER NOT NULL,
+        total_size INTEGER NOT NULL,
+        file_types TEXT NOT NULL
+    );
+    CREATE INDEX snapshots_root ON snapshots (root, taken_at);
+    -- Size changes of indexed files. Rows start without a snapshot and are
+    -- claimed by the next snapshot of the root they fall under.
+    CREATE TABLE file_changes (
+        snapshot_id INTEGER,
+        path TEXT NOT NULL,
+        category TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        old_size INTEGER,
+        new_size INTEGER
+    );
+    CREATE INDEX file_changes_snapshot ON file_changes (snapshot_id);
+    ",
+    "
+    CREATE TABLE directories (
+        path TEXT PRIMARY KEY,
+        parent TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        file_count INTEGER NOT NULL,
+        dir_count INTEGER NOT NULL,
+        newest_modified INTEGER NOT NULL
+    );
+    CREATE INDEX directories_parent ON directories (parent);
+    ",
+    "
+    ALTER TABLE files ADD COLUMN allocated_size INTEGER NOT NULL DEFAULT 0;
+    CREATE INDEX files_allocated_size ON files (allocated_size);
+    ALTER TABLE directories ADD COLUMN allocated_size INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE snapshots ADD COLUMN total_allocated_size INTEGER NOT NULL DEFAULT 0;
+    ",
+    "
+    ALTER TABLE files ADD COLUMN device INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE files ADD COLUMN inode INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE files ADD COLUMN hardlink_of TEXT;
+    CREATE INDEX files_device_inode ON files (device, inode);
+    ",
+    "
+    ALTER TABLE files ADD COLUMN captured_at TEXT;
+    ALTER TABLE files ADD COLUMN width INTEGER;
+    ALTER TABLE files ADD COLUMN height INTEGER;
+    CREATE INDEX files_captured_at ON files (captured_at);
+    CREATE INDEX files_pixels ON files (width * height);
+    ",
+    "
+    ALTER TABLE files ADD COLUMN duration REAL;
+    ALTER TABLE files ADD COLUMN artist TEXT COLLATE NOCASE;
+    ALTER TABLE files ADD COLUMN album TEXT COLLATE NOCASE;
+    CREATE INDEX files_duration ON files (duration);
+    CREATE INDEX files_artist ON files (artist);
+    CREATE INDEX files_album ON files (album);
+    ",
+    "
+    CREATE TABLE pins (
+        path TEXT PRIMARY KEY,
+        pinned_at INTEGER NOT NULL
+    );
+    ",
+];
+const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+fn db_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+/// Opens the index, creating it on first use. A new database imports the
+/// `metadata_chunk_N.json` files written by older builds.
+fn open_index(config_dir: &Path) -> io::Result<Connection> {
+    let data_dir = config_dir.join("data");
+    fs::create_dir_all(&data_dir)?;
+    
+    let index_path = data_dir.join(INDEX_FILE_NAME);
+    let mut verified = INDEX_VERIFIED.lock().unwrap();
+    if !*verified {
+        verify_index(&index_path);
+        *verified = true;
+    }
+    drop(verified);
+    
+    let mut conn = Connection::open(&index_path).map_err(db_error)?;
+    // Parallel drive scans, the watcher and the API all write to the index
+    conn.busy_timeout(Duration::from_secs(30)).map_err(db_error)?;
+    conn.pragma_update(None, "journal_mode", "WAL").map_err(db_error)?;
+    
+    let version: i32 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(db_error)?;
+    if version < SCHEMA_VERSION {
+        create_schema(&mut conn, &data_dir)?;
+    }
+    Ok(conn)
+}
+
+/// Runs SQLite's integrity check on the first open in this process. A damaged
+/// database is quarantined so that a fresh one is created and the next scan
+/// rebuilds the index, instead of every request failing.
+fn verify_index(index_path: &Path) {
+    if !index_path.exists() {
+        return;
+    }
+    let status = Connection::open(index_path)
+        .and_then(|conn| conn.query_row("PRAGMA quick_check", [], |row| row.get::<_, String>(0)));
+    let problem = match status {
+        Ok(status) if status == "ok" => return,
+        Ok(status) => status.lines().next().unwrap_or_default().to_string(),
+        Err(e) => e.to_string(),
+    };
+    persist::quarantine(index_path, &problem);
+    for suffix in ["-wal", "-shm"] {
+        let mut companion = index_path.as_os_str().to_os_string();
+        companion.push(suffix);
+        let _ = fs::remove_file(companion);
+    }
+}
+
+fn create_schema(conn: &mut Connection, data_dir: &Path) -> io::Result<()> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)?;
+    // Another thread may have finished the migration while this one waited
+    let version: i32 = tx.pragma_query_value(None, "user_version", |row| row.get(0)).map_err(db_error)?;
+    if version >= SCHEMA_VERSION {
+        return Ok(());
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        tx.execute_batch(migration).map_err(db_error)?;
+    }
+    
+    // Only a brand-new database can have chunk files left to import
+    let chunk_paths = if version == 0 { legacy_chunk_paths(data_dir)? } else { Vec::new() };
+    let mut imported = 0;
+    let mut unreadable = Vec::new();
+    for chunk_path in &chunk_paths {
+        let chunk: HashMap<String, FileMetadata> = match fs::read_to_string(chunk_path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        {
+            Ok(chunk) => chunk,
+            Err(e) => {
+                unreadable.push((chunk_path, e));
+                continue;
+            }
+        };
+        imported += insert_entries(&tx, chunk.values(), WriteMode::Import)?;
+    }
+    
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION).map_err(db_error)?;
+    tx.commit().map_err(db_error)?;
+    
+    if !chunk_paths.is_empty() {
+        println!("Imported {} files from {} index chunks into {}", imported, chunk_paths.len() - unreadable.len(), INDEX_FILE_NAME);
+        // Chunks that could not be read are kept aside, as the only copy of their entries
+        for (chunk_path, problem) in &unreadable {
+            persist::quarantine(chunk_path, problem);
+        }
+        for chunk_path in chunk_paths.iter().filter(|path| !unreadable.iter().any(|(failed, _)| failed == path)) {
+            let _ = fs::remove_file(chunk_path);
+        }
+    }
+    Ok(())
+}
+
+fn legacy_chunk_paths(data_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(data_dir)? {
+        let path = entry?.path();
+        let is_chunk = path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("metadata_chunk_") && name.ends_with(".json"));
+        if is_chunk && path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(paths)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WriteMode {
+    /// Entries carried over from older storage; nothing changed on disk.
+    Import,
+    /// Entries from a completed scan, which replaces the directory totals
+    /// of its root afterwards.
+    Scan,
+    /// Individual updates that also adjust the totals of every ancestor directory.
+    Update,
+}
+
+/// Writes entries to the index. Except when importing, every entry whose size
+/// differs from what was stored, or that appeared or was tombstoned, is also
+/// recorded in the change log.
+fn insert_entries<'a>(
+    conn: &Connection,
+    entries: impl IntoIterator<Item = &'a FileMetadata>,
+    mode: WriteMode,
+) -> io::Result<usize> {
+    let mut previous_statement = conn.prepare_cached(
+        "SELECT size, allocated_size, is_deleted, hardlink_of IS NOT NULL FROM files WHERE path = ?1",
+    ).map_err(db_error)?;
+    let mut link_statement = conn.prepare_cached(
+        "SELECT min(path) FROM files
+         WHERE device = ?1 AND inode = ?2 AND path != ?3 AND is_deleted = 0 AND hardlink_of IS NULL",
+    ).map_err(db_error)?;
+    let mut change_statement = conn.prepare_cached(
+        "INSERT INTO file_changes (path, category, kind, old_size, new_size) VALUES (?1, ?2, ?3, ?4, ?5)",
+    ).map_err(db_error)?;
+    let mut ancestor_statement = conn.prepare_cached(
+        "UPDATE directories SET size = size + ?2, allocated_size = allocated_size + ?3,
+            file_count = file_count + ?4, newest_modified = max(newest_modified, ?5)
+         WHERE path = ?1",
+    ).map_err(db_error)?;
+    let mut statement = conn.prepare_cached(
+        "INSERT OR REPLACE INTO files
+            (path, parent, name, size, allocated_size, category, modified, importance, content_hash, is_deleted,
+             device, inode, hardlink_of, captured_at, width, height, duration, artist, album, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20)",
+    ).map_err(db_error)?;
+    
+    let mut count = 0;
+    for meta in entries {
+        let path = meta.path.to_string_lossy();
+        let mut linked;
+        let mut meta = meta;
+        // A link that appears outside a full scan joins an inode that is
+        // already counted rather than counting it again
+        if mode == WriteMode::Update && meta.link_count > 1 && meta.hardlink_of.is_none() && !meta.is_deleted {
+            let owner: Option<String> = link_statement
+                .query_row(params![meta.device as i64, meta.inode as i64, path], |row| row.get(0))
+                .map_err(db_error)?;
+            if let Some(owner) = owner {
+                linked = meta.clone();
+                linked.hardlink_of = Some(PathBuf::from(owner));
+                meta = &linked;
+            }
+        }
+        if mode != WriteMode::Import {
+            let previous: Option<(i64, i64, bool, bool)> = previous_statement
+                .query_row(params![path], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))
+                .optional()
+                .map_err(db_error)?;
+            let previous = previous.filter(|(_, _, is_deleted, _)| !is_deleted);
+            let old_size = previous.map(|(size, _, _, _)| size as u64);
+            let new_size = Some(meta.size).filter(|_| !meta.is_deleted);
+            let kind = ChangeKind::between(old_size, new_size);
+            if mode == WriteMode::Update && (kind.is_some() || new_size.is_some()) {
+                // Bytes each side contributes to its directories; only the
+                // link carrying an inode's size counts it
+                let (old_bytes, old_allocated) = match previous {
+                    Some((size, allocated, _, false)) => (size, allocated),
+                    _ => (0, 0),
+                };
+                let (new_bytes, new_allocated) = if meta.is_deleted || meta.hardlink_of.is_some() {
+                    (0, 0)
+                } else {
+                    (meta.size as i64, meta.allocated_size as i64)
+                };
+                let size_delta = new_bytes - old_bytes;
+                let allocated_delta = new_allocated - old_allocated;
+                let count_delta = new_size.is_some() as i64 - old_size.is_some() as i64;
+                let modified = if meta.is_deleted { 0 } else { meta.modified.timestamp() };
+                for ancestor in meta.path.ancestors().skip(1) {
+                    ancestor_statement
+                        .execute(params![ancestor.to_string_lossy(), size_delta, allocated_delta, count_delta, modified])
+                        .map_err(db_error)?;
+                }
+            }
+            if let Some(kind) = kind {
+                change_statement.execute(params![
+                    path,
+                    meta.category,
+                    kind.as_str(),
+                    old_size.map(|size| size as i64),
+                    new_size.map(|size| size as i64),
+                ]).map_err(db_error)?;
+            }
+        }
+        
+        let parent = meta.path.parent().map(|parent| parent.to_string_lossy()).unwrap_or_default();
+        let extended = meta.extended.as_ref();
+        let dimensions = extended.and_then(ExtendedMetadata::dimensions);
+        let media = extended.and_then(ExtendedMetadata::media);
+        statement.execute(params![
+            path,
+            parent,
+            meta.file_name,
+            meta.size as i64,
+            meta.allocated_size as i64,
+            meta.category,
+            meta.modified.timestamp(),
+            meta.importance_score,
+            meta.content_hash,
+            meta.is_deleted,
+            meta.device as i64,
+            meta.inode as i64,
+            meta.hardlink_of.as_ref().map(|owner| owner.to_string_lossy()),
+            extended.and_then(ExtendedMetadata::captured_at).map(|at| at.format(CAPTURED_AT_FORMAT).to_string()),
+            dimensions.map(|(width, _)| width),
+            dimensions.map(|(_, height)| height),
+            media.and_then(|media| media.duration_seconds),
+            media.and_then(|media| media.artist.as_deref()),
+            media.and_then(|media| media.album.as_deref()),
+            serde_json::to_string(meta)?,
+        ]).map_err(db_error)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Starts a transaction that holds the write lock from the beginning. A
+/// deferred one that reads first cannot wait for the lock when it later
+/// writes after another connection has committed; SQLite fails it right away.
+fn begin_write(conn: &mut Connection) -> io::Result<Transaction<'_>> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate).map_err(db_error)
 }
 
-pub fn save_scan_result(config_dir: &Path, result: &ScanResult) -> io::Result<()> {
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<FileMetadata> {
+    let data: String = row.get(0)?;
+    serde_json::from_str(&data)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))
+}
+
+/// Stores a completed scan of `root`: the changed index entries minus the
+/// excluded ones, a snapshot of the scan statistics, and the claim of every
+/// logged change under `root` since its previous snapshot, except those under
+/// roots nested in it.
+pub fn save_scan_result(config_dir: &Path, root: &Path, result: &ScanResult) -> io::Result<()> {
     // Create the data directory if it doesn't exist
     let data_dir = config_dir.join("data");
     fs::create_dir_all(&data_dir)?;
     
     // Save overall statistics
+    let taken_at = Utc::now();
     let stats = serde_json::json!({
-        "timestamp": Utc::now().timestamp(), // changed: using timestamp (i64)
+        "timestamp": taken_at.timestamp(), // changed: using timestamp (i64)
         "total_files": result.total_files,
         "total_size": result.total_size,
+        "total_allocated_size": result.total_allocated_size,
         "file_types": result.file_types,
     });
     
-    let stats_path = data_dir.join("latest_stats.json");
-    fs::write(&stats_path, serde_json::to_string_pretty(&stats)?)?;
+    persist::write_json(&data_dir.join(STATS_FILE_NAME), &stats)?;
     
-    // Save file metadata
-    // Split into chunks to avoid large files
-    let chunk_size = 10000;
-    let mut current_chunk = 0;
-    let mut current_index = 0;
-    
-    for (path, metadata) in &result.metadata {
-        if current_index % chunk_size == 0 {
-            current_chunk += 1;
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    insert_entries(&tx, result.metadata.values(), WriteMode::Scan)?;
+    {
+        let mut statement = tx.prepare_cached("DELETE FROM files WHERE path = ?1").map_err(db_error)?;
+        for path in &result.excluded {
+            statement.execute(params![path.to_string_lossy()]).map_err(db_error)?;
         }
-        
-        let chunk_path = data_dir.join(format!("metadata_chunk_{}.json", current_chunk));
-        
-        // Load existing chunk if it exists
-        let mut chunk_data: HashMap<String, FileMetadata> = if chunk_path.exists() {
-            match fs::read_to_string(&chunk_path) {
-                Ok(content) => serde_json::from_str(&content).unwrap_or_default(),
-                Err(_) => HashMap::new(),
-            }
-        } else {
-            HashMap::new()
-        };
-        
-        // Add metadata to chunk
-        chunk_data.insert(path.to_string_lossy().to_string(), metadata.clone());
-        
-        // Save chunk
-        fs::write(&chunk_path, serde_json::to_string_pretty(&chunk_data)?)?;
-        
-        current_index += 1;
     }
+    replace_directories(&tx, root, &result.nested_roots, &result.directories)?;
+    
+    let root_text = root.to_string_lossy();
+    tx.execute(
+        "INSERT INTO snapshots (root, taken_at, total_files, total_size, total_allocated_size, file_types)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            root_text,
+            taken_at.timestamp(),
+            result.total_files as i64,
+            result.total_size as i64,
+            result.total_allocated_size as i64,
+            serde_json::to_string(&result.file_types)?,
+        ],
+    ).map_err(db_error)?;
+    let snapshot_id = tx.last_insert_rowid();
+    
+    // Changes under nested roots wait for a snapshot of their own root
+    let (below_root, mut values) = below_root_condition(root, &result.nested_roots, 2);
+    values.insert(0, Value::Integer(snapshot_id));
+    tx.execute(
+        &format!("UPDATE file_changes SET snapshot_id = ?1 WHERE snapshot_id IS NULL AND {}", below_root),
+        params_from_iter(values.iter()),
+    ).map_err(db_error)?;
+    
+    tx.execute(
+        "DELETE FROM file_changes WHERE snapshot_id IN
+            (SELECT id FROM snapshots WHERE root = ?1 ORDER BY id DESC LIMIT -1 OFFSET ?2)",
+        params![root_text, SNAPSHOT_LIMIT as i64],
+    ).map_err(db_error)?;
+    tx.execute(
+        "DELETE FROM snapshots WHERE id IN
+            (SELECT id FROM snapshots WHERE root = ?1 ORDER BY id DESC LIMIT -1 OFFSET ?2)",
+        params![root_text, SNAPSHOT_LIMIT as i64],
+    ).map_err(db_error)?;
+    
+    tx.commit().map_err(db_error)
+}
+
+/// Replaces the stored totals of `root` and every directory below it, except
+/// those of `nested` roots and their subdirectories, which other walks own.
+pub fn save_directories(
+    config_dir: &Path,
+    root: &Path,
+    nested: &[PathBuf],
+    directories: &HashMap<PathBuf, DirectoryTotals>,
+) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    replace_directories(&tx, root, nested, directories)?;
+    tx.commit().map_err(db_error)
+}
+
+fn replace_directories(
+    conn: &Connection,
+    root: &Path,
+    nested: &[PathBuf],
+    directories: &HashMap<PathBuf, DirectoryTotals>,
+) -> io::Result<()> {
+    let (below_root, mut values) = below_root_condition(root, nested, 2);
+    values.insert(0, Value::Text(root.to_string_lossy().to_string()));
+    conn.execute(
+        &format!("DELETE FROM directories WHERE path = ?1 OR ({})", below_root),
+        params_from_iter(values.iter()),
+    ).map_err(db_error)?;
     
+    let mut statement = conn.prepare_cached(
+        "INSERT OR REPLACE INTO directories (path, parent, size, allocated_size, file_count, dir_count, newest_modified)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    ).map_err(db_error)?;
+    for (path, totals) in directories {
+        let parent = path.parent().map(|parent| parent.to_string_lossy()).unwrap_or_default();
+        statement.execute(params![
+            path.to_string_lossy(),
+            parent,
+            totals.size as i64,
+            totals.allocated_size as i64,
+            totals.file_count as i64,
+            totals.dir_count as i64,
+            totals.newest_modified,
+        ]).map_err(db_error)?;
+    }
+    Ok(())
+}
+
+fn totals_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<DirectoryTotals> {
+    Ok(DirectoryTotals {
+        size: row.get::<_, i64>(offset)? as u64,
+        allocated_size: row.get::<_, i64>(offset + 1)? as u64,
+        file_count: row.get::<_, i64>(offset + 2)? as u64,
+        dir_count: row.get::<_, i64>(offset + 3)? as u64,
+        newest_modified: row.get(offset + 4)?,
+    })
+}
+
+/// Directory totals and the files in each directory, read through one
+/// connection for walks that visit many directories.
+pub struct DirectoryIndex {
+    conn: Connection,
+}
+
+impl DirectoryIndex {
+    pub fn open(config_dir: &Path) -> io::Result<Self> {
+        Ok(DirectoryIndex { conn: open_index(config_dir)? })
+    }
+
+    pub fn directory(&self, path: &Path) -> io::Result<Option<DirectoryTotals>> {
+        self.conn.query_row(
+            "SELECT size, allocated_size, file_count, dir_count, newest_modified FROM directories WHERE path = ?1",
+            params![path.to_string_lossy()],
+            |row| totals_from_row(row, 0),
+        ).optional().map_err(db_error)
+    }
+
+    /// Immediate subdirectories of `parent` with their totals.
+    pub fn subdirectories(&self, parent: &Path) -> io::Result<Vec<(PathBuf, DirectoryTotals)>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT path, size, allocated_size, file_count, dir_count, newest_modified FROM directories WHERE parent = ?1",
+        ).map_err(db_error)?;
+        let directories = statement
+            .query_map(params![parent.to_string_lossy()], |row| {
+                let path: String = row.get(0)?;
+                Ok((PathBuf::from(path), totals_from_row(row, 1)?))
+            })
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+        Ok(directories)
+    }
+
+    /// Live files directly inside `parent`.
+    pub fn files_in(&self, parent: &Path) -> io::Result<Vec<FileMetadata>> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT data FROM files WHERE parent = ?1 AND is_deleted = 0",
+        ).map_err(db_error)?;
+        let files = statement
+            .query_map(params![parent.to_string_lossy()], entry_from_row)
+            .map_err(db_error)?
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(db_error)?;
+        Ok(files)
+    }
+}
+
+pub fn load_directory_paths(config_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare("SELECT path FROM directories").map_err(db_error)?;
+    let paths = statement
+        .query_map([], |row| row.get::<_, String>(0).map(PathBuf::from))
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(paths)
+}
+
+pub fn remove_directories(config_dir: &Path, paths: &[PathBuf]) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    {
+        let mut statement = tx.prepare_cached("DELETE FROM directories WHERE path = ?1").map_err(db_error)?;
+        for path in paths {
+            statement.execute(params![path.to_string_lossy()]).map_err(db_error)?;
+        }
+    }
+    tx.commit().map_err(db_error)
+}
+
+/// Drops the totals of `dir` and everything below it, e.g. after it was deleted.
+pub fn remove_directory_tree(config_dir: &Path, dir: &Path) -> io::Result<()> {
+    let conn = open_index(config_dir)?;
+    conn.execute(
+        "DELETE FROM directories WHERE path = ?1 OR substr(path, 1, length(?2)) = ?2",
+        params![dir.to_string_lossy(), directory_prefix(dir)],
+    ).map_err(db_error)?;
     Ok(())
 }
 
+/// SQL condition on `path` for the paths below `root`, leaving out `nested`
+/// and everything below those. Its values are numbered from `?first`.
+fn below_root_condition(root: &Path, nested: &[PathBuf], first: usize) -> (String, Vec<Value>) {
+    let mut values = vec![Value::Text(directory_prefix(root))];
+    let mut condition = format!("substr(path, 1, length(?{0})) = ?{0}", first);
+    for dir in nested {
+        values.push(Value::Text(dir.to_string_lossy().to_string()));
+        values.push(Value::Text(directory_prefix(dir)));
+        condition.push_str(&format!(
+            " AND path != ?{0} AND substr(path, 1, length(?{1})) != ?{1}",
+            first + values.len() - 2, first + values.len() - 1
+        ));
+    }
+    (condition, values)
+}
+
+/// `dir` with a trailing separator, for matching paths below it by prefix.
+fn directory_prefix(dir: &Path) -> String {
+    let mut prefix = dir.to_string_lossy().to_string();
+    if !prefix.ends_with(std::path::MAIN_SEPARATOR) {
+        prefix.push(std::path::MAIN_SEPARATOR);
+    }
+    prefix
+}
+
+/// Statistics of the most recent completed scan, if there is a readable one.
+pub fn load_scan_stats(config_dir: &Path) -> Option<serde_json::Value> {
+    persist::read_json(&config_dir.join("data").join(STATS_FILE_NAME))
+}
+
+/// Merges `metadata` into the stored index without touching the scan statistics.
+pub fn upsert_file_metadata(config_dir: &Path, metadata: &HashMap<PathBuf, FileMetadata>) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    insert_entries(&tx, metadata.values(), WriteMode::Update)?;
+    tx.commit().map_err(db_error)
+}
+
+/// Stores AI results with the importance recomputed from them. Only those two
+/// fields change, so entries a scan rewrote in the meantime keep the rest.
+pub fn set_ai_analysis(config_dir: &Path, results: &[(PathBuf, AIAnalysisResult, u8)]) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    {
+        let mut statement = tx.prepare_cached(
+            "UPDATE files SET importance = ?3,
+                data = json_set(data, '$.ai_analysis', json(?2), '$.importance_score', ?3)
+             WHERE path = ?1 AND is_deleted = 0",
+        ).map_err(db_error)?;
+        for (path, analysis, score) in results {
+            statement.execute(params![path.to_string_lossy(), serde_json::to_string(analysis)?, score]).map_err(db_error)?;
+        }
+    }
+    tx.commit().map_err(db_error)
+}
+
+/// Stores recomputed importance scores. Only the score changes, so entries a
+/// scan rewrote in the meantime keep the rest.
+pub fn set_importance_scores(config_dir: &Path, scores: &[(PathBuf, u8)]) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    {
+        let mut statement = tx.prepare_cached(
+            "UPDATE files SET importance = ?2, data = json_set(data, '$.importance_score', ?2)
+             WHERE path = ?1 AND is_deleted = 0",
+        ).map_err(db_error)?;
+        for (path, score) in scores {
+            statement.execute(params![path.to_string_lossy(), score]).map_err(db_error)?;
+        }
+    }
+    tx.commit().map_err(db_error)
+}
+
+/// Stores the content hash and duplicate flags of `files`. An entry a scan
+/// rewrote with a different size or time since is left alone, as the hash
+/// may no longer describe it.
+pub fn set_duplicate_state(config_dir: &Path, files: &HashMap<PathBuf, FileMetadata>) -> io::Result<()> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    {
+        let mut statement = tx.prepare_cached(
+            "UPDATE files SET content_hash = ?2,
+                data = json_set(data, '$.content_hash', ?2, '$.is_duplicate', json(?3), '$.duplicate_of', ?4)
+             WHERE path = ?1 AND size = ?5 AND modified = ?6 AND is_deleted = 0",
+        ).map_err(db_error)?;
+        for meta in files.values() {
+            statement.execute(params![
+                meta.path.to_string_lossy(),
+                meta.content_hash,
+                if meta.is_duplicate { "true" } else { "false" },
+                meta.duplicate_of.as_ref().map(|original| original.to_string_lossy()),
+                meta.size as i64,
+                meta.modified.timestamp(),
+            ]).map_err(db_error)?;
+        }
+    }
+    tx.commit().map_err(db_error)
+}
+
+/// Drops entries from the index entirely, without leaving tombstones.
+pub fn remove_file_metadata(config_dir: &Path, paths: &[PathBuf]) -> io::Result<usize> {
+    let mut conn = open_index(config_dir)?;
+    let tx = begin_write(&mut conn)?;
+    let mut removed = 0;
+    {
+        let mut statement = tx.prepare_cached("DELETE FROM files WHERE path = ?1").map_err(db_error)?;
+        for path in paths {
+            removed += statement.execute(params![path.to_string_lossy()]).map_err(db_error)?;
+        }
+    }
+    tx.commit().map_err(db_error)?;
+    Ok(removed)
+}
+
+/// Loads every entry, tombstones included.
 pub fn load_file_metadata(config_dir: &Path) -> io::Result<HashMap<PathBuf, FileMetadata>> {
-    let data_dir = config_dir.join("data");
-    let mut result = HashMap::new();
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare("SELECT data FROM files").map_err(db_error)?;
+    let rows = statement.query_map([], entry_from_row).map_err(db_error)?;
     
-    if !data_dir.exists() {
-        return Ok(result);
+    let mut result = HashMap::new();
+    for metadata in rows {
+        let metadata = metadata.map_err(db_error)?;
+        result.insert(metadata.path.clone(), metadata);
     }
-    
-    // Find all metadata chunk files
-    for entry in fs::read_dir(&data_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        
-        if path.is_file() && path.file_name().unwrap().to_string_lossy().starts_with("metadata_chunk_") {
-            let content = fs::read_to_string(&path)?;
-            let chunk_data: HashMap<String, FileMetadata> = serde_json::from_str(&content)?;
-            
-            // Convert string keys to PathBuf and add to result
-            for (_, metadata) in chunk_data {
-                result.insert(metadata.path.clone(), metadata);
-            }
+    Ok(result)
+}
+
+/// Loads the entries stored for `paths`, tombstones included. Paths that were
+/// never indexed are left out.
+pub fn load_entries(config_dir: &Path, paths: &[PathBuf]) -> io::Result<HashMap<PathBuf, FileMetadata>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare_cached("SELECT data FROM files WHERE path = ?1").map_err(db_error)?;
+    let mut result = HashMap::new();
+    for path in paths {
+        let stored = statement.query_row(params![path.to_string_lossy()], entry_from_row).optional();
+        if let Some(metadata) = stored.map_err(db_error)? {
+            result.insert(metadata.path.clone(), metadata);
         }
     }
-    
     Ok(result)
 }
 
-pub fn find_duplicate_files(metadata: &HashMap<PathBuf, FileMetadata>) -> Vec<(PathBuf, PathBuf)> {
-    let mut size_map: HashMap<u64, Vec<PathBuf>> = HashMap::new();
-    let mut duplicates = Vec::new();
-    
-    // Group files by size
-    for (path, meta) in metadata {
-        size_map.entry(meta.size).or_default().push(path.clone());
+/// Live entries at `path` or below it, e.g. after it was deleted.
+pub fn load_live_entries_under(config_dir: &Path, path: &Path) -> io::Result<Vec<FileMetadata>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT data FROM files WHERE is_deleted = 0 AND (path = ?1 OR substr(path, 1, length(?2)) = ?2)",
+    ).map_err(db_error)?;
+    let entries = statement
+        .query_map(params![path.to_string_lossy(), directory_prefix(path)], entry_from_row)
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(entries)
+}
+
+/// The columns `/metadata` lists for each live file.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileSummary {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub category: String,
+    pub importance: u8,
+}
+
+/// Every live entry, read from the indexed columns alone.
+pub fn list_file_summaries(config_dir: &Path) -> io::Result<Vec<FileSummary>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT path, name, size, category, importance FROM files WHERE is_deleted = 0",
+    ).map_err(db_error)?;
+    let files = statement
+        .query_map([], |row| {
+            let path: String = row.get(0)?;
+            Ok(FileSummary {
+                path: PathBuf::from(path),
+                name: row.get(1)?,
+                size: row.get::<_, i64>(2)? as u64,
+                category: row.get(3)?,
+                importance: row.get(4)?,
+            })
+        })
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(files)
+}
+
+/// One live file of a set that shares its content or its inode.
+#[derive(Debug, Clone)]
+pub struct SharedFile {
+    pub size: u64,
+    pub path: PathBuf,
+    /// Marked as a duplicate, or a link that does not carry the inode's size.
+    pub is_secondary: bool,
+}
+
+/// Files of at least `min_size` bytes whose size and content hash another
+/// file shares, ordered by set. Each inode counts once, through the link
+/// that carries its size. Sets are keyed by content hash.
+pub fn load_duplicate_files(config_dir: &Path, min_size: u64) -> io::Result<Vec<(String, SharedFile)>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT size, content_hash, path, json_extract(data, '$.is_duplicate') FROM files
+         WHERE is_deleted = 0 AND hardlink_of IS NULL AND size >= ?1 AND (size, content_hash) IN
+            (SELECT size, content_hash FROM files
+             WHERE is_deleted = 0 AND hardlink_of IS NULL AND content_hash IS NOT NULL AND size >= ?1
+             GROUP BY size, content_hash HAVING count(*) > 1)
+         ORDER BY size, content_hash",
+    ).map_err(db_error)?;
+    let files = statement
+        .query_map(params![min_size as i64], |row| {
+            let path: String = row.get(2)?;
+            Ok((row.get(1)?, SharedFile {
+                size: row.get::<_, i64>(0)? as u64,
+                path: PathBuf::from(path),
+                is_secondary: row.get(3)?,
+            }))
+        })
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(files)
+}
+
+/// Files of at least `min_size` bytes that are one of several indexed links
+/// to their inode, ordered by inode. Such an inode always has a link that
+/// points at the one carrying its size. Sets are keyed by device and inode.
+pub fn load_hardlinked_files(config_dir: &Path, min_size: u64) -> io::Result<Vec<((u64, u64), SharedFile)>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT size, device, inode, path, hardlink_of IS NOT NULL FROM files
+         WHERE is_deleted = 0 AND size >= ?1 AND (device, inode) IN
+            (SELECT device, inode FROM files WHERE is_deleted = 0 AND hardlink_of IS NOT NULL)
+         ORDER BY device, inode",
+    ).map_err(db_error)?;
+    let files = statement
+        .query_map(params![min_size as i64], |row| {
+            let path: String = row.get(3)?;
+            let inode = (row.get::<_, i64>(1)? as u64, row.get::<_, i64>(2)? as u64);
+            Ok((inode, SharedFile {
+                size: row.get::<_, i64>(0)? as u64,
+                path: PathBuf::from(path),
+                is_secondary: row.get(4)?,
+            }))
+        })
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(files)
+}
+
+pub fn get_file_metadata(config_dir: &Path, path: &Path) -> io::Result<Option<FileMetadata>> {
+    let conn = open_index(config_dir)?;
+    conn.query_row("SELECT data FROM files WHERE path = ?1", params![path.to_string_lossy()], entry_from_row)
+        .optional()
+        .map_err(db_error)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSort {
+    Name,
+    Size,
+    Modified,
+    Importance,
+    /// Capture date of photos and recordings; files without one come last.
+    Captured,
+    /// Width times height; files without dimensions come last.
+    Pixels,
+    /// Length of recordings; other files come last.
+    Duration,
+}
+
+/// Filters, order and page for [`query_files`]. Tombstones are never returned.
+#[derive(Debug, Clone)]
+pub struct FileQuery {
+    /// A category and its subcategories: `code` also matches `code/rust`.
+    pub category: Option<String>,
+    pub size_min: Option<u64>,
+    pub size_max: Option<u64>,
+    /// Case-insensitive substring of the path.
+    pub search: Option<String>,
+    /// Which size `size_min`, `size_max` and sorting by size refer to.
+    pub size_mode: SizeMode,
+    /// Only files whose content does not match their extension.
+    pub mismatched_only: bool,
+    /// Capture time range, start inclusive and end exclusive.
+    pub captured_after: Option<NaiveDateTime>,
+    pub captured_before: Option<NaiveDateTime>,
+    pub width_min: Option<u32>,
+    pub width_max: Option<u32>,
+    pub height_min: Option<u32>,
+    pub height_max: Option<u32>,
+    /// Recording length range in seconds, both ends inclusive.
+    pub duration_min: Option<f64>,
+    pub duration_max: Option<f64>,
+    /// Exact artist and album tags, ignoring ASCII case.
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub sort: Option<FileSort>,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// Returns one page of matching entries plus the total number of matches.
+pub fn query_files(config_dir: &Path, query: &FileQuery) -> io::Result<(Vec<FileMetadata>, usize)> {
+    let size_column = match query.size_mode {
+        SizeMode::Apparent => "size",
+        SizeMode::Allocated => "allocated_size",
+    };
+    let mut conditions = vec!["is_deleted = 0".to_string()];
+    let mut values: Vec<Value> = Vec::new();
+    if let Some(category) = &query.category {
+        // Subcategories sort between `category/` and `category0`, `0` being
+        // the character after `/`
+        values.push(Value::Text(category.clone()));
+        values.push(Value::Text(format!("{}/", category)));
+        values.push(Value::Text(format!("{}0", category)));
+        conditions.push(format!(
+            "(category = ?{} OR (category >= ?{} AND category < ?{}))",
+            values.len() - 2, values.len() - 1, values.len()
+        ));
     }
-    
-    // For each size group with multiple files, check if they are duplicates
-    for (_, files) in size_map.into_iter().filter(|(_, files)| files.len() > 1) {
-        // Compare files by content hash
-        for i in 0..files.len() {
-            for j in i + 1..files.len() {
-                if are_files_identical(&files[i], &files[j]) {
-                    duplicates.push((files[i].clone(), files[j].clone()));
-                }
-            }
+    if let Some(size_min) = query.size_min {
+        values.push(Value::Integer(size_min as i64));
+        conditions.push(format!("{} >= ?{}", size_column, values.len()));
+    }
+    if let Some(size_max) = query.size_max {
+        values.push(Value::Integer(size_max as i64));
+        conditions.push(format!("{} <= ?{}", size_column, values.len()));
+    }
+    if let Some(search) = &query.search {
+        let escaped = search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        values.push(Value::Text(format!("%{}%", escaped)));
+        conditions.push(format!("path LIKE ?{} ESCAPE '\\'", values.len()));
+    }
+    if query.mismatched_only {
+        conditions.push("json_extract(data, '$.extension_mismatch') = 1".to_string());
+    }
+    let captured_bounds = [(query.captured_after, ">="), (query.captured_before, "<")];
+    for (bound, operator) in captured_bounds {
+        if let Some(bound) = bound {
+            values.push(Value::Text(bound.format(CAPTURED_AT_FORMAT).to_string()));
+            conditions.push(format!("captured_at {} ?{}", operator, values.len()));
+        }
+    }
+    let dimension_bounds = [
+        ("width", query.width_min, ">="),
+        ("width", query.width_max, "<="),
+        ("height", query.height_min, ">="),
+        ("height", query.height_max, "<="),
+    ];
+    for (column, bound, operator) in dimension_bounds {
+        if let Some(bound) = bound {
+            values.push(Value::Integer(bound.into()));
+            conditions.push(format!("{} {} ?{}", column, operator, values.len()));
+        }
+    }
+    let duration_bounds = [(query.duration_min, ">="), (query.duration_max, "<=")];
+    for (bound, operator) in duration_bounds {
+        if let Some(bound) = bound {
+            values.push(Value::Real(bound));
+            conditions.push(format!("duration {} ?{}", operator, values.len()));
+        }
+    }
+    for (column, tag) in [("artist", &query.artist), ("album", &query.album)] {
+        if let Some(tag) = tag {
+            values.push(Value::Text(tag.clone()));
+            conditions.push(format!("{} = ?{}", column, values.len()));
         }
     }
+    let filter = conditions.join(" AND ");
+    
+    let conn = open_index(config_dir)?;
+    let total: i64 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM files WHERE {}", filter),
+        params_from_iter(values.iter()),
+        |row| row.get(0),
+    ).map_err(db_error)?;
     
-    duplicates
+    let order = match query.sort {
+        Some(FileSort::Name) => "name",
+        Some(FileSort::Size) => size_column,
+        Some(FileSort::Modified) => "modified",
+        Some(FileSort::Importance) => "importance",
+        Some(FileSort::Captured) => "captured_at",
+        Some(FileSort::Pixels) => "width * height",
+        Some(FileSort::Duration) => "duration",
+        None => "rowid",
+    };
+    let direction = if query.descending { "DESC" } else { "ASC" };
+    let sql = format!(
+        "SELECT data FROM files WHERE {} ORDER BY ({}) IS NULL, {} {}, path LIMIT {} OFFSET {}",
+        filter, order, order, direction, query.limit, query.offset
+    );
+    let mut statement = conn.prepare(&sql).map_err(db_error)?;
+    let files = statement.query_map(params_from_iter(values.iter()), entry_from_row)
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    
+    Ok((files, total as usize))
+}
+
+/// The statistics recorded for one completed scan of a root.
+#[derive(Debug, Clone, Serialize)]
+pub struct Snapshot {
+    pub id: i64,
+    pub root: PathBuf,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub taken_at: DateTime<Utc>,
+    pub total_files: u64,
+    pub total_size: u64,
+    pub total_allocated_size: u64,
+    pub file_types: HashMap<String, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Grown,
+    Shrunk,
+}
+
+impl ChangeKind {
+    /// Classifies a size change; `None` stands for a file that did not exist.
+    pub fn between(old_size: Option<u64>, new_size: Option<u64>) -> Option<ChangeKind> {
+        match (old_size, new_size) {
+            (None, Some(_)) => Some(ChangeKind::Added),
+            (Some(_), None) => Some(ChangeKind::Removed),
+            (Some(old), Some(new)) if new > old => Some(ChangeKind::Grown),
+            (Some(old), Some(new)) if new < old => Some(ChangeKind::Shrunk),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::Grown => "grown",
+            ChangeKind::Shrunk => "shrunk",
+        }
+    }
+}
+
+/// One entry of the per-file change log.
+#[derive(Debug, Clone)]
+pub struct ChangeRecord {
+    pub path: PathBuf,
+    pub category: String,
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
 }
 
-fn are_files_identical(path1: &Path, path2: &Path) -> bool {
-    // Simple implementation: read and compare file contents
-    // For production, you'd want to use hashing or more efficient methods
-    match (fs::read(path1), fs::read(path2)) {
-        (Ok(content1), Ok(content2)) => content1 == content2,
-        _ => false,
+fn snapshot_from_row(row: &rusqlite::Row) -> rusqlite::Result<Snapshot> {
+    let root: String = row.get(1)?;
+    let file_types: String = row.get(5)?;
+    Ok(Snapshot {
+        id: row.get(0)?,
+        root: PathBuf::from(root),
+        taken_at: DateTime::from_timestamp(row.get(2)?, 0).unwrap_or_default(),
+        total_files: row.get::<_, i64>(3)? as u64,
+        total_size: row.get::<_, i64>(4)? as u64,
+        total_allocated_size: row.get::<_, i64>(6)? as u64,
+        file_types: serde_json::from_str(&file_types).unwrap_or_default(),
+    })
+}
+
+/// Snapshots newest first, optionally only those of one root.
+pub fn list_snapshots(config_dir: &Path, root: Option<&Path>) -> io::Result<Vec<Snapshot>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT id, root, taken_at, total_files, total_size, file_types, total_allocated_size FROM snapshots
+         WHERE ?1 IS NULL OR root = ?1 ORDER BY id DESC",
+    ).map_err(db_error)?;
+    let snapshots = statement
+        .query_map(params![root.map(|root| root.to_string_lossy())], snapshot_from_row)
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(snapshots)
+}
+
+/// Changes claimed by snapshots of `root` after `after_id` up to and
+/// including `up_to_id`, oldest first. Snapshot ids are shared by all roots,
+/// so other roots' snapshots in that range are left out.
+pub fn load_changes(config_dir: &Path, root: &Path, after_id: i64, up_to_id: i64) -> io::Result<Vec<ChangeRecord>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare(
+        "SELECT path, category, old_size, new_size FROM file_changes
+         WHERE snapshot_id > ?1 AND snapshot_id <= ?2
+            AND snapshot_id IN (SELECT id FROM snapshots WHERE root = ?3)
+         ORDER BY snapshot_id, rowid",
+    ).map_err(db_error)?;
+    let changes = statement
+        .query_map(params![after_id, up_to_id, root.to_string_lossy()], |row| {
+            let path: String = row.get(0)?;
+            Ok(ChangeRecord {
+                path: PathBuf::from(path),
+                category: row.get(1)?,
+                old_size: row.get::<_, Option<i64>>(2)?.map(|size| size as u64),
+                new_size: row.get::<_, Option<i64>>(3)?.map(|size| size as u64),
+            })
+        })
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(changes)
+}
+
+/// A file or directory the user marked as important, as served by `/pins`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pin {
+    pub path: PathBuf,
+    pub pinned_at: DateTime<Utc>,
+}
+
+/// Pins, oldest first.
+pub fn load_pins(config_dir: &Path) -> io::Result<Vec<Pin>> {
+    let conn = open_index(config_dir)?;
+    let mut statement = conn.prepare("SELECT path, pinned_at FROM pins ORDER BY pinned_at, path").map_err(db_error)?;
+    let pins = statement
+        .query_map([], |row| {
+            let path: String = row.get(0)?;
+            Ok(Pin {
+                path: PathBuf::from(path),
+                pinned_at: DateTime::from_timestamp(row.get(1)?, 0).unwrap_or_default(),
+            })
+        })
+        .map_err(db_error)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(db_error)?;
+    Ok(pins)
+}
+
+/// Pins `path`. Returns false when it already was.
+pub fn add_pin(config_dir: &Path, path: &Path) -> io::Result<bool> {
+    let conn = open_index(config_dir)?;
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO pins (path, pinned_at) VALUES (?1, ?2)",
+        params![path.to_string_lossy(), Utc::now().timestamp()],
+    ).map_err(db_error)?;
+    Ok(added > 0)
+}
+
+/// Unpins `path`. Returns false when it was not pinned.
+pub fn remove_pin(config_dir: &Path, path: &Path) -> io::Result<bool> {
+    let conn = open_index(config_dir)?;
+    let removed = conn.execute("DELETE FROM pins WHERE path = ?1", params![path.to_string_lossy()]).map_err(db_error)?;
+    Ok(removed > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TempDir;
+
+    #[derive(Serialize, Deserialize)]
+    struct Stamped {
+        #[serde(with = "precise_time")]
+        modified: DateTime<Utc>,
+    }
+
+    #[test]
+    fn modified_time_keeps_nanoseconds() {
+        let modified = DateTime::from_timestamp(1_700_000_000, 123_456_789).unwrap();
+        let json = serde_json::to_string(&Stamped { modified }).unwrap();
+        let loaded: Stamped = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.modified, modified);
+    }
+
+    #[test]
+    fn below_root_condition_leaves_out_nested_roots() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE files (path TEXT);
+             INSERT INTO files VALUES ('/etc'), ('/etc/hosts'), ('/home'), ('/home/a'), ('/homework/b');",
+        ).unwrap();
+        let (condition, values) = below_root_condition(Path::new("/"), &[PathBuf::from("/home")], 1);
+        let mut statement = conn.prepare(&format!("SELECT path FROM files WHERE {} ORDER BY path", condition)).unwrap();
+        let paths: Vec<String> = statement
+            .query_map(params_from_iter(values.iter()), |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(paths, ["/etc", "/etc/hosts", "/homework/b"]);
+    }
+
+    #[test]
+    fn changes_stay_with_the_root_that_claimed_them() {
+        let dir = TempDir::new();
+        let conn = open_index(dir.path()).unwrap();
+        // Scans of / and /home taking turns, as when both drives are scanned at once
+        conn.execute_batch(
+            "INSERT INTO snapshots (id, root, taken_at, total_files, total_size, file_types) VALUES
+                (1, '/', 100, 0, 0, '{}'), (2, '/home', 110, 0, 0, '{}'),
+                (3, '/', 200, 0, 0, '{}'), (4, '/home', 210, 0, 0, '{}');
+             INSERT INTO file_changes (snapshot_id, path, category, kind, old_size, new_size) VALUES
+                (2, '/home/a/notes.txt', 'document', 'grown', 10, 20),
+                (3, '/etc/hosts', 'other', 'grown', 1, 2),
+                (4, '/home/a/photo.jpg', 'image', 'added', NULL, 5);",
+        ).unwrap();
+        let paths = |root: &str, after_id, up_to_id| -> Vec<PathBuf> {
+            load_changes(dir.path(), Path::new(root), after_id, up_to_id)
+                .unwrap()
+                .into_iter()
+                .map(|change| change.path)
+                .collect()
+        };
+        assert_eq!(paths("/", 1, 3), [PathBuf::from("/etc/hosts")]);
+        assert_eq!(paths("/home", 2, 4), [PathBuf::from("/home/a/photo.jpg")]);
+    }
+
+    #[test]
+    fn migration_keeps_chunks_it_could_not_import() {
+        let dir = TempDir::new();
+        let data_dir = dir.path().join("data");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::write(data_dir.join("metadata_chunk_1.json"), "{}").unwrap();
+        fs::write(data_dir.join("metadata_chunk_2.json"), "{\"truncated").unwrap();
+
+        open_index(dir.path()).unwrap();
+        let names: Vec<String> = fs::read_dir(&data_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .filter(|name| name.starts_with("metadata_chunk_"))
+            .collect();
+        assert_eq!(names.len(), 1);
+        assert!(names[0].starts_with("metadata_chunk_2.json.corrupt-"));
+    }
+
+    #[test]
+    fn removed_directory_takes_only_its_own_entries() {
+        let dir = TempDir::new();
+        let config = crate::config::Config::default();
+        let mut entries = HashMap::new();
+        for name in ["b", "b/inner.txt", "bc.txt"] {
+            let path = dir.file(&name.replace('/', "-"), b"x");
+            let mut meta = crate::analyzer::analyze_file(&path, &fs::metadata(&path).unwrap(), &config);
+            meta.path = Path::new("/a").join(name);
+            entries.insert(meta.path.clone(), meta);
+        }
+        upsert_file_metadata(dir.path(), &entries).unwrap();
+
+        let mut under: Vec<PathBuf> = load_live_entries_under(dir.path(), Path::new("/a/b"))
+            .unwrap()
+            .into_iter()
+            .map(|meta| meta.path)
+            .collect();
+        under.sort();
+        assert_eq!(under, [PathBuf::from("/a/b"), PathBuf::from("/a/b/inner.txt")]);
+        let loaded = load_entries(dir.path(), &[PathBuf::from("/a/bc.txt"), PathBuf::from("/a/missing")]).unwrap();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), [Path::new("/a/bc.txt")]);
+    }
+
+    #[test]
+    fn modified_time_loads_legacy_seconds() {
+        let loaded: Stamped = serde_json::from_str(r#"{"modified": 1700000000}"#).unwrap();
+        assert_eq!(loaded.modified, DateTime::from_timestamp(1_700_000_000, 0).unwrap());
     }
 }
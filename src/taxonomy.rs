@@ -0,0 +1,244 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde_derive::{Serialize, Deserialize};
+use crate::config::Config;
+use crate::exclusion::expand_home;
+
+/// Category of files that no rule matches.
+pub const OTHER: &str = "other";
+
+/// Rules shipped with the program; see the file for the rule syntax.
+const DEFAULT_RULES: &str = include_str!("taxonomy.toml");
+
+static DEFAULTS: OnceLock<Vec<CategoryRule>> = OnceLock::new();
+/// The taxonomy last built by [`Taxonomy::for_config`].
+static CURRENT: Mutex<Option<Arc<Taxonomy>>> = Mutex::new(None);
+
+/// One rule of the taxonomy. Every condition that is set must hold; within
+/// a list, any entry may match.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CategoryRule {
+    /// Category given to matching files; `/` separates subcategories, as in
+    /// `code/rust`.
+    pub category: String,
+    /// Extensions without the dot; `""` matches files without one.
+    pub extensions: Vec<String>,
+    /// Detected MIME types; `type/*` matches a whole top-level type.
+    pub mime_types: Vec<String>,
+    /// Globs matched against the full path.
+    pub paths: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Whether an execute permission bit must be set, or must not be.
+    pub executable: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct RuleFile {
+    rule: Vec<CategoryRule>,
+}
+
+/// What the rules look at in a file.
+pub struct FileFacts<'a> {
+    pub path: &'a Path,
+    /// Lowercase, empty for files without one.
+    pub extension: &'a str,
+    pub mime_type: &'a str,
+    pub size: u64,
+    pub executable: bool,
+}
+
+struct CompiledRule {
+    category: String,
+    extensions: Vec<String>,
+    mime_types: Vec<String>,
+    paths: Option<GlobSet>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    executable: Option<bool>,
+}
+
+impl CompiledRule {
+    fn compile(rule: &CategoryRule) -> Result<Self, String> {
+        let is_name = |segment: &str| {
+            !segment.is_empty() && segment.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        };
+        if !rule.category.split('/').all(is_name) {
+            return Err(format!(
+                "category '{}' must be lowercase names of letters, digits, '-' or '_' separated by '/'",
+                rule.category
+            ));
+        }
+        if let Some(mime_type) = rule.mime_types.iter().find(|mime_type| !mime_type.contains('/')) {
+            return Err(format!("'{}' is not a MIME type", mime_type));
+        }
+        if let (Some(min_size), Some(max_size)) = (rule.min_size, rule.max_size) {
+            if min_size > max_size {
+                return Err("min_size must not be larger than max_size".to_string());
+            }
+        }
+        let paths = if rule.paths.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &rule.paths {
+                let glob = GlobBuilder::new(&expand_home(pattern))
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                builder.add(glob);
+            }
+            Some(builder.build().map_err(|e| e.to_string())?)
+        };
+        let lowercase = |values: &[String]| values.iter().map(|value| value.trim_start_matches('.').to_lowercase()).collect();
+        Ok(CompiledRule {
+            category: rule.category.clone(),
+            extensions: lowercase(&rule.extensions),
+            mime_types: lowercase(&rule.mime_types),
+            paths,
+            min_size: rule.min_size,
+            max_size: rule.max_size,
+            executable: rule.executable,
+        })
+    }
+
+    fn matches(&self, file: &FileFacts) -> bool {
+        let mime_matches = |pattern: &String| match pattern.strip_suffix("/*") {
+            Some(top_level) => file.mime_type.split('/').next() == Some(top_level),
+            None => pattern == file.mime_type,
+        };
+        (self.extensions.is_empty() || self.extensions.iter().any(|extension| extension == file.extension))
+            && (self.mime_types.is_empty() || self.mime_types.iter().any(mime_matches))
+            && self.paths.as_ref().is_none_or(|paths| paths.is_match(file.path))
+            && self.min_size.is_none_or(|min_size| file.size >= min_size)
+            && self.max_size.is_none_or(|max_size| file.size <= max_size)
+            && self.executable.is_none_or(|executable| executable == file.executable)
+    }
+}
+
+/// Compiled form of the `category_rules` setting followed by the built-in
+/// rules.
+pub struct Taxonomy {
+    custom: Vec<CategoryRule>,
+    rules: Vec<CompiledRule>,
+}
+
+impl Taxonomy {
+    /// The taxonomy for `config`, compiled again only when its rules change.
+    pub fn for_config(config: &Config) -> Arc<Taxonomy> {
+        let mut current = CURRENT.lock().unwrap();
+        match current.as_ref() {
+            Some(taxonomy) if taxonomy.custom == config.category_rules => taxonomy.clone(),
+            _ => {
+                let taxonomy = Arc::new(Taxonomy::new(&config.category_rules));
+                *current = Some(taxonomy.clone());
+                taxonomy
+            },
+        }
+    }
+
+    /// Builds the taxonomy, skipping custom rules that do not compile. Use
+    /// [`Taxonomy::rule_errors`] to report those.
+    pub fn new(custom: &[CategoryRule]) -> Self {
+        let rules = custom.iter()
+            .chain(default_rules())
+            .filter_map(|rule| CompiledRule::compile(rule).ok())
+            .collect();
+        Taxonomy { custom: custom.to_vec(), rules }
+    }
+
+    /// Returns the index and error message of every rule that is invalid.
+    pub fn rule_errors(rules: &[CategoryRule]) -> Vec<(usize, String)> {
+        rules.iter()
+            .enumerate()
+            .filter_map(|(index, rule)| CompiledRule::compile(rule).err().map(|e| (index, e)))
+            .collect()
+    }
+
+    /// Category of the first matching rule, or [`OTHER`].
+    pub fn categorize(&self, file: &FileFacts) -> String {
+        self.rules.iter()
+            .find(|rule| rule.matches(file))
+            .map_or_else(|| OTHER.to_string(), |rule| rule.category.clone())
+    }
+}
+
+fn default_rules() -> &'static [CategoryRule] {
+    DEFAULTS.get_or_init(|| {
+        toml::from_str::<RuleFile>(DEFAULT_RULES)
+            .expect("built-in taxonomy is valid")
+            .rule
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn facts<'a>(path: &'a str, extension: &'a str, mime_type: &'a str) -> FileFacts<'a> {
+        FileFacts { path: Path::new(path), extension, mime_type, size: 1024, executable: false }
+    }
+
+    #[test]
+    fn built_in_rules_compile() {
+        assert!(Taxonomy::rule_errors(default_rules()).is_empty());
+    }
+
+    #[test]
+    fn first_matching_built_in_rule_decides() {
+        let taxonomy = Taxonomy::new(&[]);
+        assert_eq!(taxonomy.categorize(&facts("/home/a/photo.cr2", "cr2", "image/tiff")), "image/raw");
+        assert_eq!(taxonomy.categorize(&facts("/home/a/photo.jpg", "jpg", "image/jpeg")), "image");
+        assert_eq!(taxonomy.categorize(&facts("/src/app/.git/objects/ab/cd.txt", "txt", "text/plain")), "code/vcs");
+        // No telling extension, so the content decides
+        assert_eq!(taxonomy.categorize(&facts("/home/a/download", "", "application/pdf")), "document");
+        assert_eq!(taxonomy.categorize(&facts("/home/a/clip.bin", "bin", "video/mp4")), "video");
+        assert_eq!(taxonomy.categorize(&facts("/home/a/blob", "", "application/octet-stream")), OTHER);
+
+        let tool = FileFacts { executable: true, ..facts("/usr/local/bin/tool", "", "text/x-shellscript") };
+        assert_eq!(taxonomy.categorize(&tool), "application");
+    }
+
+    #[test]
+    fn custom_rules_come_first() {
+        let custom = [
+            CategoryRule {
+                category: "project/scans".to_string(),
+                paths: vec!["/data/scans/**".to_string()],
+                mime_types: vec!["image/*".to_string()],
+                ..Default::default()
+            },
+            CategoryRule {
+                category: "video/large".to_string(),
+                extensions: vec![".MKV".to_string()],
+                min_size: Some(1 << 30),
+                ..Default::default()
+            },
+        ];
+        let taxonomy = Taxonomy::new(&custom);
+        assert_eq!(taxonomy.categorize(&facts("/data/scans/2024/page.png", "png", "image/png")), "project/scans");
+        // Elsewhere, and below the size limit, the built-in rules apply
+        assert_eq!(taxonomy.categorize(&facts("/data/other/page.png", "png", "image/png")), "image");
+        assert_eq!(taxonomy.categorize(&facts("/media/film.mkv", "mkv", "video/x-matroska")), "video");
+        let film = FileFacts { size: 4 << 30, ..facts("/media/film.mkv", "mkv", "video/x-matroska") };
+        assert_eq!(taxonomy.categorize(&film), "video/large");
+    }
+
+    #[test]
+    fn reports_invalid_rules() {
+        let rules = [
+            CategoryRule { category: "Photos".to_string(), ..Default::default() },
+            CategoryRule { category: "ok".to_string(), mime_types: vec!["image".to_string()], ..Default::default() },
+            CategoryRule { category: "ok".to_string(), min_size: Some(10), max_size: Some(1), ..Default::default() },
+            CategoryRule { category: "ok".to_string(), paths: vec!["/a/[".to_string()], ..Default::default() },
+            CategoryRule { category: "ok/fine".to_string(), ..Default::default() },
+        ];
+        let indexes: Vec<usize> = Taxonomy::rule_errors(&rules).into_iter().map(|(index, _)| index).collect();
+        assert_eq!(indexes, [0, 1, 2, 3]);
+        // Invalid custom rules are left out rather than failing the rest
+        let taxonomy = Taxonomy::new(&rules);
+        assert_eq!(taxonomy.categorize(&facts("/a/b.png", "png", "image/png")), "ok/fine");
+    }
+}
@@ -0,0 +1,136 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use serde_derive::Serialize;
+use crate::storage::{self, DirectoryIndex, SizeMode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Directory,
+    File,
+}
+
+/// One entry of `/tree`: a directory with its recursive totals, or a file.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreeNode {
+    pub path: PathBuf,
+    pub name: String,
+    pub kind: NodeKind,
+    /// Category of a file; `None` for directories.
+    pub category: Option<String>,
+    pub size: u64,
+    pub allocated_size: u64,
+    pub file_count: u64,
+    pub dir_count: u64,
+    pub newest_modified: i64,
+    /// Largest children first, by the requested size. Empty for files and
+    /// below the requested depth.
+    pub children: Vec<TreeNode>,
+    /// Children left out because of the per-directory limit.
+    pub omitted_children: usize,
+    pub omitted_size: u64,
+    pub omitted_allocated_size: u64,
+}
+
+impl TreeNode {
+    fn directory(path: &Path, totals: storage::DirectoryTotals) -> Self {
+        TreeNode {
+            name: display_name(path),
+            path: path.to_path_buf(),
+            kind: NodeKind::Directory,
+            category: None,
+            size: totals.size,
+            allocated_size: totals.allocated_size,
+            file_count: totals.file_count,
+            dir_count: totals.dir_count,
+            newest_modified: totals.newest_modified,
+            children: Vec::new(),
+            omitted_children: 0,
+            omitted_size: 0,
+            omitted_allocated_size: 0,
+        }
+    }
+
+    /// A link that does not carry its inode's size counts 0 bytes, as it does
+    /// in the directory totals, so that children add up to their parent.
+    fn file(meta: &storage::FileMetadata) -> Self {
+        let (size, allocated_size) = match meta.hardlink_of {
+            Some(_) => (0, 0),
+            None => (meta.size, meta.allocated_size),
+        };
+        TreeNode {
+            name: meta.file_name.clone(),
+            path: meta.path.clone(),
+            kind: NodeKind::File,
+            category: Some(meta.category.clone()),
+            size,
+            allocated_size,
+            file_count: 1,
+            dir_count: 0,
+            newest_modified: meta.modified.timestamp(),
+            children: Vec::new(),
+            omitted_children: 0,
+            omitted_size: 0,
+            omitted_allocated_size: 0,
+        }
+    }
+
+    pub fn size_in(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.size,
+            SizeMode::Allocated => self.allocated_size,
+        }
+    }
+
+    pub fn omitted_size_in(&self, mode: SizeMode) -> u64 {
+        match mode {
+            SizeMode::Apparent => self.omitted_size,
+            SizeMode::Allocated => self.omitted_allocated_size,
+        }
+    }
+}
+
+/// Builds the size tree under `path` from the stored directory totals,
+/// expanding `depth` levels and keeping the `limit` largest children of each
+/// directory by `mode`. Returns `None` when `path` is not an indexed directory.
+pub fn build_tree(config_dir: &Path, path: &Path, depth: usize, limit: usize, mode: SizeMode) -> io::Result<Option<TreeNode>> {
+    let index = DirectoryIndex::open(config_dir)?;
+    let Some(totals) = index.directory(path)? else {
+        return Ok(None);
+    };
+    let mut root = TreeNode::directory(path, totals);
+    expand(&index, &mut root, depth, limit, mode)?;
+    Ok(Some(root))
+}
+
+fn expand(index: &DirectoryIndex, node: &mut TreeNode, depth: usize, limit: usize, mode: SizeMode) -> io::Result<()> {
+    if depth == 0 {
+        return Ok(());
+    }
+    let directories = index.subdirectories(&node.path)?;
+    let files = index.files_in(&node.path)?;
+    let mut children: Vec<TreeNode> = directories
+        .into_iter()
+        .map(|(path, totals)| TreeNode::directory(&path, totals))
+        .chain(files.iter().map(TreeNode::file))
+        .collect();
+    children.sort_by(|a, b| b.size_in(mode).cmp(&a.size_in(mode)).then_with(|| a.name.cmp(&b.name)));
+
+    if children.len() > limit {
+        let omitted = children.split_off(limit);
+        node.omitted_children = omitted.len();
+        node.omitted_size = omitted.iter().map(|child| child.size).sum();
+        node.omitted_allocated_size = omitted.iter().map(|child| child.allocated_size).sum();
+    }
+    for child in children.iter_mut().filter(|child| child.kind == NodeKind::Directory) {
+        expand(index, child, depth - 1, limit, mode)?;
+    }
+    node.children = children;
+    Ok(())
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string_lossy().to_string())
+}
@@ -0,0 +1,196 @@
+use std::path::PathBuf;
+use serde_derive::Serialize;
+use crate::storage::SizeMode;
+use crate::tree::{NodeKind, TreeNode};
+
+/// Colour key of directories that are drawn without their contents.
+const DIRECTORY_COLOR_KEY: &str = "directory";
+/// Colour key of the block standing in for children beyond the limit.
+const OMITTED_COLOR_KEY: &str = "omitted";
+
+/// One laid-out block of the treemap, in the coordinate space of the
+/// requested rectangle.
+#[derive(Debug, Clone, Serialize)]
+pub struct TreemapRect {
+    /// `None` for the block that sums up omitted children.
+    pub path: Option<PathBuf>,
+    pub name: String,
+    /// Size the area stands for, apparent or allocated as requested.
+    pub size: u64,
+    pub depth: usize,
+    pub kind: NodeKind,
+    /// File category for files, `directory` or `omitted` otherwise.
+    pub color_key: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bounds {
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+}
+
+/// Lays out `tree` as a squarified treemap filling `width` x `height`.
+/// Parents come before their children in the result; children share their
+/// parent's area in proportion to their size under `mode`.
+pub fn layout(tree: &TreeNode, width: f64, height: f64, mode: SizeMode) -> Vec<TreemapRect> {
+    let mut rects = Vec::new();
+    place(tree, Bounds { x: 0.0, y: 0.0, width, height }, 0, mode, &mut rects);
+    rects
+}
+
+fn place(node: &TreeNode, bounds: Bounds, depth: usize, mode: SizeMode, rects: &mut Vec<TreemapRect>) {
+    let color_key = node.category.clone().unwrap_or_else(|| DIRECTORY_COLOR_KEY.to_string());
+    rects.push(TreemapRect {
+        path: Some(node.path.clone()),
+        name: node.name.clone(),
+        size: node.size_in(mode),
+        depth,
+        kind: node.kind,
+        color_key,
+        x: bounds.x,
+        y: bounds.y,
+        width: bounds.width,
+        height: bounds.height,
+    });
+
+    let mut sizes: Vec<u64> = node.children.iter().map(|child| child.size_in(mode)).collect();
+    sizes.push(node.omitted_size_in(mode));
+    let total: u64 = sizes.iter().sum();
+    if total == 0 {
+        return;
+    }
+
+    let scale = bounds.width * bounds.height / total as f64;
+    let areas: Vec<(usize, f64)> = sizes.iter()
+        .enumerate()
+        .filter(|(_, size)| **size > 0)
+        .map(|(index, size)| (index, *size as f64 * scale))
+        .collect();
+
+    for (index, child_bounds) in squarify(areas, bounds) {
+        match node.children.get(index) {
+            Some(child) => place(child, child_bounds, depth + 1, mode, rects),
+            None => rects.push(TreemapRect {
+                path: None,
+                name: format!("{} more", node.omitted_children),
+                size: node.omitted_size_in(mode),
+                depth: depth + 1,
+                kind: NodeKind::Directory,
+                color_key: OMITTED_COLOR_KEY.to_string(),
+                x: child_bounds.x,
+                y: child_bounds.y,
+                width: child_bounds.width,
+                height: child_bounds.height,
+            }),
+        }
+    }
+}
+
+/// Squarified layout (Bruls, Huizing, van Wijk): items are taken largest
+/// first and added to the current row along the shorter side as long as that
+/// does not make the row's worst aspect ratio worse.
+fn squarify(mut areas: Vec<(usize, f64)>, mut bounds: Bounds) -> Vec<(usize, Bounds)> {
+    areas.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let mut placed = Vec::with_capacity(areas.len());
+    let mut row: Vec<(usize, f64)> = Vec::new();
+
+    for item in areas {
+        let side = bounds.width.min(bounds.height);
+        if !row.is_empty() {
+            let mut extended = row.clone();
+            extended.push(item);
+            if worst_ratio(&extended, side) > worst_ratio(&row, side) {
+                bounds = lay_out_row(&row, bounds, &mut placed);
+                row.clear();
+            }
+        }
+        row.push(item);
+    }
+    if !row.is_empty() {
+        lay_out_row(&row, bounds, &mut placed);
+    }
+    placed
+}
+
+/// Largest aspect ratio in `row` if it were laid along a side of length `side`.
+fn worst_ratio(row: &[(usize, f64)], side: f64) -> f64 {
+    let sum: f64 = row.iter().map(|(_, area)| area).sum();
+    let (min, max) = row.iter().fold((f64::MAX, 0.0f64), |(min, max), (_, area)| (min.min(*area), max.max(*area)));
+    if sum <= 0.0 || min <= 0.0 {
+        return f64::MAX;
+    }
+    let side_squared = side * side;
+    let sum_squared = sum * sum;
+    (side_squared * max / sum_squared).max(sum_squared / (side_squared * min))
+}
+
+/// Places `row` along the shorter side of `bounds` and returns the space left.
+fn lay_out_row(row: &[(usize, f64)], bounds: Bounds, placed: &mut Vec<(usize, Bounds)>) -> Bounds {
+    let sum: f64 = row.iter().map(|(_, area)| area).sum();
+    if bounds.width >= bounds.height {
+        // Column on the left
+        let column_width = if bounds.height > 0.0 { sum / bounds.height } else { 0.0 };
+        let mut y = bounds.y;
+        for (index, area) in row {
+            let height = if column_width > 0.0 { area / column_width } else { 0.0 };
+            placed.push((*index, Bounds { x: bounds.x, y, width: column_width, height }));
+            y += height;
+        }
+        Bounds { x: bounds.x + column_width, width: (bounds.width - column_width).max(0.0), ..bounds }
+    } else {
+        // Row along the top
+        let row_height = if bounds.width > 0.0 { sum / bounds.width } else { 0.0 };
+        let mut x = bounds.x;
+        for (index, area) in row {
+            let width = if row_height > 0.0 { area / row_height } else { 0.0 };
+            placed.push((*index, Bounds { x, y: bounds.y, width, height: row_height }));
+            x += width;
+        }
+        Bounds { y: bounds.y + row_height, height: (bounds.height - row_height).max(0.0), ..bounds }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, kind: NodeKind, size: u64, children: Vec<TreeNode>) -> TreeNode {
+        TreeNode {
+            path: PathBuf::from("/data").join(name),
+            name: name.to_string(),
+            kind,
+            category: (kind == NodeKind::File).then(|| "document".to_string()),
+            size,
+            allocated_size: size,
+            file_count: 1,
+            dir_count: 0,
+            newest_modified: 0,
+            children,
+            omitted_children: 0,
+            omitted_size: 0,
+            omitted_allocated_size: 0,
+        }
+    }
+
+    #[test]
+    fn links_without_the_inode_size_get_no_area() {
+        let tree = node("", NodeKind::Directory, 300, vec![
+            node("owner", NodeKind::File, 200, Vec::new()),
+            node("other", NodeKind::File, 100, Vec::new()),
+            node("link", NodeKind::File, 0, Vec::new()),
+        ]);
+        let rects = layout(&tree, 30.0, 10.0, SizeMode::Apparent);
+
+        let names: Vec<&str> = rects.iter().map(|rect| rect.name.as_str()).collect();
+        assert_eq!(names, ["", "owner", "other"]);
+        let child_area: f64 = rects[1..].iter().map(|rect| rect.width * rect.height).sum();
+        assert!((child_area - 300.0).abs() < 1e-9);
+        assert!((rects[1].width * rects[1].height - 200.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use crate::analyzer::analyze_file;
+use crate::config::Config;
+use crate::events::{self, Event, FileChange};
+use crate::jobs::JobManager;
+use crate::storage::{self, FileMetadata};
+
+/// Bumped whenever settings that decide what is watched change; a running
+/// watcher rebuilds its rules when it sees a new value.
+static WATCH_SETTINGS_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Keeps the index current after the initial scan. Uses inotify where
+/// available and falls back to rescanning every `scan_interval_hours` when
+/// the platform has no watcher or the kernel's watch limit is exhausted.
+pub fn watch_drives(config: Arc<Mutex<Config>>, roots: Vec<PathBuf>, jobs: Arc<JobManager>) {
+    #[cfg(target_os = "linux")]
+    {
+        match inotify::InotifyWatcher::new(config.clone(), &roots, jobs.clone()) {
+            Ok(mut watcher) => {
+                println!("Watching {} directories for changes", watcher.watch_count());
+                if let Err(e) = watcher.run() {
+                    eprintln!("File watching stopped: {}", e);
+                }
+            },
+            Err(e) => eprintln!("Could not watch {:?}: {}", roots, e),
+        }
+    }
+
+    periodic_rescan(config, roots, &jobs);
+}
+
+/// Reacts to a configuration update by having the watcher rebuild its
+/// exclusion rules, mount limits and watches.
+pub fn apply_config_change(old: &Config, new: &Config) {
+    let changed = old.excluded_paths != new.excluded_paths
+        || old.respect_ignore_files != new.respect_ignore_files
+        || old.skip_fs_types != new.skip_fs_types
+        || old.root_options != new.root_options;
+    if changed {
+        WATCH_SETTINGS_GENERATION.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn periodic_rescan(config: Arc<Mutex<Config>>, roots: Vec<PathBuf>, jobs: &JobManager) {
+    loop {
+        let hours = config.lock().unwrap().scan_interval_hours;
+        println!("Rescanning drives every {} hours", hours);
+        thread::sleep(Duration::from_secs(hours * 3600));
+        jobs.run(roots.clone(), config.clone());
+    }
+}
+
+#[derive(Debug)]
+pub enum WatchError {
+    /// `fs.inotify.max_user_watches` or `max_user_instances` is used up.
+    LimitReached,
+    Io(io::Error),
+}
+
+impl fmt::Display for WatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WatchError::LimitReached => write!(f, "inotify watch limit reached"),
+            WatchError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// What a batch of events means for one path once bursts are collapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    /// Created, modified or moved into place: re-read it from disk.
+    Upsert,
+    /// Deleted or moved away: tombstone it and everything indexed below it.
+    Remove,
+}
+
+/// Applies a debounced batch of changes to the stored index. Directories that
+/// appeared are returned so the caller can watch and index them.
+fn apply_changes(changes: &HashMap<PathBuf, Change>, config: &Config) -> io::Result<Vec<PathBuf>> {
+    let config_dir = crate::get_config_dir();
+    let touched: Vec<PathBuf> = changes.keys().cloned().collect();
+    let index = storage::load_entries(&config_dir, &touched)?;
+    let mut updates: HashMap<PathBuf, FileMetadata> = HashMap::new();
+    let mut new_dirs = Vec::new();
+
+    for (path, change) in changes {
+        let on_disk = match change {
+            Change::Upsert => fs::symlink_metadata(path).ok(),
+            Change::Remove => None,
+        };
+        match on_disk {
+            Some(metadata) if metadata.is_dir() => new_dirs.push(path.clone()),
+            Some(metadata) if metadata.is_file() => {
+                if index.get(path).is_some_and(|previous| previous.is_unchanged(&metadata)) {
+                    continue;
+                }
+                updates.insert(path.clone(), analyze_file(path, &metadata, config));
+            },
+            Some(_) => {},
+            None => {
+                // A removed directory takes its whole indexed subtree with it
+                for mut tombstone in storage::load_live_entries_under(&config_dir, path)? {
+                    tombstone.is_deleted = true;
+                    updates.insert(tombstone.path.clone(), tombstone);
+                }
+            },
+        }
+    }
+
+    if !updates.is_empty() {
+        storage::upsert_file_metadata(&config_dir, &updates)?;
+    }
+    for (path, change) in changes {
+        if *change == Change::Remove {
+            storage::remove_directory_tree(&config_dir, path)?;
+        }
+    }
+    for (path, meta) in &updates {
+        let change = if meta.is_deleted {
+            FileChange::Deleted
+        } else if index.get(path).is_some_and(|previous| !previous.is_deleted) {
+            FileChange::Modified
+        } else {
+            FileChange::Created
+        };
+        events::publish(Event::FileChanged { path: path.clone(), change });
+    }
+    Ok(new_dirs)
+}
+
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::os::fd::AsFd;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+    use nix::errno::Errno;
+    use nix::poll::{poll, PollFd, PollFlags};
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor};
+    use crate::config::Config;
+    use crate::exclusion::{ExclusionRules, IgnoreScope};
+    use crate::jobs::JobManager;
+    use crate::mounts::{MountPlan, RootLimits};
+    use crate::scanner;
+    use super::{apply_changes, Change, WatchError, WATCH_SETTINGS_GENERATION};
+
+    /// Quiet period that ends a burst of events.
+    const DEBOUNCE: Duration = Duration::from_millis(500);
+    /// Upper bound on how long a continuous stream of events is held back.
+    const MAX_BATCH_DELAY: Duration = Duration::from_secs(5);
+    /// How often an idle watcher checks for settings changes, and the reader
+    /// thread for a request to stop, in milliseconds.
+    const IDLE_CHECK_MS: u16 = 1000;
+
+    fn watch_flags() -> AddWatchFlags {
+        AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_MODIFY
+            | AddWatchFlags::IN_CLOSE_WRITE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ONLYDIR
+            | AddWatchFlags::IN_DONT_FOLLOW
+    }
+
+    impl From<Errno> for WatchError {
+        fn from(errno: Errno) -> Self {
+            match errno {
+                Errno::ENOSPC | Errno::EMFILE => WatchError::LimitReached,
+                errno => WatchError::Io(errno.into()),
+            }
+        }
+    }
+
+    struct WatchedDir {
+        path: PathBuf,
+        scope: IgnoreScope,
+    }
+
+    pub struct InotifyWatcher {
+        inotify: Arc<Inotify>,
+        config: Arc<Mutex<Config>>,
+        rules: ExclusionRules,
+        roots: Vec<PathBuf>,
+        /// Where the walk of each root stops, so that watches stay off pseudo,
+        /// skipped and other roots' filesystems like the scan does.
+        limits: Vec<(PathBuf, RootLimits)>,
+        jobs: Arc<JobManager>,
+        dirs: HashMap<WatchDescriptor, WatchedDir>,
+        /// Set when the kernel queue overflowed and events were lost.
+        overflowed: bool,
+        /// [`WATCH_SETTINGS_GENERATION`] that `rules` and `limits` were built for.
+        generation: u64,
+        /// Tells the reader thread to exit.
+        stop: Arc<AtomicBool>,
+        reader: Option<JoinHandle<()>>,
+    }
+
+    impl InotifyWatcher {
+        pub fn new(config: Arc<Mutex<Config>>, roots: &[PathBuf], jobs: Arc<JobManager>) -> Result<Self, WatchError> {
+            let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+            let generation = WATCH_SETTINGS_GENERATION.load(Ordering::SeqCst);
+            let (rules, limits) = watch_settings(&config.lock().unwrap(), roots);
+            let mut watcher = InotifyWatcher {
+                inotify: Arc::new(inotify),
+                config,
+                rules,
+                roots: roots.to_vec(),
+                limits,
+                jobs,
+                dirs: HashMap::new(),
+                overflowed: false,
+                generation,
+                stop: Arc::new(AtomicBool::new(false)),
+                reader: None,
+            };
+            watcher.watch_roots()?;
+            Ok(watcher)
+        }
+
+        fn watch_roots(&mut self) -> Result<(), WatchError> {
+            for root in self.roots.clone() {
+                if self.rules.is_path_excluded(&root, true) {
+                    continue;
+                }
+                let scope = self.rules.enter_dir(&root, &IgnoreScope::default());
+                self.watch_tree(&root, scope)?;
+            }
+            Ok(())
+        }
+
+        /// Rebuilds the rules and limits if the settings changed since they were built,
+        /// then re-walks the roots: directories no longer excluded gain a
+        /// watch and newly excluded ones lose theirs.
+        fn refresh_rules(&mut self) -> Result<(), WatchError> {
+            let generation = WATCH_SETTINGS_GENERATION.load(Ordering::SeqCst);
+            if generation == self.generation {
+                return Ok(());
+            }
+            self.generation = generation;
+            (self.rules, self.limits) = watch_settings(&self.config.lock().unwrap(), &self.roots);
+            let previous = std::mem::take(&mut self.dirs);
+            self.watch_roots()?;
+            // Adding a watch for a directory already watched returns its old descriptor
+            for wd in previous.into_keys().filter(|wd| !self.dirs.contains_key(wd)) {
+                let _ = self.inotify.rm_watch(wd);
+            }
+            println!("Watch settings changed, now watching {} directories", self.dirs.len());
+            Ok(())
+        }
+
+        pub fn watch_count(&self) -> usize {
+            self.dirs.len()
+        }
+
+        /// Adds a watch for `dir` and every directory below it that the scan
+        /// of its root would walk.
+        fn watch_tree(&mut self, dir: &Path, scope: IgnoreScope) -> Result<(), WatchError> {
+            let limits = self.limits.iter()
+                .filter(|(root, _)| dir.starts_with(root))
+                .max_by_key(|(root, _)| root.components().count())
+                .map(|(_, limits)| limits.clone())
+                .unwrap_or_default();
+            let mut stack = vec![(dir.to_path_buf(), scope)];
+            while let Some((dir, scope)) = stack.pop() {
+                match self.inotify.add_watch(&dir, watch_flags()) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, WatchedDir { path: dir.clone(), scope: scope.clone() });
+                    },
+                    // Directories that vanished or cannot be read are simply not watched
+                    Err(Errno::ENOENT) | Err(Errno::EACCES) | Err(Errno::ENOTDIR) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+                let Ok(entries) = fs::read_dir(&dir) else {
+                    continue;
+                };
+                for entry in entries.filter_map(|e| e.ok()) {
+                    if !entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                        continue;
+                    }
+                    let path = entry.path();
+                    if self.rules.is_excluded(&path, true, &scope) || limits.leaves_root(&path, &entry) {
+                        continue;
+                    }
+                    let child_scope = self.rules.enter_dir(&path, &scope);
+                    stack.push((path, child_scope));
+                }
+            }
+            Ok(())
+        }
+
+        /// Processes events until the watch limit is hit or inotify fails.
+        pub fn run(&mut self) -> Result<(), WatchError> {
+            // Blocking reads happen on their own thread so that the debounce
+            // timer below can fire while the filesystem is quiet
+            let (sender, receiver) = mpsc::channel::<Vec<InotifyEvent>>();
+            let inotify = self.inotify.clone();
+            let stop = self.stop.clone();
+            self.reader = Some(thread::spawn(move || read_events(&inotify, &sender, &stop)));
+
+            let mut pending: HashMap<PathBuf, Change> = HashMap::new();
+            let mut moved_from: HashMap<u32, PathBuf> = HashMap::new();
+            let mut batch_started: Option<Instant> = None;
+
+            loop {
+                let timeout = if pending.is_empty() && !self.overflowed {
+                    Duration::from_millis(IDLE_CHECK_MS.into())
+                } else {
+                    DEBOUNCE
+                };
+                let events = match receiver.recv_timeout(timeout) {
+                    Ok(events) => Some(events),
+                    Err(mpsc::RecvTimeoutError::Timeout) => None,
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        return Err(WatchError::Io(std::io::Error::other("inotify reader stopped")))
+                    },
+                };
+                self.refresh_rules()?;
+
+                if let Some(events) = events {
+                    for event in events {
+                        self.record(event, &mut pending, &mut moved_from);
+                    }
+                    let started = *batch_started.get_or_insert_with(Instant::now);
+                    if started.elapsed() < MAX_BATCH_DELAY {
+                        continue;
+                    }
+                } else if pending.is_empty() && !self.overflowed {
+                    continue;
+                }
+
+                self.flush(std::mem::take(&mut pending))?;
+                moved_from.clear();
+                batch_started = None;
+            }
+        }
+
+        fn record(
+            &mut self,
+            event: InotifyEvent,
+            pending: &mut HashMap<PathBuf, Change>,
+            moved_from: &mut HashMap<u32, PathBuf>,
+        ) {
+            if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+                self.overflowed = true;
+                return;
+            }
+            if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+                self.dirs.remove(&event.wd);
+                return;
+            }
+            let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                return;
+            };
+            let path = dir.path.join(name);
+            let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+            if self.rules.is_excluded(&path, is_dir, &dir.scope) {
+                return;
+            }
+
+            if event.mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+                moved_from.insert(event.cookie, path.clone());
+                pending.insert(path, Change::Remove);
+            } else if event.mask.contains(AddWatchFlags::IN_MOVED_TO) {
+                // Existing watches follow a renamed directory, so only their paths change
+                if let Some(old_path) = moved_from.remove(&event.cookie).filter(|_| is_dir) {
+                    for watched in self.dirs.values_mut() {
+                        if let Ok(relative) = watched.path.strip_prefix(&old_path) {
+                            watched.path = path.join(relative);
+                        }
+                    }
+                }
+                pending.insert(path, Change::Upsert);
+            } else if event.mask.contains(AddWatchFlags::IN_DELETE) {
+                pending.insert(path, Change::Remove);
+            } else {
+                pending.insert(path, Change::Upsert);
+            }
+        }
+
+        fn flush(&mut self, pending: HashMap<PathBuf, Change>) -> Result<(), WatchError> {
+            let config = self.config.lock().unwrap().clone();
+
+            if std::mem::take(&mut self.overflowed) {
+                println!("inotify queue overflowed, rescanning watched drives");
+                self.jobs.run(self.roots.clone(), self.config.clone());
+                return Ok(());
+            }
+
+            let new_dirs = apply_changes(&pending, &config).map_err(WatchError::Io)?;
+            for dir in new_dirs {
+                let parent_scope = self.scope_of_parent(&dir);
+                let scope = self.rules.enter_dir(&dir, &parent_scope);
+                self.watch_tree(&dir, scope)?;
+                scanner::rescan_subtree(&dir, &config, &parent_scope);
+            }
+            Ok(())
+        }
+
+        fn scope_of_parent(&self, dir: &Path) -> IgnoreScope {
+            let parent = dir.parent();
+            self.dirs
+                .values()
+                .find(|watched| Some(watched.path.as_path()) == parent)
+                .map(|watched| watched.scope.clone())
+                .unwrap_or_default()
+        }
+    }
+
+    fn watch_settings(config: &Config, roots: &[PathBuf]) -> (ExclusionRules, Vec<(PathBuf, RootLimits)>) {
+        let plan = MountPlan::current(config);
+        let limits = roots.iter().map(|root| (root.clone(), plan.limits_of(root, config))).collect();
+        (ExclusionRules::from_config(config), limits)
+    }
+
+    impl Drop for InotifyWatcher {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(reader) = self.reader.take() {
+                let _ = reader.join();
+            }
+        }
+    }
+
+    /// Forwards batches of events until told to stop, the receiver goes away
+    /// or reading fails. Waits in `poll` with a timeout rather than a blocking
+    /// read, so that the stop flag is seen while the filesystem is quiet.
+    fn read_events(inotify: &Inotify, sender: &mpsc::Sender<Vec<InotifyEvent>>, stop: &AtomicBool) {
+        while !stop.load(Ordering::Relaxed) {
+            let mut fds = [PollFd::new(inotify.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, IDLE_CHECK_MS) {
+                Ok(0) | Err(Errno::EINTR) => continue,
+                Ok(_) => {},
+                Err(e) => {
+                    eprintln!("Failed to wait for inotify events: {}", e);
+                    break;
+                },
+            }
+            match inotify.read_events() {
+                Ok(events) => {
+                    if sender.send(events).is_err() {
+                        break;
+                    }
+                },
+                Err(Errno::EAGAIN) | Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    eprintln!("Failed to read inotify events: {}", e);
+                    break;
+                },
+            }
+        }
+    }
+}